@@ -0,0 +1,167 @@
+// What jit's commands do, apart from parsing their arguments and reporting to the user, which
+// main.rs takes care of
+pub mod add;
+pub mod branch;
+pub mod commit;
+pub mod ls_files;
+pub mod ls_tree;
+pub mod read_tree;
+pub mod rm;
+pub mod submodule;
+pub mod update_index;
+pub mod workspace_check;
+pub mod write_tree;
+
+use std::{
+    error::Error,
+    fmt,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+// Quotes a path as git does for output when it has control characters, quotes, backslashes or
+// non-ASCII bytes, using C-style escapes
+pub fn quote_path(path: &[u8]) -> String {
+    let needs_quoting = path
+        .iter()
+        .any(|&b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f);
+    if !needs_quoting {
+        return String::from_utf8_lossy(path).into_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for &b in path {
+        match b {
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Resolves `.` and `..` in a path given relative to the root of the repository, refusing one
+// that is absolute or leads outside it rather than quietly naming some other path
+pub fn repository_path(path: &Path) -> Result<PathBuf, OutsideRepository> {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => {}
+            Component::ParentDir if resolved.pop() => {}
+            _ => return Err(OutsideRepository(path.to_path_buf())),
+        }
+    }
+    Ok(resolved)
+}
+
+// A path filter for ls-tree and ls-files, where a trailing slash selects a directory's contents
+// rather than the directory itself
+pub struct PathSpec {
+    path: PathBuf,
+    contents: bool,
+}
+
+pub enum PathMatch {
+    Matched,
+    Leading,
+    None,
+}
+
+impl PathSpec {
+    pub fn new(path: &Path) -> Result<PathSpec, OutsideRepository> {
+        Ok(PathSpec {
+            contents: path.as_os_str().as_bytes().ends_with(b"/"),
+            path: repository_path(path)?,
+        })
+    }
+
+    pub fn check(&self, path: &Path) -> PathMatch {
+        if path.starts_with(&self.path) && !(self.contents && path == self.path) {
+            PathMatch::Matched
+        } else if self.path.starts_with(path) {
+            PathMatch::Leading
+        } else {
+            PathMatch::None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OutsideRepository(PathBuf);
+impl Error for OutsideRepository {}
+impl fmt::Display for OutsideRepository {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' is outside the repository", self.0.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_quotes_only_paths_that_need_it() {
+        assert_eq!("dir/file.txt", quote_path(b"dir/file.txt"));
+        assert_eq!(r#""a\tb\n\"q\"\\""#, quote_path(b"a\tb\n\"q\"\\"));
+        assert_eq!(r#""caf\303\251""#, quote_path("caf\u{e9}".as_bytes()));
+    }
+
+    #[test]
+    fn it_resolves_paths_only_within_the_repository() {
+        let resolve = |path: &str| repository_path(Path::new(path)).map_err(|e| e.to_string());
+        assert_eq!(Ok(PathBuf::from("b")), resolve("a/../b"));
+        assert_eq!(Ok(PathBuf::from("a/b")), resolve("./a/./b/"));
+        assert_eq!(Ok(PathBuf::new()), resolve("a/.."));
+        assert_eq!(
+            Err(String::from("'../x' is outside the repository")),
+            resolve("../x")
+        );
+        assert_eq!(
+            Err(String::from("'a/../../x' is outside the repository")),
+            resolve("a/../../x")
+        );
+        assert_eq!(
+            Err(String::from("'/x' is outside the repository")),
+            resolve("/x")
+        );
+    }
+
+    #[test]
+    fn it_matches_paths_within_a_pathspec() {
+        let spec = PathSpec::new(Path::new("./dir/other/../sub")).unwrap();
+        assert!(matches!(
+            spec.check(Path::new("dir/sub")),
+            PathMatch::Matched
+        ));
+        assert!(matches!(
+            spec.check(Path::new("dir/sub/a")),
+            PathMatch::Matched
+        ));
+        assert!(matches!(spec.check(Path::new("dir")), PathMatch::Leading));
+        assert!(matches!(
+            spec.check(Path::new("dir/subway")),
+            PathMatch::None
+        ));
+        assert!(matches!(spec.check(Path::new("other")), PathMatch::None));
+
+        let contents = PathSpec::new(Path::new("dir/sub/")).unwrap();
+        assert!(matches!(
+            contents.check(Path::new("dir/sub")),
+            PathMatch::Leading
+        ));
+        assert!(matches!(
+            contents.check(Path::new("dir/sub/a")),
+            PathMatch::Matched
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use std::{
+    error::{self, Error},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use super::super::{
+    database::{blob::Blob, Database, Storable},
+    index::Index,
+    submodule,
+    workspace::Workspace,
+};
+
+// Stages the files under `path`, which is relative to `root_path`. Symlinks are staged as the
+// paths they point to rather than followed, and nested repositories as the commit they have
+// checked out.
+pub fn add(
+    root_path: &Path,
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    path: &Path,
+    intent_to_add: bool,
+) -> Result<(), Box<dyn error::Error>> {
+    for pathname in workspace.list_workspace_files(&root_path.join(path)) {
+        // Files marked assume-unchanged or skip-worktree, or that the filesystem monitor vouches
+        // for, aren't looked at at all
+        let skipped = index.entry(pathname.as_os_str()).is_some_and(|entry| {
+            entry.assume_unchanged() || entry.skip_worktree() || entry.fsmonitor_valid()
+        });
+        if skipped {
+            continue;
+        }
+
+        // Files whose stat data hasn't changed don't need hashing again
+        let stat = workspace.stat_file(&pathname);
+        let unchanged = index
+            .entry(pathname.as_os_str())
+            .is_some_and(|entry| index.is_stat_clean(entry, &stat));
+        if unchanged {
+            index.mark_fsmonitor_valid(pathname.as_os_str());
+            continue;
+        }
+
+        // Paths already tracked keep what's staged for them
+        if intent_to_add && !stat.is_dir() {
+            if index.entry(pathname.as_os_str()).is_none() {
+                database.store(&mut Blob::new(Vec::new()));
+                index.add_intent_to_add(pathname, stat);
+            }
+            continue;
+        }
+
+        // Submodules are recorded by the commit they have checked out
+        if stat.is_dir() {
+            let oid = submodule::head_commit(&root_path.join(&pathname))
+                .ok_or_else(|| NoCommitCheckedOut(pathname.clone()))?;
+            index.add(pathname, oid, stat);
+            continue;
+        }
+
+        let mut blob = Blob::new(workspace.read_file(&pathname));
+        database.store(&mut blob);
+        index.add(pathname, blob.oid().unwrap().to_string(), stat);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct NoCommitCheckedOut(PathBuf);
+impl Error for NoCommitCheckedOut {}
+impl fmt::Display for NoCommitCheckedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' does not have a commit checked out",
+            self.0.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{database::tree::OidAndMode, index::entry::Entry, temp_dir::TempDir};
+    use super::*;
+    use std::{fs, os::unix::fs::symlink};
+
+    #[test]
+    fn it_stages_symlinks_as_links() {
+        let root_path = TempDir::repository("add");
+        fs::write(root_path.join("file.txt"), "content").unwrap();
+        symlink("file.txt", root_path.join("link")).unwrap();
+
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join(".git/objects"));
+        let mut index = Index::new(root_path.join(".git/index"));
+        add(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("link"),
+            false,
+        )
+        .unwrap();
+
+        let entry = index.entry(Path::new("link").as_os_str()).unwrap();
+        assert_eq!(Entry::SYMLINK_MODE, entry.mode());
+        assert_eq!(
+            database.hash_object(&Blob::new(b"file.txt".to_vec())),
+            entry.oid()
+        );
+        assert_eq!(1, index.entries().count());
+    }
+}
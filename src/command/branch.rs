@@ -0,0 +1,346 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::super::{database::Database, refs::Refs, revision::Revision};
+
+// Lists the branches with the current one starred, or HEAD first when it's detached, and with
+// `verbose` the commit each is at
+pub fn list_branches<W: Write>(
+    refs: &Refs,
+    database: &Database,
+    verbose: bool,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let current = refs.current_branch()?;
+    let mut lines = Vec::new();
+    if current.is_none() {
+        if let Some(oid) = refs.read_head()? {
+            lines.push((true, format!("(HEAD detached at {})", &oid[..7]), oid));
+        }
+    }
+    for (name, oid) in refs.list_branches()? {
+        let is_current = current.as_deref() == Some(&format!("{}{}", Refs::HEADS, name));
+        lines.push((is_current, name, oid));
+    }
+
+    let width = lines
+        .iter()
+        .map(|(_, name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (is_current, name, oid) in lines {
+        let marker = if is_current { '*' } else { ' ' };
+        if verbose {
+            let commit = database.load_commit(&oid)?;
+            let subject = commit.message().lines().next().unwrap_or("");
+            writeln!(out, "{} {:width$} {} {}", marker, name, &oid[..7], subject)?;
+        } else {
+            writeln!(out, "{} {}", marker, name)?;
+        }
+    }
+    Ok(())
+}
+
+// Creates a branch at the given start point, or at HEAD
+pub fn create_branch(
+    refs: &Refs,
+    database: &Database,
+    names: &[String],
+) -> Result<(), Box<dyn Error>> {
+    let (name, start) = match names {
+        [name] => (name, None),
+        [name, start] => (name, Some(start)),
+        _ => return Err(BranchUsage("too many arguments").into()),
+    };
+
+    // An unborn current branch is named by git when there's no commit to start from
+    let start_name = match (start, refs.current_branch()?) {
+        (Some(start), _) => start.clone(),
+        (None, Some(branch)) => branch[Refs::HEADS.len()..].to_string(),
+        (None, None) => String::from(Refs::HEAD),
+    };
+    let oid = Revision::new(refs, database)
+        .resolve(start.map_or(Refs::HEAD, String::as_str))
+        .and_then(|oid| database.load_commit(&oid).map(|_| oid))
+        .map_err(|_| InvalidStartPoint(start_name))?;
+
+    refs.create_branch(name, &oid)
+}
+
+// Deletes each branch it can, like git reporting the others and failing only at the end
+pub fn delete_branches<W: Write>(
+    root_path: &Path,
+    refs: &Refs,
+    database: &Database,
+    names: &[String],
+    force: bool,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    if names.is_empty() {
+        return Err(BranchUsage("branch name required").into());
+    }
+
+    let current = refs.current_branch()?;
+    let head = refs.read_head()?;
+    let mut failures: Vec<Box<dyn Error>> = Vec::new();
+    for name in names {
+        let full_name = format!("{}{}", Refs::HEADS, name);
+        if current.as_deref() == Some(full_name.as_str()) {
+            failures.push(CheckedOut(name.clone(), root_path.to_path_buf()).into());
+            continue;
+        }
+
+        let oid = match refs.resolve(&full_name) {
+            Ok(Some(oid)) => oid,
+            _ => {
+                failures.push(BranchNotFound(name.clone()).into());
+                continue;
+            }
+        };
+        if !force && !is_merged(database, &oid, head.as_deref())? {
+            failures.push(NotFullyMerged(name.clone()).into());
+            continue;
+        }
+
+        match refs.delete_branch(name) {
+            Ok(oid) => writeln!(out, "Deleted branch {} (was {}).", name, &oid[..7])?,
+            Err(error) => failures.push(error),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(NotDeleted(failures).into());
+    }
+    Ok(())
+}
+
+// Whether the commit is in the history of HEAD, reached through any parent
+pub fn is_merged(
+    database: &Database,
+    oid: &str,
+    head: Option<&str>,
+) -> Result<bool, Box<dyn Error>> {
+    let mut pending: Vec<String> = head.into_iter().map(String::from).collect();
+    let mut seen = HashSet::new();
+    while let Some(ancestor) = pending.pop() {
+        if ancestor == oid {
+            return Ok(true);
+        }
+        if seen.insert(ancestor.clone()) {
+            pending.extend(database.load_commit(&ancestor)?.parents().iter().cloned());
+        }
+    }
+    Ok(false)
+}
+
+// Renames the given branch, or the current one when only the new name is given
+pub fn rename_branch(refs: &Refs, names: &[String]) -> Result<(), Box<dyn Error>> {
+    match names {
+        [] => Err(BranchUsage("branch name required").into()),
+        [new_name] => match refs.current_branch()? {
+            Some(current) => refs.rename_branch(&current[Refs::HEADS.len()..], new_name),
+            None => {
+                Err(BranchUsage("cannot rename the current branch while not on any branch").into())
+            }
+        },
+        [old_name, new_name] => refs.rename_branch(old_name, new_name),
+        _ => Err(BranchUsage("too many arguments for a rename operation").into()),
+    }
+}
+
+#[derive(Debug)]
+struct BranchUsage(&'static str);
+impl Error for BranchUsage {}
+impl fmt::Display for BranchUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+struct InvalidStartPoint(String);
+impl Error for InvalidStartPoint {}
+impl fmt::Display for InvalidStartPoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "not a valid object name: '{}'", self.0)
+    }
+}
+
+// The branches `delete_branches` had to leave, each with why
+#[derive(Debug)]
+pub struct NotDeleted(pub Vec<Box<dyn Error>>);
+impl Error for NotDeleted {}
+impl fmt::Display for NotDeleted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reasons: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        write!(f, "{}", reasons.join("\n"))
+    }
+}
+
+#[derive(Debug)]
+struct CheckedOut(String, PathBuf);
+impl Error for CheckedOut {}
+impl fmt::Display for CheckedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Cannot delete branch '{}' checked out at '{}'",
+            self.0,
+            self.1.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct BranchNotFound(String);
+impl Error for BranchNotFound {}
+impl fmt::Display for BranchNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "branch '{}' not found.", self.0)
+    }
+}
+
+#[derive(Debug)]
+struct NotFullyMerged(String);
+impl Error for NotFullyMerged {}
+impl fmt::Display for NotFullyMerged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The branch '{}' is not fully merged.\nIf you are sure you want to delete it, run 'jit branch -D {}'.",
+            self.0, self.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        database::{commit::Commit, Storable},
+        temp_dir::TempDir,
+    };
+    use super::*;
+
+    fn repository(name: &str) -> (TempDir, Refs, Database) {
+        let root_path = TempDir::repository(name);
+        let git_path = root_path.join(".git");
+        let refs = Refs::new(git_path.clone());
+        refs.init_head().unwrap();
+        (root_path, refs, Database::new(git_path.join("objects")))
+    }
+
+    fn commit(database: &Database, parents: &[&str], message: &str) -> String {
+        let mut data = String::from("tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n");
+        for parent in parents {
+            data.push_str(&format!("parent {}\n", parent));
+        }
+        data.push_str("author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\n");
+        data.push_str(message);
+        data.push('\n');
+        let mut commit = Commit::parse("", data.as_bytes()).unwrap();
+        database.store(&mut commit);
+        commit.oid().unwrap().to_string()
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn it_finds_commits_merged_through_any_parent() {
+        let (_root_path, _, database) = repository("merged");
+        let base = commit(&database, &[], "base");
+        let main = commit(&database, &[&base], "main");
+        let topic = commit(&database, &[&base], "topic");
+        let merge = commit(&database, &[&main, &topic], "merge");
+        let other = commit(&database, &[&base], "other");
+
+        assert!(is_merged(&database, &topic, Some(&merge)).unwrap());
+        assert!(is_merged(&database, &base, Some(&merge)).unwrap());
+        assert!(!is_merged(&database, &other, Some(&merge)).unwrap());
+        assert!(!is_merged(&database, &topic, None).unwrap());
+    }
+
+    #[test]
+    fn it_creates_and_lists_branches() {
+        let (_root_path, refs, database) = repository("list");
+        let first = commit(&database, &[], "first");
+        let second = commit(&database, &[&first], "second\n\nbody");
+        refs.update_head(&second, None).unwrap();
+
+        create_branch(&refs, &database, &names(&["topic", &first])).unwrap();
+        create_branch(&refs, &database, &names(&["copy"])).unwrap();
+        let error = create_branch(&refs, &database, &names(&["bad", "nowhere"])).unwrap_err();
+        assert_eq!("not a valid object name: 'nowhere'", error.to_string());
+
+        let mut out = Vec::new();
+        list_branches(&refs, &database, false, &mut out).unwrap();
+        assert_eq!("  copy\n* main\n  topic\n", String::from_utf8(out).unwrap());
+
+        let mut out = Vec::new();
+        refs.detach_head(&first).unwrap();
+        list_branches(&refs, &database, true, &mut out).unwrap();
+        // Names are padded to the width of the detached HEAD's description
+        let (first, second) = (&first[..7], &second[..7]);
+        let detached = format!("(HEAD detached at {})", first);
+        let expected = [
+            format!("* {} {} first", detached, first),
+            format!("  {:26} {} second", "copy", second),
+            format!("  {:26} {} second", "main", second),
+            format!("  {:26} {} first", "topic", first),
+        ];
+        assert_eq!(expected.join("\n") + "\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn it_deletes_the_branches_it_safely_can() {
+        let (root_path, refs, database) = repository("delete");
+        let base = commit(&database, &[], "base");
+        let topic = commit(&database, &[&base], "topic");
+        let other = commit(&database, &[&base], "other");
+        let merge = commit(&database, &[&base, &topic], "merge");
+        refs.update_head(&merge, None).unwrap();
+        refs.create_branch("topic", &topic).unwrap();
+        refs.create_branch("other", &other).unwrap();
+
+        let mut out = Vec::new();
+        let branches = names(&["main", "topic", "missing", "other"]);
+        let error =
+            delete_branches(&root_path, &refs, &database, &branches, false, &mut out).unwrap_err();
+        assert_eq!(
+            format!("Deleted branch topic (was {}).\n", &topic[..7]),
+            String::from_utf8(out).unwrap()
+        );
+        assert_eq!(
+            format!(
+                "Cannot delete branch 'main' checked out at '{}'\n\
+                 branch 'missing' not found.\n\
+                 The branch 'other' is not fully merged.\n\
+                 If you are sure you want to delete it, run 'jit branch -D other'.",
+                root_path.display()
+            ),
+            error.to_string()
+        );
+        assert!(error.is::<NotDeleted>());
+
+        let mut out = Vec::new();
+        delete_branches(
+            &root_path,
+            &refs,
+            &database,
+            &names(&["other"]),
+            true,
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![(String::from("main"), merge)],
+            refs.list_branches().unwrap()
+        );
+    }
+}
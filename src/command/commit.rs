@@ -0,0 +1,40 @@
+use super::super::index::Index;
+
+// Whether committing `tree` would record nothing new: it's the tree of the parent commit, or
+// there's no parent and nothing is staged other than paths only intended to be added
+pub fn nothing_to_commit(parent_tree: Option<&str>, tree: &str, index: &Index) -> bool {
+    match parent_tree {
+        Some(parent_tree) => parent_tree == tree,
+        None => index.entries().all(|entry| entry.intent_to_add()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::index::entry::Entry;
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn it_finds_nothing_to_commit_only_without_changes() {
+        let tree = "1".repeat(40);
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+
+        assert!(nothing_to_commit(Some(&tree), &tree, &index));
+        assert!(!nothing_to_commit(Some(&"2".repeat(40)), &tree, &index));
+        assert!(nothing_to_commit(None, &tree, &index));
+
+        let mut entry =
+            Entry::from_database(PathBuf::from("a.txt"), Entry::EMPTY_BLOB_OID, 0o100644);
+        entry.set_intent_to_add();
+        index.add_entry(entry);
+        assert!(nothing_to_commit(None, &tree, &index));
+
+        index.add_entry(Entry::from_database(
+            PathBuf::from("b.txt"),
+            &"3".repeat(40),
+            0o100644,
+        ));
+        assert!(!nothing_to_commit(None, &tree, &index));
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    io::Write,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    super::{
+        database::{tree::OidAndMode, Database},
+        ignore::Ignore,
+        index::{entry::Entry, Index},
+        workspace::Workspace,
+    },
+    quote_path,
+    workspace_check::{WorkspaceCheck, WorkspaceStatus},
+    PathMatch, PathSpec,
+};
+
+pub struct Options {
+    pub cached: bool,
+    pub stage: bool,
+    pub deleted: bool,
+    pub modified: bool,
+    pub others: bool,
+    pub ignored: bool,
+    // Whether untracked files are ignored by the standard rules alone, which are all the
+    // untracked cache knows about
+    pub standard_excludes: bool,
+    pub nul_terminated: bool,
+    pub paths: Vec<PathSpec>,
+}
+
+impl Options {
+    // Like git, refuses -i without anything to pick ignored files from, or any rules to do it by
+    pub fn check(&self, ignore: &Ignore) -> Result<(), Box<dyn Error>> {
+        if self.ignored && !self.others && !self.cached {
+            return Err(IgnoredUsage("ls-files -i must be used with either -o or -c").into());
+        }
+        if self.ignored && !ignore.has_patterns() {
+            return Err(IgnoredUsage("ls-files --ignored needs some exclude pattern").into());
+        }
+        Ok(())
+    }
+
+    // Listing untracked files can use and update the untracked cache, and checking tracked files
+    // can refresh their stat data, so both want the index locked to write it back
+    pub fn updates_index(&self) -> bool {
+        self.uses_untracked_cache() || self.deleted || self.modified
+    }
+
+    fn uses_untracked_cache(&self) -> bool {
+        self.others && !self.ignored && self.standard_excludes
+    }
+
+    fn shows_index(&self) -> bool {
+        self.cached || self.stage || (!self.deleted && !self.modified && !self.others)
+    }
+}
+
+// Lists untracked files first, then index entries, as git does. The index is left with whatever
+// the untracked cache and the workspace checks learnt, for the caller to write if it holds the
+// lock.
+pub fn ls_files<W: Write>(
+    root_path: &Path,
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    ignore: &mut Ignore,
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let mut output = Output {
+        options,
+        ignore,
+        out,
+    };
+
+    if options.others {
+        output.untracked(root_path, workspace, index)?;
+    }
+    if options.shows_index() || options.deleted || options.modified {
+        let check = WorkspaceCheck::new(root_path, workspace, database, index);
+        for entry in index.entries() {
+            output.entry(entry, &check)?;
+        }
+        for (path, stat) in check.into_refreshed() {
+            index.update_entry_stat(path.as_os_str(), &stat);
+        }
+    }
+    Ok(())
+}
+
+struct Output<'a, W: Write> {
+    options: &'a Options,
+    ignore: &'a mut Ignore,
+    out: &'a mut W,
+}
+
+impl<W: Write> Output<'_, W> {
+    fn untracked(
+        &mut self,
+        root_path: &Path,
+        workspace: &Workspace,
+        index: &mut Index,
+    ) -> Result<(), Box<dyn Error>> {
+        let cached = if self.options.uses_untracked_cache() {
+            index.untracked_files(self.ignore)
+        } else {
+            None
+        };
+
+        // The cache lists directories, which are only nested repositories, with a trailing slash
+        let mut paths: Vec<(PathBuf, bool)> = match cached {
+            Some(paths) => paths
+                .into_iter()
+                .map(|path| {
+                    let name = path.as_os_str().as_bytes();
+                    match name.strip_suffix(b"/") {
+                        Some(name) => (PathBuf::from(OsStr::from_bytes(name)), true),
+                        None => (path, false),
+                    }
+                })
+                .collect(),
+            None => workspace
+                .list_workspace_files(root_path)
+                .into_iter()
+                .filter(|path| !index.tracked_file(path.as_os_str()))
+                .filter_map(|path| {
+                    // Untracked nested repositories are listed as directories
+                    let is_dir = root_path
+                        .join(&path)
+                        .symlink_metadata()
+                        .is_ok_and(|stat| stat.is_dir());
+                    if self.ignore.is_ignored(&path, is_dir) == self.options.ignored {
+                        Some((path, is_dir))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        };
+        paths.sort();
+
+        for (path, is_dir) in paths {
+            if !self.matches(&path) {
+                continue;
+            }
+            let mut name = path.into_os_string().into_vec();
+            if is_dir {
+                name.push(b'/');
+            }
+            self.write(None, &name)?;
+        }
+        Ok(())
+    }
+
+    fn entry(&mut self, entry: &Entry, check: &WorkspaceCheck) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(entry.path());
+        if (self.options.ignored && !self.ignore.is_ignored(path, false)) || !self.matches(path) {
+            return Ok(());
+        }
+        if self.options.shows_index() {
+            self.write_entry(entry)?;
+        }
+        let (deleted, modified) = (self.options.deleted, self.options.modified);
+        if !(deleted || modified) || entry.skip_worktree() {
+            return Ok(());
+        }
+
+        let status = check.status(entry);
+        if deleted && status == WorkspaceStatus::Deleted {
+            self.write_entry(entry)?;
+        }
+        if modified && status != WorkspaceStatus::Unchanged {
+            self.write_entry(entry)?;
+        }
+        Ok(())
+    }
+
+    fn matches(&self, path: &Path) -> bool {
+        self.options.paths.is_empty()
+            || self
+                .options
+                .paths
+                .iter()
+                .any(|spec| matches!(spec.check(path), PathMatch::Matched))
+    }
+
+    fn write_entry(&mut self, entry: &Entry) -> Result<(), Box<dyn Error>> {
+        let stage = if self.options.stage {
+            Some(format!(
+                "{:06o} {} {}\t",
+                entry.mode(),
+                entry.oid(),
+                entry.stage()
+            ))
+        } else {
+            None
+        };
+        self.write(stage, entry.path().as_bytes())
+    }
+
+    fn write(&mut self, prefix: Option<String>, path: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut line = prefix.unwrap_or_default().into_bytes();
+        if self.options.nul_terminated {
+            line.extend(path);
+            line.push(b'\0');
+        } else {
+            line.extend(quote_path(path).into_bytes());
+            line.push(b'\n');
+        }
+        self.out.write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct IgnoredUsage(&'static str);
+impl Error for IgnoredUsage {}
+impl fmt::Display for IgnoredUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{database::blob::Blob, temp_dir::TempDir};
+    use super::*;
+    use std::fs;
+
+    // A repository with `a.txt`, `dir/b.txt` and `gone.txt` tracked, `a.txt` then changed and
+    // `gone.txt` deleted, and `new.txt`, `build.log` and a nested repository left untracked
+    fn repository(name: &str) -> (TempDir, Workspace, Database, Index) {
+        let root_path = TempDir::repository(name);
+        fs::create_dir_all(root_path.join("dir")).unwrap();
+        fs::create_dir_all(root_path.join("nested/.git")).unwrap();
+
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join(".git/objects"));
+        let mut index = Index::new(root_path.join(".git/index"));
+        for name in &["a.txt", "dir/b.txt", "gone.txt"] {
+            fs::write(root_path.join(name), name).unwrap();
+            let path = PathBuf::from(name);
+            let oid = database.hash_object(&Blob::new(name.as_bytes().to_vec()));
+            index.add(path.clone(), oid, workspace.stat_file(&path));
+        }
+        fs::write(root_path.join("a.txt"), "changed").unwrap();
+        fs::remove_file(root_path.join("gone.txt")).unwrap();
+        fs::write(root_path.join("new.txt"), "new").unwrap();
+        fs::write(root_path.join("build.log"), "log").unwrap();
+
+        (root_path, workspace, database, index)
+    }
+
+    // Options from ls-files' short flags, with `paths` as pathspecs
+    fn options(flags: &str, paths: &[&str]) -> Options {
+        Options {
+            cached: flags.contains('c'),
+            stage: flags.contains('s'),
+            deleted: flags.contains('d'),
+            modified: flags.contains('m'),
+            others: flags.contains('o'),
+            ignored: flags.contains('i'),
+            standard_excludes: false,
+            nul_terminated: flags.contains('z'),
+            paths: paths
+                .iter()
+                .map(|path| PathSpec::new(Path::new(path)).unwrap())
+                .collect(),
+        }
+    }
+
+    fn list(
+        repository: &mut (TempDir, Workspace, Database, Index),
+        ignore: &mut Ignore,
+        options: &Options,
+    ) -> String {
+        let (root_path, workspace, database, index) = repository;
+        let mut out = Vec::new();
+        ls_files(
+            root_path, workspace, database, index, ignore, options, &mut out,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_lists_index_entries_by_default() {
+        let mut repository = repository("cached");
+        let mut ignore = Ignore::new(repository.0.to_path_buf());
+        let mut list = |flags: &str, paths: &[&str]| {
+            list(&mut repository, &mut ignore, &options(flags, paths))
+        };
+
+        assert_eq!("a.txt\ndir/b.txt\ngone.txt\n", list("", &[]));
+        assert_eq!(list("", &[]), list("c", &[]));
+        assert_eq!("a.txt\0dir/b.txt\0gone.txt\0", list("z", &[]));
+        assert_eq!("dir/b.txt\n", list("c", &["dir"]));
+
+        let oid = Database::new(PathBuf::new()).hash_object(&Blob::new(b"dir/b.txt".to_vec()));
+        assert_eq!(
+            format!("100644 {} 0\tdir/b.txt\n", oid),
+            list("s", &["dir/b.txt"])
+        );
+    }
+
+    #[test]
+    fn it_lists_deleted_and_modified_files() {
+        let mut repository = repository("changed");
+        let mut ignore = Ignore::new(repository.0.to_path_buf());
+        let mut list = |flags: &str| list(&mut repository, &mut ignore, &options(flags, &[]));
+
+        assert_eq!("gone.txt\n", list("d"));
+        // Deleted files count as modified too
+        assert_eq!("a.txt\ngone.txt\n", list("m"));
+        assert_eq!("a.txt\ngone.txt\ngone.txt\n", list("dm"));
+        assert_eq!(
+            "a.txt\na.txt\ndir/b.txt\ngone.txt\ngone.txt\ngone.txt\n",
+            list("cdm")
+        );
+    }
+
+    #[test]
+    fn it_lists_untracked_or_ignored_files() {
+        let mut repository = repository("others");
+        let mut ignore = Ignore::new(repository.0.to_path_buf());
+        ignore.add_patterns("*.log\na.txt\n");
+        let mut list = |flags: &str| list(&mut repository, &mut ignore, &options(flags, &[]));
+
+        // Nested repositories are listed as directories, before any index entries
+        assert_eq!("nested/\nnew.txt\n", list("o"));
+        assert_eq!("nested/\nnew.txt\na.txt\ndir/b.txt\ngone.txt\n", list("oc"));
+        assert_eq!("build.log\n", list("oi"));
+        assert_eq!("a.txt\n", list("ci"));
+        assert_eq!("a.txt\n", list("mi"));
+    }
+
+    #[test]
+    fn it_uses_the_untracked_cache_only_for_the_standard_rules() {
+        let mut repository = repository("cache");
+        repository.3.set_untracked_cache(true);
+        let mut ignore = Ignore::new(repository.0.to_path_buf());
+
+        let mut standard = options("o", &[]);
+        standard.standard_excludes = true;
+        assert!(standard.updates_index());
+        assert!(!options("o", &[]).updates_index());
+        assert!(!options("oi", &[]).updates_index());
+        assert!(!options("cs", &[]).updates_index());
+        assert!(options("d", &[]).updates_index());
+        assert!(options("m", &[]).updates_index());
+
+        let uncached = list(&mut repository, &mut ignore, &options("o", &[]));
+        assert_eq!("build.log\nnested/\nnew.txt\n", uncached);
+        assert_eq!(uncached, list(&mut repository, &mut ignore, &standard));
+        // And again once the cache holds what the first listing found
+        assert_eq!(uncached, list(&mut repository, &mut ignore, &standard));
+    }
+
+    #[test]
+    fn it_refuses_to_list_ignored_files_without_a_source_or_rules() {
+        let mut ignore = Ignore::new(PathBuf::new());
+        let error = options("i", &[]).check(&ignore).unwrap_err();
+        assert_eq!(
+            "ls-files -i must be used with either -o or -c",
+            error.to_string()
+        );
+        let error = options("oi", &[]).check(&ignore).unwrap_err();
+        assert_eq!(
+            "ls-files --ignored needs some exclude pattern",
+            error.to_string()
+        );
+
+        ignore.add_patterns("*.log");
+        assert!(options("oi", &[]).check(&ignore).is_ok());
+        assert!(options("ci", &[]).check(&ignore).is_ok());
+        assert!(options("o", &[])
+            .check(&Ignore::new(PathBuf::new()))
+            .is_ok());
+    }
+}
@@ -0,0 +1,234 @@
+use std::{error::Error, io::Write, os::unix::ffi::OsStrExt, path::Path};
+
+use super::{
+    super::database::{
+        tree::{DatabaseEntry, OidAndMode, Tree},
+        Database,
+    },
+    quote_path, PathMatch, PathSpec,
+};
+
+pub struct Options {
+    pub recursive: bool,
+    pub show_trees: bool,
+    pub trees_only: bool,
+    pub long: bool,
+    pub name_only: bool,
+    pub paths: Vec<PathSpec>,
+}
+
+// Lists the entries of `tree`, descending into subtrees that pathspecs lead into or, when
+// recursive, into every subtree
+pub fn ls_tree<W: Write>(
+    database: &Database,
+    tree: &Tree,
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    list_entries(database, tree, Path::new(""), options, out)
+}
+
+fn list_entries<W: Write>(
+    database: &Database,
+    tree: &Tree,
+    prefix: &Path,
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    for (name, entry) in tree.entries() {
+        let path = prefix.join(name);
+
+        let matched = if options.paths.is_empty() {
+            PathMatch::Matched
+        } else {
+            options.paths.iter().map(|spec| spec.check(&path)).fold(
+                PathMatch::None,
+                |best, check| match (best, check) {
+                    (PathMatch::Matched, _) | (_, PathMatch::Matched) => PathMatch::Matched,
+                    (PathMatch::Leading, _) | (_, PathMatch::Leading) => PathMatch::Leading,
+                    _ => PathMatch::None,
+                },
+            )
+        };
+
+        let (show, descend) = match (matched, entry.is_tree()) {
+            (PathMatch::None, _) => (false, false),
+            (PathMatch::Leading, true) => (options.show_trees, true),
+            (PathMatch::Leading, false) => (false, false),
+            (PathMatch::Matched, true) if options.recursive => {
+                (options.show_trees || options.trees_only, true)
+            }
+            (PathMatch::Matched, true) => (true, false),
+            (PathMatch::Matched, false) => (!options.trees_only, false),
+        };
+
+        if show {
+            write_entry(database, &path, &entry, options, out)?;
+        }
+        if descend {
+            let subtree = database.load_tree(entry.oid())?;
+            list_entries(database, &subtree, &path, options, out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_entry<W: Write>(
+    database: &Database,
+    path: &Path,
+    entry: &DatabaseEntry,
+    options: &Options,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let name = quote_path(path.as_os_str().as_bytes());
+    if options.name_only {
+        writeln!(out, "{}", name)?;
+        return Ok(());
+    }
+
+    let size = if !options.long {
+        None
+    } else if entry.type_() == "blob" {
+        Some(database.object_size(entry.oid())?.to_string())
+    } else {
+        Some(String::from("-"))
+    };
+
+    match size {
+        Some(size) => writeln!(
+            out,
+            "{:06o} {} {} {:>7}\t{}",
+            entry.mode(),
+            entry.type_(),
+            entry.oid(),
+            size,
+            name
+        )?,
+        None => writeln!(
+            out,
+            "{:06o} {} {}\t{}",
+            entry.mode(),
+            entry.type_(),
+            entry.oid(),
+            name
+        )?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        database::{blob::Blob, Storable},
+        index::entry::Entry,
+        temp_dir::TempDir,
+    };
+    use super::*;
+    use std::path::PathBuf;
+
+    // A tree holding `a.txt`, `dir/b.txt` and `dir/sub/c.txt`, whose contents are their names
+    fn tree(name: &str) -> (TempDir, Database, Tree) {
+        let dir = TempDir::new(name);
+        let database = Database::new(dir.to_path_buf());
+
+        let entries: Vec<_> = ["a.txt", "dir/b.txt", "dir/sub/c.txt"]
+            .iter()
+            .map(|name| {
+                let mut blob = Blob::new(name.as_bytes().to_vec());
+                database.store(&mut blob);
+                Entry::from_database(PathBuf::from(name), blob.oid().unwrap(), 0o100644)
+            })
+            .collect();
+        let mut root = Tree::build(&entries);
+        root.traverse(|tree| database.store(tree));
+        (dir, database, root)
+    }
+
+    fn options(paths: &[&str]) -> Options {
+        Options {
+            recursive: false,
+            show_trees: false,
+            trees_only: false,
+            long: false,
+            name_only: true,
+            paths: paths
+                .iter()
+                .map(|path| PathSpec::new(Path::new(path)).unwrap())
+                .collect(),
+        }
+    }
+
+    fn list(database: &Database, tree: &Tree, options: &Options) -> Vec<String> {
+        let mut out = Vec::new();
+        ls_tree(database, tree, options, &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn it_lists_top_level_entries_or_everything_when_recursive() {
+        let (_dir, database, tree) = tree("recursive");
+
+        assert_eq!(vec!["a.txt", "dir"], list(&database, &tree, &options(&[])));
+
+        let recursive = Options {
+            recursive: true,
+            ..options(&[])
+        };
+        assert_eq!(
+            vec!["a.txt", "dir/b.txt", "dir/sub/c.txt"],
+            list(&database, &tree, &recursive)
+        );
+
+        let show_trees = Options {
+            show_trees: true,
+            ..recursive
+        };
+        assert_eq!(
+            vec!["a.txt", "dir", "dir/b.txt", "dir/sub", "dir/sub/c.txt"],
+            list(&database, &tree, &show_trees)
+        );
+
+        let trees_only = Options {
+            trees_only: true,
+            recursive: true,
+            ..options(&[])
+        };
+        assert_eq!(vec!["dir", "dir/sub"], list(&database, &tree, &trees_only));
+    }
+
+    #[test]
+    fn it_descends_into_trees_leading_to_pathspecs() {
+        let (_dir, database, tree) = tree("pathspecs");
+
+        assert_eq!(vec!["dir"], list(&database, &tree, &options(&["dir"])));
+        assert_eq!(
+            vec!["dir/b.txt", "dir/sub"],
+            list(&database, &tree, &options(&["dir/"]))
+        );
+        assert_eq!(
+            vec!["a.txt", "dir/sub"],
+            list(&database, &tree, &options(&["dir/sub", "a.txt"]))
+        );
+        assert!(list(&database, &tree, &options(&["missing"])).is_empty());
+    }
+
+    #[test]
+    fn it_shows_modes_types_and_sizes() {
+        let (_dir, database, tree) = tree("long");
+        let long = Options {
+            long: true,
+            name_only: false,
+            ..options(&[])
+        };
+        let lines = list(&database, &tree, &long);
+
+        let blob = database.hash_object(&Blob::new(b"a.txt".to_vec()));
+        assert_eq!(format!("100644 blob {}       5\ta.txt", blob), lines[0]);
+        assert!(lines[1].starts_with("040000 tree "));
+        assert!(lines[1].ends_with("       -\tdir"));
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    super::{
+        database::tree::{DatabaseEntry, OidAndMode},
+        index::{entry::Entry, Index},
+    },
+    repository_path,
+};
+
+// Reads the trees into the index: a single tree replaces it or, with a prefix, is added beneath
+// it, while merging follows the rules of `git read-tree -m` for one, two or three trees
+pub fn read_tree(
+    index: &mut Index,
+    trees: &[TreeList],
+    prefix: Option<&Path>,
+    merge: bool,
+) -> Result<(), Box<dyn Error>> {
+    match (prefix, merge) {
+        (Some(prefix), _) => read_tree_prefix(index, &trees[0], prefix),
+        (None, false) => {
+            index.clear_entries();
+            add_tree_entries(index, &trees[0]);
+            Ok(())
+        }
+        (None, true) if index.is_unmerged() => Err(UnresolvedIndex.into()),
+        (None, true) => read_tree_merge(index, trees),
+    }
+}
+
+pub type TreeList = BTreeMap<PathBuf, DatabaseEntry>;
+
+fn add_tree_entries(index: &mut Index, tree: &TreeList) {
+    for (path, item) in tree {
+        index.add_entry(Entry::from_database(path.clone(), item.oid(), item.mode()));
+    }
+}
+
+fn read_tree_prefix(
+    index: &mut Index,
+    tree: &TreeList,
+    prefix: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let prefix = repository_path(prefix)?;
+    if index
+        .entries()
+        .any(|entry| Path::new(entry.path()).starts_with(&prefix))
+    {
+        return Err(PrefixExists(prefix).into());
+    }
+
+    let prefixed: TreeList = tree
+        .iter()
+        .map(|(path, item)| (prefix.join(path), item.clone()))
+        .collect();
+    add_tree_entries(index, &prefixed);
+    Ok(())
+}
+
+pub fn same_object(a: Option<&DatabaseEntry>, b: Option<&DatabaseEntry>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.oid() == b.oid() && a.mode() == b.mode(),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// Implements the index rules of `git read-tree -m`, without touching the workspace
+fn read_tree_merge(index: &mut Index, trees: &[TreeList]) -> Result<(), Box<dyn Error>> {
+    let current: BTreeMap<PathBuf, Entry> = index
+        .entries()
+        .map(|entry| (PathBuf::from(entry.path()), entry.clone()))
+        .collect();
+    let initial_checkout = current.is_empty();
+
+    let mut paths: Vec<&PathBuf> = current
+        .keys()
+        .chain(trees.iter().flat_map(|t| t.keys()))
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut merged: Vec<Entry> = Vec::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let existing = current.get(path);
+        let existing_item =
+            existing.map(|entry| DatabaseEntry::new(entry.oid().to_string(), entry.mode()));
+        let index_item = existing_item.as_ref();
+        let keep = || existing.cloned();
+        let take = |item: Option<&DatabaseEntry>| {
+            item.map(|item| Entry::from_database(path.clone(), item.oid(), item.mode()))
+        };
+
+        let result = match trees {
+            // Keep the cached stat data of entries that haven't changed
+            [tree] => {
+                let item = tree.get(path);
+                if same_object(index_item, item) {
+                    keep()
+                } else {
+                    take(item)
+                }
+            }
+            [head, merge] => {
+                let (head, merge) = (head.get(path), merge.get(path));
+                match (index_item, head, merge) {
+                    (_, None, None) => keep(),
+                    (None, None, Some(_)) => take(merge),
+                    (None, Some(_), None) => None,
+                    (None, Some(_), Some(_)) if initial_checkout => take(merge),
+                    (None, Some(_), Some(_)) if same_object(head, merge) => None,
+                    (Some(_), _, _) if same_object(head, merge) => keep(),
+                    (Some(_), _, _) if same_object(index_item, merge) => keep(),
+                    (Some(_), _, _) if same_object(index_item, head) => take(merge),
+                    _ => return Err(WouldOverwrite(path.clone()).into()),
+                }
+            }
+            // Only trivial merges are resolved, where one side is unchanged from the base or both
+            // sides agree, so a path deleted on one side and kept on the other is a conflict
+            [base, ours, theirs] => {
+                let (base, ours, theirs) = (base.get(path), ours.get(path), theirs.get(path));
+                let take_theirs =
+                    theirs.is_some() && same_object(base, ours) && !same_object(base, theirs);
+                let keep_ours =
+                    ours.is_some() && (same_object(ours, theirs) || same_object(base, theirs));
+
+                // The index may already hold the result, otherwise it has to match ours
+                let index_merged = take_theirs && same_object(index_item, theirs);
+                if existing.is_some() && !same_object(index_item, ours) && !index_merged {
+                    return Err(WouldOverwrite(path.clone()).into());
+                }
+
+                if keep_ours {
+                    keep().or_else(|| take(ours))
+                } else if index_merged {
+                    keep()
+                } else if take_theirs {
+                    take(theirs)
+                } else {
+                    conflicts.push((
+                        path.clone(),
+                        [base.cloned(), ours.cloned(), theirs.cloned()],
+                    ));
+                    continue;
+                }
+            }
+            _ => unreachable!("read-tree takes between one and three trees"),
+        };
+
+        if let Some(entry) = result {
+            merged.push(entry);
+        }
+    }
+
+    index.clear_entries();
+    for entry in merged {
+        index.add_entry(entry);
+    }
+    for (path, items) in conflicts {
+        index.add_conflict_set(path, items);
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct PrefixExists(PathBuf);
+impl Error for PrefixExists {}
+impl fmt::Display for PrefixExists {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "subdirectory '{}/' already exists", self.0.display())
+    }
+}
+
+#[derive(Debug)]
+struct WouldOverwrite(PathBuf);
+impl Error for WouldOverwrite {}
+impl fmt::Display for WouldOverwrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Entry '{}' would be overwritten by merge. Cannot merge.",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct UnresolvedIndex;
+impl Error for UnresolvedIndex {}
+impl fmt::Display for UnresolvedIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "you need to resolve your current index first")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(n: char) -> DatabaseEntry {
+        DatabaseEntry::new(n.to_string().repeat(40), Entry::REGULAR_MODE)
+    }
+
+    fn tree(items: &[(&str, char)]) -> TreeList {
+        items
+            .iter()
+            .map(|(path, n)| (PathBuf::from(path), item(*n)))
+            .collect()
+    }
+
+    // Each entry is marked assume-unchanged so that entries kept from before can be told apart
+    fn index_of(items: &[(&str, char)]) -> Index {
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+        for (path, item) in tree(items) {
+            let mut entry = Entry::from_database(path, item.oid(), item.mode());
+            entry.set_assume_unchanged(true);
+            index.add_entry(entry);
+        }
+        index
+    }
+
+    fn contents(index: &Index) -> Vec<(String, u16, String, bool)> {
+        index
+            .entries()
+            .map(|entry| {
+                (
+                    entry.path().to_string_lossy().into_owned(),
+                    entry.stage(),
+                    entry.oid()[..1].to_string(),
+                    entry.assume_unchanged(),
+                )
+            })
+            .collect()
+    }
+
+    fn entry(path: &str, stage: u16, n: char, kept: bool) -> (String, u16, String, bool) {
+        (path.to_string(), stage, n.to_string(), kept)
+    }
+
+    #[test]
+    fn it_replaces_the_index_or_adds_beneath_a_prefix() {
+        let mut index = index_of(&[("a.txt", '1')]);
+        let trees = [tree(&[("b.txt", '2'), ("dir/c.txt", '3')])];
+
+        read_tree(&mut index, &trees, Some(Path::new("./lib/")), false).unwrap();
+        assert_eq!(
+            vec![
+                entry("a.txt", 0, '1', true),
+                entry("lib/b.txt", 0, '2', false),
+                entry("lib/dir/c.txt", 0, '3', false),
+            ],
+            contents(&index)
+        );
+        let error = read_tree(&mut index, &trees, Some(Path::new("lib")), false).unwrap_err();
+        assert_eq!("subdirectory 'lib/' already exists", error.to_string());
+        let error = read_tree(&mut index, &trees, Some(Path::new("lib/../..")), false).unwrap_err();
+        assert_eq!("'lib/../..' is outside the repository", error.to_string());
+
+        read_tree(&mut index, &trees, None, false).unwrap();
+        assert_eq!(
+            vec![
+                entry("b.txt", 0, '2', false),
+                entry("dir/c.txt", 0, '3', false)
+            ],
+            contents(&index)
+        );
+    }
+
+    #[test]
+    fn it_keeps_unchanged_entries_when_merging_one_tree() {
+        let mut index = index_of(&[("a.txt", '1'), ("b.txt", '2'), ("gone.txt", '3')]);
+        let trees = [tree(&[("a.txt", '1'), ("b.txt", '4'), ("new.txt", '5')])];
+
+        read_tree(&mut index, &trees, None, true).unwrap();
+        assert_eq!(
+            vec![
+                entry("a.txt", 0, '1', true),
+                entry("b.txt", 0, '4', false),
+                entry("new.txt", 0, '5', false),
+            ],
+            contents(&index)
+        );
+    }
+
+    #[test]
+    fn it_moves_from_one_tree_to_another_over_unrelated_changes() {
+        let head = tree(&[("a.txt", '1'), ("b.txt", '2'), ("c.txt", '3')]);
+        let merge = tree(&[("a.txt", '4'), ("b.txt", '2'), ("d.txt", '5')]);
+
+        let mut index = index_of(&[
+            ("a.txt", '1'),
+            ("b.txt", '6'),
+            ("c.txt", '3'),
+            ("e.txt", '7'),
+        ]);
+        read_tree(&mut index, &[head.clone(), merge.clone()], None, true).unwrap();
+        assert_eq!(
+            vec![
+                entry("a.txt", 0, '4', false),
+                entry("b.txt", 0, '6', true),
+                entry("d.txt", 0, '5', false),
+                entry("e.txt", 0, '7', true),
+            ],
+            contents(&index)
+        );
+
+        let mut index = index_of(&[("a.txt", '6')]);
+        let error = read_tree(&mut index, &[head, merge], None, true).unwrap_err();
+        assert_eq!(
+            "Entry 'a.txt' would be overwritten by merge. Cannot merge.",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn it_resolves_trivial_three_way_merges_and_records_conflicts() {
+        let base = tree(&[("a.txt", '1'), ("b.txt", '2'), ("c.txt", '3')]);
+        let ours = tree(&[("a.txt", '1'), ("b.txt", '4'), ("c.txt", '5')]);
+        let theirs = tree(&[("a.txt", '6'), ("b.txt", '2'), ("c.txt", '7')]);
+        let trees = [base, ours, theirs];
+
+        let mut index = index_of(&[("a.txt", '1'), ("b.txt", '4'), ("c.txt", '5')]);
+        read_tree(&mut index, &trees, None, true).unwrap();
+        assert_eq!(
+            vec![
+                entry("a.txt", 0, '6', false),
+                entry("b.txt", 0, '4', true),
+                entry("c.txt", 1, '3', false),
+                entry("c.txt", 2, '5', false),
+                entry("c.txt", 3, '7', false),
+            ],
+            contents(&index)
+        );
+
+        let error = read_tree(&mut index, &trees, None, true).unwrap_err();
+        assert_eq!(
+            "you need to resolve your current index first",
+            error.to_string()
+        );
+
+        let mut index = index_of(&[("a.txt", '1'), ("b.txt", '8'), ("c.txt", '5')]);
+        let error = read_tree(&mut index, &trees, None, true).unwrap_err();
+        assert_eq!(
+            "Entry 'b.txt' would be overwritten by merge. Cannot merge.",
+            error.to_string()
+        );
+    }
+}
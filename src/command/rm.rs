@@ -0,0 +1,344 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    super::{
+        database::tree::{DatabaseEntry, OidAndMode},
+        index::{entry::Entry, Index},
+        workspace::Workspace,
+    },
+    read_tree::{same_object, TreeList},
+    repository_path,
+    workspace_check::{WorkspaceCheck, WorkspaceStatus},
+};
+
+// Resolves the paths given to `rm` to the index entries they name, which must all exist
+pub fn expand_removals(
+    index: &Index,
+    paths: &[PathBuf],
+    recursive: bool,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut removals = Vec::new();
+    for given in paths {
+        let path = repository_path(given)?;
+        if index.tracked_file(path.as_os_str()) {
+            removals.push(path);
+        } else if index.tracked_directory(path.as_os_str()) {
+            if !recursive {
+                return Err(NotRecursive(given.clone()).into());
+            }
+            removals.extend(index.child_paths(path.as_os_str()));
+        } else {
+            return Err(UnmatchedPathspec(given.clone()).into());
+        }
+    }
+    removals.sort();
+    removals.dedup();
+    Ok(removals)
+}
+
+pub struct RemovalCheck<'a> {
+    pub workspace: WorkspaceCheck<'a>,
+    pub head: &'a TreeList,
+}
+
+impl RemovalCheck<'_> {
+    fn staged_changes(&self, entry: &Entry) -> bool {
+        !same_object(
+            Some(&DatabaseEntry::new(entry.oid().to_string(), entry.mode())),
+            self.head.get(Path::new(entry.path())),
+        )
+    }
+
+    // A file that has been deleted has nothing left to lose
+    fn local_changes(&self, entry: &Entry) -> bool {
+        self.workspace.status(entry) == WorkspaceStatus::Modified
+    }
+}
+
+// Removals that would lose work, grouped by what would be lost
+#[derive(Debug, Default, PartialEq)]
+pub struct RemovalProblems {
+    pub both: Vec<PathBuf>,
+    pub staged: Vec<PathBuf>,
+    pub local: Vec<PathBuf>,
+    pub repositories: Vec<PathBuf>,
+}
+
+impl RemovalProblems {
+    pub fn is_empty(&self) -> bool {
+        self.both.is_empty()
+            && self.staged.is_empty()
+            && self.local.is_empty()
+            && self.repositories.is_empty()
+    }
+}
+
+// Finds any removals that would lose work. Besides what -f allows, a submodule's own history is
+// never deleted.
+pub fn removal_problems(
+    check: &RemovalCheck,
+    removals: &[PathBuf],
+    cached: bool,
+    force: bool,
+) -> RemovalProblems {
+    let mut problems = RemovalProblems::default();
+
+    for path in removals {
+        // Conflicted paths have no single version to compare, and are removed as they are
+        let entry = match check.workspace.index.entry(path.as_os_str()) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if !cached
+            && entry.mode() == Entry::GITLINK_MODE
+            && check.workspace.root_path.join(path).join(".git").is_dir()
+        {
+            problems.repositories.push(path.clone());
+        }
+        if force {
+            continue;
+        }
+
+        match (check.staged_changes(entry), check.local_changes(entry)) {
+            (true, true) => problems.both.push(path.clone()),
+            (true, false) if !cached => problems.staged.push(path.clone()),
+            (false, true) if !cached => problems.local.push(path.clone()),
+            _ => {}
+        }
+    }
+    problems
+}
+
+// Unstages the removals and, unless only removing them from the index, deletes their files.
+// As in git, failing to delete the first file stops everything, while later failures are left
+// for the user to notice, since the index must then record what was already deleted.
+pub fn remove<W: Write>(
+    workspace: &Workspace,
+    index: &mut Index,
+    removals: &[PathBuf],
+    cached: bool,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    for path in removals {
+        index.remove(path.as_os_str());
+        let mut line = b"rm '".to_vec();
+        line.extend(path.as_os_str().as_bytes());
+        line.extend(b"'\n");
+        out.write_all(&line)?;
+    }
+    if cached {
+        return Ok(());
+    }
+
+    let mut removed = false;
+    for path in removals {
+        match workspace.remove_file(path) {
+            Ok(()) => removed = true,
+            Err(error) if !removed => return Err(CouldNotRemove(path.clone(), error).into()),
+            Err(_) => {}
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct NotRecursive(PathBuf);
+impl Error for NotRecursive {}
+impl fmt::Display for NotRecursive {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "not removing '{}' recursively without -r",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct CouldNotRemove(PathBuf, io::Error);
+impl Error for CouldNotRemove {}
+impl fmt::Display for CouldNotRemove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "could not remove '{}': {}", self.0.display(), self.1)
+    }
+}
+
+#[derive(Debug)]
+struct UnmatchedPathspec(PathBuf);
+impl Error for UnmatchedPathspec {}
+impl fmt::Display for UnmatchedPathspec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pathspec '{}' did not match any files", self.0.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        database::{blob::Blob, Database},
+        temp_dir::TempDir,
+    };
+    use super::*;
+    use std::fs;
+
+    fn paths(names: &[&str]) -> Vec<PathBuf> {
+        names.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn it_expands_directories_only_when_recursive() {
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+        for name in &["a.txt", "dir/b.txt", "dir/sub/c.txt"] {
+            index.add_entry(Entry::from_database(
+                PathBuf::from(name),
+                &"1".repeat(40),
+                Entry::REGULAR_MODE,
+            ));
+        }
+
+        assert_eq!(
+            paths(&["a.txt", "dir/b.txt"]),
+            expand_removals(
+                &index,
+                &paths(&["./dir/b.txt", "dir/../a.txt", "a.txt"]),
+                false
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            paths(&["dir/b.txt", "dir/sub/c.txt"]),
+            expand_removals(&index, &paths(&["dir"]), true).unwrap()
+        );
+        let error = expand_removals(&index, &paths(&["dir"]), false).unwrap_err();
+        assert_eq!(
+            "not removing 'dir' recursively without -r",
+            error.to_string()
+        );
+        let error = expand_removals(&index, &paths(&["missing"]), true).unwrap_err();
+        assert_eq!(
+            "pathspec 'missing' did not match any files",
+            error.to_string()
+        );
+        let error = expand_removals(&index, &paths(&["dir/../../a.txt"]), false).unwrap_err();
+        assert_eq!(
+            "'dir/../../a.txt' is outside the repository",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn it_refuses_removals_that_would_lose_work() {
+        let root_path = TempDir::new("rm");
+        fs::create_dir_all(root_path.join("sub/.git")).unwrap();
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join("objects"));
+        let oid = |content: &str| database.hash_object(&Blob::new(content.as_bytes().to_vec()));
+
+        // Each file is staged as `staged` and committed as `committed`, then made `current`
+        let files = [
+            ("clean.txt", "a", "a", "a"),
+            ("staged.txt", "a", "b", "b"),
+            ("local.txt", "a", "a", "c"),
+            ("both.txt", "a", "b", "c"),
+        ];
+        let mut index = Index::new(root_path.join("index"));
+        let mut head = TreeList::new();
+        for (name, committed, staged, current) in &files {
+            let path = PathBuf::from(name);
+            fs::write(root_path.join(name), staged).unwrap();
+            index.add(path.clone(), oid(staged), workspace.stat_file(&path));
+            fs::write(root_path.join(name), current).unwrap();
+            head.insert(
+                path,
+                DatabaseEntry::new(oid(committed), Entry::REGULAR_MODE),
+            );
+        }
+        // A submodule whose repository has no commit checked out, unlike the one recorded
+        let submodule = DatabaseEntry::new("1".repeat(40), Entry::GITLINK_MODE);
+        index.add_entry(Entry::from_database(
+            PathBuf::from("sub"),
+            submodule.oid(),
+            submodule.mode(),
+        ));
+        head.insert(PathBuf::from("sub"), submodule);
+
+        let check = RemovalCheck {
+            workspace: WorkspaceCheck::new(&root_path, &workspace, &database, &index),
+            head: &head,
+        };
+        let removals = paths(&["both.txt", "clean.txt", "local.txt", "staged.txt", "sub"]);
+
+        assert_eq!(
+            RemovalProblems {
+                both: paths(&["both.txt"]),
+                staged: paths(&["staged.txt"]),
+                local: paths(&["local.txt", "sub"]),
+                repositories: paths(&["sub"]),
+            },
+            removal_problems(&check, &removals, false, false)
+        );
+        assert_eq!(
+            RemovalProblems {
+                both: paths(&["both.txt"]),
+                ..RemovalProblems::default()
+            },
+            removal_problems(&check, &removals, true, false)
+        );
+        assert_eq!(
+            RemovalProblems {
+                repositories: paths(&["sub"]),
+                ..RemovalProblems::default()
+            },
+            removal_problems(&check, &removals, false, true)
+        );
+        assert!(removal_problems(&check, &removals, true, true).is_empty());
+    }
+
+    #[test]
+    fn it_stops_only_if_the_first_file_cannot_be_deleted() {
+        let root_path = TempDir::new("rm_remove");
+        fs::create_dir_all(root_path.join("dir")).unwrap();
+        for name in &["blocker", "dir/a.txt", "dir/b.txt"] {
+            fs::write(root_path.join(name), "").unwrap();
+        }
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let mut index = Index::new(root_path.join("index"));
+        for name in &["blocker/x.txt", "dir/a.txt", "dir/b.txt"] {
+            index.add_entry(Entry::from_database(
+                PathBuf::from(name),
+                Entry::EMPTY_BLOB_OID,
+                Entry::REGULAR_MODE,
+            ));
+        }
+
+        // A file beneath a file can't be looked at, let alone deleted
+        let mut out = Vec::new();
+        let error = remove(
+            &workspace,
+            &mut index,
+            &paths(&["blocker/x.txt"]),
+            false,
+            &mut out,
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("could not remove 'blocker/x.txt': "));
+
+        let mut out = Vec::new();
+        let removals = paths(&["dir/a.txt", "blocker/x.txt", "dir/b.txt"]);
+        remove(&workspace, &mut index, &removals, false, &mut out).unwrap();
+        assert_eq!(
+            "rm 'dir/a.txt'\nrm 'blocker/x.txt'\nrm 'dir/b.txt'\n",
+            String::from_utf8(out).unwrap()
+        );
+        assert!(index.entries().next().is_none());
+        assert!(!root_path.join("dir").exists());
+    }
+}
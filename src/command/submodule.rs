@@ -0,0 +1,325 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::super::{
+    config::Config,
+    database::tree::OidAndMode,
+    index::{entry::Entry, Index},
+    submodule::{self, Submodule},
+};
+
+// Lists each submodule with the commit it has checked out, prefixed by `-` if it isn't checked
+// out and `+` if that's not the commit recorded in the index
+pub fn status<W: Write>(root_path: &Path, index: &Index, out: &mut W) -> io::Result<()> {
+    for submodule in Submodule::list(root_path) {
+        let recorded = match recorded_commit(index, &submodule) {
+            Some(oid) => oid,
+            None => continue,
+        };
+
+        let (prefix, oid) = if !submodule.is_checked_out(root_path) {
+            ('-', recorded)
+        } else {
+            match submodule::head_commit(&root_path.join(submodule.path())) {
+                Some(head) if head == recorded => (' ', head),
+                Some(head) => ('+', head),
+                None => ('-', recorded),
+            }
+        };
+        writeln!(out, "{}{} {}", prefix, oid, submodule.path().display())?;
+    }
+    Ok(())
+}
+
+pub fn init<W: Write>(
+    root_path: &Path,
+    config_path: &Path,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    for submodule in Submodule::list(root_path) {
+        if submodule.init(root_path, config_path)? {
+            writeln!(
+                out,
+                "Submodule '{}' ({}) registered for path '{}'",
+                submodule.name(),
+                submodule.url().unwrap_or(""),
+                submodule.path().display()
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Checks out the commit recorded in the index in every submodule that has been initialised
+pub fn update<W: Write>(
+    root_path: &Path,
+    config_path: &Path,
+    index: &Index,
+    out: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let config = Config::load(config_path);
+
+    for submodule in Submodule::list(root_path) {
+        let url = match config.get(&format!("submodule.{}.url", submodule.name())) {
+            Some(url) => PathBuf::from(url),
+            None => continue,
+        };
+        let recorded = match recorded_commit(index, &submodule) {
+            Some(oid) => oid,
+            None => continue,
+        };
+
+        let updated = submodule
+            .update(root_path, &url, &recorded)
+            .map_err(|error| UpdateFailed(submodule.path().to_path_buf(), error))?;
+        if updated {
+            writeln!(
+                out,
+                "Submodule path '{}': checked out '{}'",
+                submodule.path().display(),
+                recorded
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn recorded_commit(index: &Index, submodule: &Submodule) -> Option<String> {
+    match index.entry(submodule.path().as_os_str()) {
+        Some(entry) if entry.mode() == Entry::GITLINK_MODE => Some(entry.oid().to_string()),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct UpdateFailed(PathBuf, Box<dyn Error>);
+impl Error for UpdateFailed {}
+impl fmt::Display for UpdateFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unable to update submodule path '{}': {}",
+            self.0.display(),
+            self.1
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        database::{author::Author, blob::Blob, commit::Commit, tree::Tree, Database, Storable},
+        temp_dir::TempDir,
+    };
+    use super::*;
+    use chrono::{DateTime, FixedOffset};
+    use std::fs;
+
+    // A superproject at `<dir>/super` whose `lib` submodule comes from the repository at
+    // `<dir>/lib`, which starts out with no commits
+    fn superproject(name: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        for repo in &["super", "lib"] {
+            fs::create_dir_all(dir.join(repo).join(".git/objects")).unwrap();
+        }
+        fs::write(
+            dir.join("super/.gitmodules"),
+            "[submodule \"lib\"]\n\tpath = lib\n\turl = ../lib\n",
+        )
+        .unwrap();
+        let root_path = dir.join("super");
+        (dir, root_path)
+    }
+
+    fn commit(repo_path: &Path, files: &[(&str, &str)], parent: Option<String>) -> String {
+        let database = Database::new(repo_path.join(".git/objects"));
+        let entries: Vec<_> = files
+            .iter()
+            .map(|(path, content)| {
+                let mut blob = Blob::new(content.as_bytes().to_vec());
+                database.store(&mut blob);
+                Entry::from_database(
+                    PathBuf::from(path),
+                    blob.oid().unwrap(),
+                    Entry::REGULAR_MODE,
+                )
+            })
+            .collect();
+        let mut root = Tree::build(&entries);
+        root.traverse(|tree| database.store(tree));
+
+        let time = DateTime::<FixedOffset>::parse_from_str("1600000000 +0100", "%s %z").unwrap();
+        let author = Author::new(String::from("Alice"), String::from("a@b.c"), time);
+        let tree = Storable::oid(&root).unwrap().to_string();
+        let mut commit = Commit::new(parent, tree, author, "Message\n");
+        database.store(&mut commit);
+        commit.oid().unwrap().to_string()
+    }
+
+    fn recording(oid: &str) -> Index {
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+        index.add_entry(Entry::from_database(
+            PathBuf::from("lib"),
+            oid,
+            Entry::GITLINK_MODE,
+        ));
+        index
+    }
+
+    fn run_update(root_path: &Path, index: &Index) -> Result<String, Box<dyn Error>> {
+        let mut out = Vec::new();
+        update(root_path, &root_path.join(".git/config"), index, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn it_registers_submodule_urls_once() {
+        let (_dir, root_path) = superproject("init");
+        let config_path = root_path.join(".git/config");
+
+        let mut out = Vec::new();
+        init(&root_path, &config_path, &mut out).unwrap();
+        assert_eq!(
+            "Submodule 'lib' (../lib) registered for path 'lib'\n",
+            String::from_utf8(out).unwrap()
+        );
+        let url = root_path.join("../lib").canonicalize().unwrap();
+        assert_eq!(
+            Some(url.to_str().unwrap()),
+            Config::load(&config_path).get("submodule.lib.url")
+        );
+
+        let mut out = Vec::new();
+        init(&root_path, &config_path, &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn it_shows_whether_submodules_have_the_recorded_commit() {
+        let (_dir, root_path) = superproject("status");
+        let lib_path = root_path.join("../lib");
+        let first = commit(&lib_path, &[("a.txt", "one")], None);
+        let index = recording(&first);
+
+        let show = || {
+            let mut out = Vec::new();
+            status(&root_path, &index, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(format!("-{} lib\n", first), show());
+
+        init(&root_path, &root_path.join(".git/config"), &mut Vec::new()).unwrap();
+        run_update(&root_path, &index).unwrap();
+        assert_eq!(format!(" {} lib\n", first), show());
+
+        let second = commit(&lib_path, &[("a.txt", "two")], Some(first));
+        run_update(&root_path, &recording(&second)).unwrap();
+        assert_eq!(format!("+{} lib\n", second), show());
+    }
+
+    #[test]
+    fn it_checks_out_the_recorded_commit() {
+        let (_dir, root_path) = superproject("update");
+        let lib_path = root_path.join("../lib");
+        let first = commit(&lib_path, &[("a.txt", "one"), ("old.txt", "old")], None);
+        let second = commit(&lib_path, &[("a.txt", "two"), ("new.txt", "new")], None);
+
+        // Nothing happens to submodules that haven't been initialised
+        assert_eq!("", run_update(&root_path, &recording(&first)).unwrap());
+        assert!(!root_path.join("lib").exists());
+
+        init(&root_path, &root_path.join(".git/config"), &mut Vec::new()).unwrap();
+        assert_eq!(
+            format!("Submodule path 'lib': checked out '{}'\n", first),
+            run_update(&root_path, &recording(&first)).unwrap()
+        );
+        assert_eq!(
+            "one",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+        assert_eq!(
+            Some(first.clone()),
+            submodule::head_commit(&root_path.join("lib"))
+        );
+        assert_eq!("", run_update(&root_path, &recording(&first)).unwrap());
+
+        run_update(&root_path, &recording(&second)).unwrap();
+        assert_eq!(
+            "two",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+        assert!(root_path.join("lib/new.txt").exists());
+        assert!(!root_path.join("lib/old.txt").exists());
+    }
+
+    #[test]
+    fn it_refuses_to_clone_from_a_repository_with_packs() {
+        let (_dir, root_path) = superproject("packs");
+        let lib_path = root_path.join("../lib");
+        let first = commit(&lib_path, &[("a.txt", "one")], None);
+        let pack_path = lib_path.join(".git/objects/pack");
+        fs::create_dir_all(&pack_path).unwrap();
+        fs::write(pack_path.join("pack-1.pack"), "pack").unwrap();
+        fs::write(pack_path.join("pack-1.idx"), "idx").unwrap();
+
+        init(&root_path, &root_path.join(".git/config"), &mut Vec::new()).unwrap();
+        let error = run_update(&root_path, &recording(&first)).unwrap_err();
+        assert!(error.to_string().contains("has packed objects"));
+        assert!(!root_path.join("lib").exists());
+
+        fs::remove_dir_all(&pack_path).unwrap();
+        run_update(&root_path, &recording(&first)).unwrap();
+        assert_eq!(
+            "one",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_leaves_changed_or_locked_submodules_alone() {
+        let (_dir, root_path) = superproject("dirty");
+        let lib_path = root_path.join("../lib");
+        let first = commit(&lib_path, &[("a.txt", "one")], None);
+        let second = commit(&lib_path, &[("a.txt", "two"), ("b.txt", "b")], None);
+        init(&root_path, &root_path.join(".git/config"), &mut Vec::new()).unwrap();
+        run_update(&root_path, &recording(&first)).unwrap();
+
+        // An untracked file where the new commit has one
+        fs::write(root_path.join("lib/b.txt"), "mine").unwrap();
+        let error = run_update(&root_path, &recording(&second)).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("untracked working tree file 'b.txt'"));
+        fs::remove_file(root_path.join("lib/b.txt")).unwrap();
+
+        // A tracked file with changes of its own
+        fs::write(root_path.join("lib/a.txt"), "changed").unwrap();
+        let error = run_update(&root_path, &recording(&second)).unwrap_err();
+        assert!(error.to_string().contains("local changes to 'a.txt'"));
+        assert!(!root_path.join("lib/.git/index.lock").exists());
+        assert_eq!(
+            "changed",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+        fs::write(root_path.join("lib/a.txt"), "one").unwrap();
+
+        fs::write(root_path.join("lib/.git/index.lock"), "").unwrap();
+        let error = run_update(&root_path, &recording(&second)).unwrap_err();
+        assert!(error.to_string().contains("index.lock"));
+        assert_eq!(
+            "one",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+        fs::remove_file(root_path.join("lib/.git/index.lock")).unwrap();
+
+        run_update(&root_path, &recording(&second)).unwrap();
+        assert_eq!(
+            "two",
+            fs::read_to_string(root_path.join("lib/a.txt")).unwrap()
+        );
+    }
+}
@@ -0,0 +1,390 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::Metadata,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    super::{
+        database::{blob::Blob, tree::OidAndMode, Database, Storable},
+        index::{entry::Entry, Index},
+        submodule,
+        workspace::Workspace,
+    },
+    workspace_check::WorkspaceCheck,
+};
+
+// Brings the entry for `path` in line with the working tree, adding it only with `add` and
+// dropping it only with `remove`
+pub fn update_path(
+    root_path: &Path,
+    workspace: &Workspace,
+    database: &Database,
+    index: &mut Index,
+    path: &Path,
+    add: bool,
+    remove: bool,
+) -> Result<(), Box<dyn Error>> {
+    let check = WorkspaceCheck::new(root_path, workspace, database, index);
+    match index_update(&check, path, add, remove)? {
+        IndexUpdate::Unchanged => {}
+        IndexUpdate::Removed => index.remove(path.as_os_str()),
+        IndexUpdate::Added(oid, stat) => index.add(path.to_path_buf(), oid, stat),
+    }
+    Ok(())
+}
+
+enum IndexUpdate {
+    Unchanged,
+    Removed,
+    Added(String, Metadata),
+}
+
+// Works out how the entry for `path` should change to match the working tree
+fn index_update(
+    check: &WorkspaceCheck,
+    path: &Path,
+    add: bool,
+    remove: bool,
+) -> Result<IndexUpdate, Box<dyn Error>> {
+    let entry = check.index.entry(path.as_os_str());
+    let stat = match check.root_path.join(path).symlink_metadata() {
+        Ok(stat) => stat,
+        Err(_) if remove => return Ok(IndexUpdate::Removed),
+        Err(_) => return Err(MissingWithoutRemove(path.to_path_buf()).into()),
+    };
+
+    if entry.is_some_and(|entry| entry.skip_worktree() || entry.fsmonitor_valid()) {
+        return Ok(IndexUpdate::Unchanged);
+    }
+    let oid = if stat.is_dir() {
+        let oid = submodule::head_commit(&check.root_path.join(path))
+            .ok_or_else(|| IsDirectory(path.to_path_buf()))?;
+        if entry.is_none() && !add {
+            return Err(MissingAdd(path.to_path_buf()).into());
+        }
+        oid
+    } else {
+        if entry.is_none() && !add {
+            return Err(MissingAdd(path.to_path_buf()).into());
+        }
+        if entry.is_some_and(|entry| check.index.is_stat_clean(entry, &stat)) {
+            return Ok(IndexUpdate::Unchanged);
+        }
+        let mut blob = Blob::new(check.workspace.read_file(path));
+        check.database.store(&mut blob);
+        blob.oid().unwrap().to_string()
+    };
+
+    Ok(IndexUpdate::Added(oid, stat))
+}
+
+// Stages the object named by `--cacheinfo <mode>,<oid>,<path>` without looking at the working tree
+pub fn add_cacheinfo(index: &mut Index, info: &str, add: bool) -> Result<PathBuf, Box<dyn Error>> {
+    let mut fields = info.splitn(3, ',');
+    let (mode, oid, path) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(mode), Some(oid), Some(path)) => (mode, oid, PathBuf::from(path)),
+        _ => return Err(InvalidCacheinfo.into()),
+    };
+    let mode = u32::from_str_radix(mode, 8).map_err(|_| InvalidCacheinfo)?;
+    if oid.len() != 40 || hex::decode(oid).is_err() {
+        return Err(InvalidCacheinfo.into());
+    }
+
+    if index.entry(path.as_os_str()).is_none() && !add {
+        return Err(CacheinfoCannotAdd(path).into());
+    }
+    index.add_entry(Entry::from_database(
+        path.clone(),
+        &oid.to_lowercase(),
+        Entry::canonical_mode(mode),
+    ));
+    Ok(path)
+}
+
+// Sets or clears the assume-unchanged and skip-worktree bits of a staged path, leaving its
+// content as staged
+pub fn mark(
+    index: &mut Index,
+    path: &Path,
+    assume_unchanged: Option<bool>,
+    skip_worktree: Option<bool>,
+) -> Result<(), Box<dyn Error>> {
+    let name = path.as_os_str();
+    let marked = assume_unchanged.is_none_or(|flag| index.set_assume_unchanged(name, flag))
+        && skip_worktree.is_none_or(|flag| index.set_skip_worktree(name, flag));
+    if !marked {
+        return Err(UnableToMark(path.to_path_buf()).into());
+    }
+    Ok(())
+}
+
+// Applies `--chmod=+x` or `--chmod=-x` to staged regular files
+pub fn chmod(index: &mut Index, paths: &[PathBuf], chmod: &str) -> Result<(), Box<dyn Error>> {
+    let mode = match chmod {
+        "+x" => Entry::EXECUTABLE_MODE,
+        _ => Entry::REGULAR_MODE,
+    };
+    for path in paths {
+        let is_file = index
+            .entry(path.as_os_str())
+            .is_some_and(|entry| Entry::is_regular(entry.mode()));
+        if !is_file || !index.set_entry_mode(path.as_os_str(), mode) {
+            return Err(CannotChmod(chmod.to_string(), path.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct MissingWithoutRemove(PathBuf);
+impl Error for MissingWithoutRemove {}
+impl fmt::Display for MissingWithoutRemove {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: does not exist and --remove not passed",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingAdd(PathBuf);
+impl Error for MissingAdd {}
+impl fmt::Display for MissingAdd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: cannot add to the index - missing --add option?",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct IsDirectory(PathBuf);
+impl Error for IsDirectory {}
+impl fmt::Display for IsDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: is a directory - add files inside instead",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCacheinfo;
+impl Error for InvalidCacheinfo {}
+impl fmt::Display for InvalidCacheinfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "option 'cacheinfo' expects <mode>,<sha1>,<path>")
+    }
+}
+
+#[derive(Debug)]
+pub struct CacheinfoCannotAdd(PathBuf);
+impl Error for CacheinfoCannotAdd {}
+impl fmt::Display for CacheinfoCannotAdd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "git update-index: --cacheinfo cannot add {}",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct UnableToMark(PathBuf);
+impl Error for UnableToMark {}
+impl fmt::Display for UnableToMark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to mark file {}", self.0.display())
+    }
+}
+
+#[derive(Debug)]
+pub struct CannotChmod(String, PathBuf);
+impl Error for CannotChmod {}
+impl fmt::Display for CannotChmod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "git update-index: cannot chmod {} '{}'",
+            self.0,
+            self.1.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::temp_dir::TempDir;
+    use super::*;
+    use std::{ffi::OsStr, fs};
+
+    fn oid(n: u8) -> String {
+        n.to_string().repeat(40)
+    }
+
+    #[test]
+    fn it_updates_paths_only_as_add_and_remove_allow() {
+        let root_path = TempDir::repository("update_index");
+        fs::create_dir_all(root_path.join("dir")).unwrap();
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join(".git/objects"));
+        let mut index = Index::new(root_path.join(".git/index"));
+        fs::write(root_path.join("new.txt"), "new").unwrap();
+
+        let error = update_path(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("new.txt"),
+            false,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "new.txt: cannot add to the index - missing --add option?",
+            error.to_string()
+        );
+        update_path(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("new.txt"),
+            true,
+            false,
+        )
+        .unwrap();
+        let blob = database.hash_object(&Blob::new(b"new".to_vec()));
+        assert_eq!(blob, index.entry(OsStr::new("new.txt")).unwrap().oid());
+
+        let error = update_path(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("dir"),
+            true,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "dir: is a directory - add files inside instead",
+            error.to_string()
+        );
+
+        fs::remove_file(root_path.join("new.txt")).unwrap();
+        let error = update_path(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("new.txt"),
+            false,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            "new.txt: does not exist and --remove not passed",
+            error.to_string()
+        );
+        update_path(
+            &root_path,
+            &workspace,
+            &database,
+            &mut index,
+            Path::new("new.txt"),
+            false,
+            true,
+        )
+        .unwrap();
+        assert!(index.entry(OsStr::new("new.txt")).is_none());
+    }
+
+    #[test]
+    fn it_stages_cacheinfo_only_when_well_formed() {
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+
+        let info = format!("100755,{},bin/run", oid(1));
+        let error = add_cacheinfo(&mut index, &info, false).unwrap_err();
+        assert_eq!(
+            "git update-index: --cacheinfo cannot add bin/run",
+            error.to_string()
+        );
+        assert_eq!(
+            PathBuf::from("bin/run"),
+            add_cacheinfo(&mut index, &info, true).unwrap()
+        );
+        let entry = index.entry(OsStr::new("bin/run")).unwrap();
+        assert_eq!(
+            (oid(1).as_str(), Entry::EXECUTABLE_MODE),
+            (entry.oid(), entry.mode())
+        );
+
+        // An existing entry can be replaced without --add, and odd modes are canonicalised
+        let info = format!("100664,{},bin/run", oid(2));
+        add_cacheinfo(&mut index, &info, false).unwrap();
+        let entry = index.entry(OsStr::new("bin/run")).unwrap();
+        assert_eq!(
+            (oid(2).as_str(), Entry::REGULAR_MODE),
+            (entry.oid(), entry.mode())
+        );
+
+        for info in &[
+            "100644,bin/run",
+            "10x644,1111,bin/run",
+            "100644,xyz,bin/run",
+        ] {
+            let error = add_cacheinfo(&mut index, info, true).unwrap_err();
+            assert_eq!(
+                "option 'cacheinfo' expects <mode>,<sha1>,<path>",
+                error.to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn it_marks_and_chmods_only_staged_paths() {
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+        index.add_entry(Entry::from_database(
+            PathBuf::from("a.txt"),
+            &oid(1),
+            Entry::REGULAR_MODE,
+        ));
+        index.add_entry(Entry::from_database(
+            PathBuf::from("link"),
+            &oid(2),
+            Entry::SYMLINK_MODE,
+        ));
+
+        mark(&mut index, Path::new("a.txt"), Some(true), Some(true)).unwrap();
+        let entry = index.entry(OsStr::new("a.txt")).unwrap();
+        assert!(entry.assume_unchanged() && entry.skip_worktree());
+        mark(&mut index, Path::new("a.txt"), None, Some(false)).unwrap();
+        let entry = index.entry(OsStr::new("a.txt")).unwrap();
+        assert!(entry.assume_unchanged() && !entry.skip_worktree());
+        let error = mark(&mut index, Path::new("missing"), Some(true), None).unwrap_err();
+        assert_eq!("Unable to mark file missing", error.to_string());
+
+        chmod(&mut index, &[PathBuf::from("a.txt")], "+x").unwrap();
+        let entry = index.entry(OsStr::new("a.txt")).unwrap();
+        assert_eq!(Entry::EXECUTABLE_MODE, entry.mode());
+        chmod(&mut index, &[PathBuf::from("a.txt")], "-x").unwrap();
+        let entry = index.entry(OsStr::new("a.txt")).unwrap();
+        assert_eq!(Entry::REGULAR_MODE, entry.mode());
+        let error = chmod(&mut index, &[PathBuf::from("link")], "+x").unwrap_err();
+        assert_eq!(
+            "git update-index: cannot chmod +x 'link'",
+            error.to_string()
+        );
+    }
+}
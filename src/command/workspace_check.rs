@@ -0,0 +1,149 @@
+use std::{
+    cell::RefCell,
+    fs::Metadata,
+    path::{Path, PathBuf},
+};
+
+use super::super::{
+    database::{blob::Blob, tree::OidAndMode, Database},
+    index::{entry::Entry, Index},
+    submodule,
+    workspace::Workspace,
+};
+
+// Compares index entries with the files in the working tree
+pub struct WorkspaceCheck<'a> {
+    pub root_path: &'a Path,
+    pub workspace: &'a Workspace,
+    pub database: &'a Database,
+    pub index: &'a Index,
+    // Entries found unchanged, with their current stat data
+    refreshed: RefCell<Vec<(PathBuf, Metadata)>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WorkspaceStatus {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+impl<'a> WorkspaceCheck<'a> {
+    pub fn new(
+        root_path: &'a Path,
+        workspace: &'a Workspace,
+        database: &'a Database,
+        index: &'a Index,
+    ) -> WorkspaceCheck<'a> {
+        WorkspaceCheck {
+            root_path,
+            workspace,
+            database,
+            index,
+            refreshed: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn status(&self, entry: &Entry) -> WorkspaceStatus {
+        if entry.skip_worktree() || entry.fsmonitor_valid() {
+            return WorkspaceStatus::Unchanged;
+        }
+
+        let path = Path::new(entry.path());
+        let stat = match self.root_path.join(path).symlink_metadata() {
+            Ok(stat) => stat,
+            Err(_) => return WorkspaceStatus::Deleted,
+        };
+        if entry.assume_unchanged() {
+            return WorkspaceStatus::Unchanged;
+        }
+
+        // Content is only read when the stat data can't tell either way
+        let modified = if !self.index.stat_match(entry, &stat) {
+            true
+        } else if self.index.is_stat_clean(entry, &stat) {
+            false
+        } else if entry.mode() == Entry::GITLINK_MODE {
+            submodule::head_commit(&self.root_path.join(path)).as_deref() != Some(entry.oid())
+        } else {
+            let blob = Blob::new(self.workspace.read_file(path));
+            self.database.hash_object(&blob) != entry.oid()
+        };
+
+        if modified {
+            WorkspaceStatus::Modified
+        } else {
+            if entry.mode() != Entry::GITLINK_MODE {
+                self.refreshed.borrow_mut().push((path.to_path_buf(), stat));
+            }
+            WorkspaceStatus::Unchanged
+        }
+    }
+
+    // The entries found unchanged, with stat data to record for them so that later checks can go
+    // by the stat data alone, or skip them while the filesystem monitor sees no change
+    pub fn into_refreshed(self) -> Vec<(PathBuf, Metadata)> {
+        self.refreshed.into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::temp_dir::TempDir;
+    use super::*;
+    use std::{fs, time::SystemTime};
+
+    #[test]
+    fn it_reads_content_only_when_stat_data_is_inconclusive() {
+        let root_path = TempDir::new("workspace_check");
+        for name in &["same.txt", "changed.txt", "deleted.txt"] {
+            fs::write(root_path.join(name), "content").unwrap();
+        }
+
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join("objects"));
+        let mut index = Index::new(root_path.join("index"));
+        let oid = database.hash_object(&Blob::new(b"content".to_vec()));
+        for name in &["same.txt", "changed.txt", "deleted.txt"] {
+            let path = PathBuf::from(name);
+            index.add(path.clone(), oid.clone(), workspace.stat_file(&path));
+        }
+        fs::write(root_path.join("changed.txt"), "CONTENT").unwrap();
+        fs::remove_file(root_path.join("deleted.txt")).unwrap();
+
+        let check = WorkspaceCheck::new(&root_path, &workspace, &database, &index);
+        let status = |name: &str| check.status(index.entry(name.as_ref()).unwrap());
+        assert_eq!(WorkspaceStatus::Unchanged, status("same.txt"));
+        assert_eq!(WorkspaceStatus::Modified, status("changed.txt"));
+        assert_eq!(WorkspaceStatus::Deleted, status("deleted.txt"));
+    }
+
+    #[test]
+    fn it_refreshes_the_stat_data_of_files_found_unchanged() {
+        let root_path = TempDir::new("workspace_refresh");
+        let path = PathBuf::from("file.txt");
+        fs::write(root_path.join(&path), "content").unwrap();
+
+        let workspace = Workspace::new(root_path.to_path_buf());
+        let database = Database::new(root_path.join("objects"));
+        let mut index = Index::new(root_path.join("index"));
+        let oid = database.hash_object(&Blob::new(b"content".to_vec()));
+        index.add(path.clone(), oid, workspace.stat_file(&path));
+
+        // The same content written again has new stat data
+        let file = fs::File::create(root_path.join(&path)).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        fs::write(root_path.join(&path), "content").unwrap();
+        let stat = workspace.stat_file(&path);
+        assert!(!index.is_stat_clean(index.entry(path.as_os_str()).unwrap(), &stat));
+
+        let check = WorkspaceCheck::new(&root_path, &workspace, &database, &index);
+        let entry = index.entry(path.as_os_str()).unwrap();
+        assert_eq!(WorkspaceStatus::Unchanged, check.status(entry));
+        for (path, stat) in check.into_refreshed() {
+            index.update_entry_stat(path.as_os_str(), &stat);
+        }
+
+        assert!(index.is_stat_clean(index.entry(path.as_os_str()).unwrap(), &stat));
+    }
+}
@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use super::super::{
+    database::{tree::Tree, Database},
+    index::Index,
+};
+
+// Paths only added with intent to add have no content to commit yet, so they're left out. Git
+// skips over index entries using the counts in the cache tree, so trees holding them mustn't be
+// cached either.
+pub fn write_tree(database: &Database, index: &mut Index) -> Tree {
+    let entries = index.entries().filter(|entry| !entry.intent_to_add());
+    let mut root = Tree::build_cached(entries, index.cache_tree());
+
+    let pending: Vec<PathBuf> = index
+        .entries()
+        .filter(|entry| entry.intent_to_add())
+        .map(|entry| PathBuf::from(entry.path()))
+        .collect();
+    index.update_cache_tree(|cache| {
+        root.traverse_cached(cache, &mut |tree| database.store(tree));
+        for path in pending {
+            cache.invalidate(&path);
+        }
+    });
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{
+        database::{blob::Blob, tree::OidAndMode, Object, Storable},
+        index::entry::Entry,
+        temp_dir::TempDir,
+    };
+    use super::*;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn it_leaves_paths_intended_to_add_out_of_trees_and_the_cache() {
+        let path = TempDir::new("write_tree");
+        let database = Database::new(path.to_path_buf());
+        let mut blob = Blob::new(b"content".to_vec());
+        database.store(&mut blob);
+        let oid = blob.oid().unwrap();
+
+        let mut index = Index::new(PathBuf::from("../tmp/index"));
+        for name in &["a.txt", "dir/b.txt", "other/c.txt"] {
+            index.add_entry(Entry::from_database(PathBuf::from(name), oid, 0o100644));
+        }
+        let mut pending = Entry::from_database(PathBuf::from("dir/new.txt"), oid, 0o100644);
+        pending.set_intent_to_add();
+        index.add_entry(pending);
+
+        let root = write_tree(&database, &mut index);
+
+        let dir = root.entry(OsStr::new("dir")).unwrap();
+        match database.load(dir.oid()).unwrap() {
+            Object::Tree(tree) => assert_eq!(
+                vec![OsStr::new("b.txt")],
+                tree.entries().map(|(name, _)| name).collect::<Vec<_>>()
+            ),
+            _ => panic!("dir should be a tree"),
+        }
+        let cache = index.cache_tree();
+        assert!(!cache.is_valid());
+        assert!(!cache.subtree(OsStr::new("dir")).unwrap().is_valid());
+        assert!(cache.subtree(OsStr::new("other")).unwrap().is_valid());
+        assert!(Storable::oid(&root).is_some());
+    }
+}
@@ -0,0 +1,262 @@
+use std::{
+    collections::HashMap,
+    error::{self, Error},
+    fmt, fs,
+    io::ErrorKind,
+    path::Path,
+};
+
+use super::lockfile::{LockDenied, Lockfile};
+
+// Read-only view of a git-style config file, e.g. `.git/config`
+//
+// Keys are stored as "section.subsection.name" with the section and name lowercased, since git
+// treats those case-insensitively while subsections are case-sensitive.
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            values: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Config {
+        match fs::read_to_string(path) {
+            Ok(content) => Config::parse(&content),
+            Err(error) => match error.kind() {
+                ErrorKind::NotFound => Config::new(),
+                _ => panic!("Error when reading config file: {:?}", error),
+            },
+        }
+    }
+
+    pub fn parse(content: &str) -> Config {
+        let mut config = Config::new();
+        let mut section = String::new();
+
+        for line in content.lines() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = parse_section(&line[1..line.len() - 1]);
+                continue;
+            }
+
+            let (name, value) = match line.find('=') {
+                Some(split) => (line[..split].trim(), parse_value(line[split + 1..].trim())),
+                // A bare key is shorthand for "true"
+                None => (line, String::from("true")),
+            };
+            config
+                .values
+                .insert(format!("{}.{}", section, name.to_lowercase()), value);
+        }
+
+        config
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values.get(&normalise_key(key)).map(String::as_str)
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key)
+            .and_then(|value| match value.to_lowercase().as_str() {
+                "true" | "yes" | "on" | "1" => Some(true),
+                "false" | "no" | "off" | "0" | "" => Some(false),
+                _ => None,
+            })
+    }
+
+    pub fn get_int(&self, key: &str) -> Option<i64> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    // Lists the distinct subsection names of `section`, e.g. the names of all submodules
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let prefix = format!("{}.", section.to_lowercase());
+        let mut names: Vec<String> = self
+            .values
+            .keys()
+            .filter_map(|key| key.strip_prefix(&prefix))
+            .filter_map(|rest| rest.rfind('.').map(|split| rest[..split].to_string()))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // Sets `key` in the config file at `path`, keeping the rest of the file as it is and adding
+    // the key's section if it doesn't exist yet
+    pub fn set(path: &Path, key: &str, value: &str) -> Result<(), Box<dyn error::Error>> {
+        let mut lockfile = Lockfile::new(path.to_path_buf());
+        if !lockfile.hold_for_update()? {
+            return Err(LockDenied(lockfile.lock_path().to_path_buf()).into());
+        }
+
+        let key = normalise_key(key);
+        let split = key.rfind('.').ok_or_else(|| InvalidKey(key.clone()))?;
+        let (section, name) = (&key[..split], &key[split + 1..]);
+        let setting = format!("\t{} = {}", name, quote_value(value));
+
+        let content = fs::read_to_string(path).unwrap_or_default();
+        let mut lines: Vec<String> = content.lines().map(String::from).collect();
+
+        let mut current = String::new();
+        let mut section_end = None;
+        let mut existing = None;
+        for (i, line) in lines.iter().enumerate() {
+            let trimmed = strip_comment(line).trim();
+            if trimmed.starts_with('[') && trimmed.ends_with(']') {
+                current = parse_section(&trimmed[1..trimmed.len() - 1]);
+                if current == section {
+                    section_end = Some(i + 1);
+                }
+            } else if current == section && !trimmed.is_empty() {
+                section_end = Some(i + 1);
+                let line_name = trimmed.split('=').next().unwrap_or("").trim();
+                if line_name.to_lowercase() == name {
+                    existing = Some(i);
+                }
+            }
+        }
+
+        match (existing, section_end) {
+            (Some(i), _) => lines[i] = setting,
+            (None, Some(end)) => lines.insert(end, setting),
+            (None, None) => {
+                lines.push(section_header(section));
+                lines.push(setting);
+            }
+        }
+
+        let mut content = lines.join("\n");
+        content.push('\n');
+        lockfile.write(content.as_bytes())?;
+        lockfile.commit()?;
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalise_key(key: &str) -> String {
+    let first = key.find('.');
+    let last = key.rfind('.');
+    match (first, last) {
+        (Some(first), Some(last)) => format!(
+            "{}{}{}",
+            key[..first].to_lowercase(),
+            &key[first..last + 1],
+            key[last + 1..].to_lowercase()
+        ),
+        _ => key.to_lowercase(),
+    }
+}
+
+fn parse_section(header: &str) -> String {
+    match header.find(' ') {
+        Some(split) => {
+            let subsection = header[split + 1..].trim().trim_matches('"');
+            format!("{}.{}", header[..split].to_lowercase(), subsection)
+        }
+        // Legacy `[section.subsection]` syntax
+        None => match header.find('.') {
+            Some(split) => format!(
+                "{}.{}",
+                header[..split].to_lowercase(),
+                &header[split + 1..]
+            ),
+            None => header.to_lowercase(),
+        },
+    }
+}
+
+fn section_header(section: &str) -> String {
+    match section.find('.') {
+        Some(split) => format!("[{} \"{}\"]", &section[..split], &section[split + 1..]),
+        None => format!("[{}]", section),
+    }
+}
+
+fn quote_value(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    if escaped != value || value.starts_with(' ') || value.ends_with(' ') || value.contains('#') {
+        format!("\"{}\"", escaped)
+    } else {
+        value.to_string()
+    }
+}
+
+fn parse_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {}
+            '\\' => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => {}
+            },
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_quotes = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '#' | ';' if !in_quotes => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[derive(Debug)]
+struct InvalidKey(String);
+impl Error for InvalidKey {}
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "key does not contain a section: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::temp_dir::TempDir;
+    use super::*;
+
+    #[test]
+    fn it_names_the_lock_when_another_process_holds_it() {
+        let git_path = TempDir::new("config");
+        let path = git_path.join("config");
+
+        let mut lockfile = Lockfile::new(path.clone());
+        assert!(lockfile.hold_for_update().unwrap());
+        let error = Config::set(&path, "user.name", "A").unwrap_err();
+        assert_eq!(
+            format!("Unable to create '{}.lock': File exists.", path.display()),
+            error.to_string()
+        );
+
+        lockfile.rollback().unwrap();
+        Config::set(&path, "user.name", "A").unwrap();
+        assert_eq!(Some("A"), Config::load(&path).get("user.name"));
+    }
+}
@@ -3,13 +3,20 @@ pub mod blob;
 pub mod commit;
 pub mod tree;
 
+use blob::Blob;
+use commit::Commit;
+use tree::{DatabaseEntry, OidAndMode, Tree};
+
 use std::{
-    fs::{self, OpenOptions},
-    io::{ErrorKind, Write},
-    path::PathBuf,
+    collections::BTreeMap,
+    error::{self, Error},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
 };
 
-use flate2::{write::ZlibEncoder, Compression};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 use rand::{seq::SliceRandom, thread_rng};
 use sha1::{Digest, Sha1};
 
@@ -21,6 +28,12 @@ pub trait Storable {
     fn to_string(&self) -> Vec<u8>;
 }
 
+pub enum Object {
+    Blob(Blob),
+    Commit(Commit),
+    Tree(Tree),
+}
+
 pub struct Database {
     pathname: PathBuf,
 }
@@ -48,14 +61,151 @@ impl Database {
     }
 
     pub fn store<T: Storable>(&self, object: &mut T) {
-        let mut bytes = object.to_string();
-        let mut content = format!("{} {}\0", object.type_(), bytes.len()).into_bytes();
-        content.append(&mut bytes);
+        let content = serialize_object(object);
 
         object.set_oid(hash_bytes(&content));
         self.write_object(object.oid().expect("oid not set on object"), &content);
     }
 
+    // The oid an object would be stored under, without writing it
+    pub fn hash_object<T: Storable>(&self, object: &T) -> String {
+        hash_bytes(&serialize_object(object))
+    }
+
+    pub fn load(&self, oid: &str) -> Result<Object, Box<dyn error::Error>> {
+        let (type_, data) = self.read_object(oid)?;
+
+        match type_.as_str() {
+            "blob" => Ok(Object::Blob(Blob::parse(oid, data))),
+            "commit" => Ok(Object::Commit(Commit::parse(oid, &data)?)),
+            "tree" => Ok(Object::Tree(Tree::parse(oid, &data)?)),
+            _ => Err(UnknownType(type_).into()),
+        }
+    }
+
+    pub fn load_commit(&self, oid: &str) -> Result<Commit, Box<dyn error::Error>> {
+        match self.load(oid)? {
+            Object::Commit(commit) => Ok(commit),
+            _ => Err(WrongType(oid.to_string(), "commit").into()),
+        }
+    }
+
+    // Loads a tree by its own oid or by the oid of a commit pointing at it
+    pub fn load_tree(&self, oid: &str) -> Result<Tree, Box<dyn error::Error>> {
+        match self.load(oid)? {
+            Object::Tree(tree) => Ok(tree),
+            Object::Commit(commit) => self.load_tree(commit.tree()),
+            _ => Err(WrongType(oid.to_string(), "tree").into()),
+        }
+    }
+
+    // Finds the entry at `path` within a tree-ish, loading only the subtrees along that path
+    pub fn load_tree_entry(
+        &self,
+        oid: &str,
+        path: &Path,
+    ) -> Result<Option<DatabaseEntry>, Box<dyn error::Error>> {
+        let mut tree = self.load_tree(oid)?;
+        let mut entry = DatabaseEntry::new(OidAndMode::oid(&tree).to_string(), tree.mode());
+
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            entry = match tree.entry(component.as_os_str()) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            if components.peek().is_some() {
+                if !entry.is_tree() {
+                    return Ok(None);
+                }
+                tree = self.load_tree(entry.oid())?;
+            }
+        }
+
+        Ok(Some(entry))
+    }
+
+    // Flattens a tree-ish into the non-tree entries beneath `prefix`, keyed by full path
+    pub fn load_tree_list(
+        &self,
+        oid: &str,
+        prefix: &Path,
+    ) -> Result<BTreeMap<PathBuf, DatabaseEntry>, Box<dyn error::Error>> {
+        let mut list = BTreeMap::new();
+        let start = match self.load_tree_entry(oid, prefix)? {
+            Some(entry) => entry,
+            None => return Ok(list),
+        };
+        self.build_tree_list(start, prefix.to_path_buf(), &mut list)?;
+        Ok(list)
+    }
+
+    fn build_tree_list(
+        &self,
+        entry: DatabaseEntry,
+        path: PathBuf,
+        list: &mut BTreeMap<PathBuf, DatabaseEntry>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        if !entry.is_tree() {
+            list.insert(path, entry);
+            return Ok(());
+        }
+
+        let tree = self.load_tree(entry.oid())?;
+        for (name, child) in tree.entries() {
+            self.build_tree_list(child, path.join(name), list)?;
+        }
+        Ok(())
+    }
+
+    pub fn object_size(&self, oid: &str) -> Result<usize, Box<dyn error::Error>> {
+        Ok(self.read_object(oid)?.1.len())
+    }
+
+    pub fn prefix_match(&self, prefix: &str) -> Vec<String> {
+        if prefix.len() < 2 {
+            return Vec::new();
+        }
+        let dirname = self.pathname.join(&prefix[..2]);
+        let dir_entries = match fs::read_dir(dirname) {
+            Ok(dir_entries) => dir_entries,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut oids: Vec<String> = dir_entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| format!("{}{}", &prefix[..2], entry.file_name().to_string_lossy()))
+            .filter(|oid| oid.len() == 40 && oid.starts_with(prefix))
+            .collect();
+        oids.sort();
+        oids
+    }
+
+    fn read_object(&self, oid: &str) -> Result<(String, Vec<u8>), Box<dyn error::Error>> {
+        if oid.len() < 3 {
+            return Err(MalformedObject(oid.to_string()).into());
+        }
+        let object_path = self.pathname.join(&oid[..2]).join(&oid[2..]);
+
+        let mut content = Vec::new();
+        ZlibDecoder::new(File::open(object_path)?).read_to_end(&mut content)?;
+
+        let space = content.iter().position(|&b| b == b' ');
+        let null = content.iter().position(|&b| b == 0);
+        match (space, null) {
+            (Some(space), Some(null)) if space < null => {
+                let type_ = String::from_utf8(content[..space].to_vec())?;
+                let size: usize = String::from_utf8(content[space + 1..null].to_vec())?.parse()?;
+                let data = content.split_off(null + 1);
+                if data.len() != size {
+                    return Err(MalformedObject(oid.to_string()).into());
+                }
+                Ok((type_, data))
+            }
+            _ => Err(MalformedObject(oid.to_string()).into()),
+        }
+    }
+
     pub fn write_object(&self, oid: &str, content: &[u8]) {
         let object_path = self.pathname.join(&oid[..2]).join(&oid[2..]);
         if object_path.exists() {
@@ -89,9 +239,43 @@ impl Database {
     }
 }
 
+fn serialize_object<T: Storable>(object: &T) -> Vec<u8> {
+    let mut bytes = object.to_string();
+    let mut content = format!("{} {}\0", object.type_(), bytes.len()).into_bytes();
+    content.append(&mut bytes);
+    content
+}
+
 fn hash_bytes(bytes: &[u8]) -> String {
     let mut hasher = Sha1::new();
     hasher.update(bytes);
     let result = hasher.finalize();
     hex::encode(result)
 }
+
+#[derive(Debug)]
+pub struct MalformedObject(String);
+impl Error for MalformedObject {}
+impl fmt::Display for MalformedObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "object {} is corrupt", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownType(String);
+impl Error for UnknownType {}
+impl fmt::Display for UnknownType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown object type {}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct WrongType(String, &'static str);
+impl Error for WrongType {}
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "object {} is not a {}", self.0, self.1)
+    }
+}
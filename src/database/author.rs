@@ -1,17 +1,48 @@
-use std::fmt;
+use std::{
+    error::{self, Error},
+    fmt,
+};
 
-use chrono::{DateTime, Local};
+use chrono::{DateTime, FixedOffset};
 
+#[derive(Clone)]
 pub struct Author {
     name: String,
     email: String,
-    time: DateTime<Local>,
+    time: DateTime<FixedOffset>,
 }
 
 impl Author {
-    pub fn new(name: String, email: String, time: DateTime<Local>) -> Author {
+    pub fn new(name: String, email: String, time: DateTime<FixedOffset>) -> Author {
         Author { name, email, time }
     }
+
+    // Parses the "name <email> timestamp timezone" format written by `Display`
+    pub fn parse(string: &str) -> Result<Author, Box<dyn error::Error>> {
+        let email_start = string.find('<').ok_or(InvalidAuthor)?;
+        let email_end = string.rfind('>').ok_or(InvalidAuthor)?;
+        if email_end < email_start {
+            return Err(InvalidAuthor.into());
+        }
+
+        let name = string[..email_start].trim().to_string();
+        let email = string[email_start + 1..email_end].to_string();
+        let time = DateTime::parse_from_str(string[email_end + 1..].trim(), "%s %z")?;
+
+        Ok(Author { name, email, time })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn readable_time(&self) -> String {
+        format!("{}", self.time.format("%a %b %-d %H:%M:%S %Y %z"))
+    }
 }
 
 impl fmt::Display for Author {
@@ -20,3 +51,35 @@ impl fmt::Display for Author {
         write!(f, "{} <{}> {}", self.name, self.email, timestamp)
     }
 }
+
+#[derive(Debug)]
+struct InvalidAuthor;
+impl Error for InvalidAuthor {}
+impl fmt::Display for InvalidAuthor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid author line")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_what_it_writes() {
+        let line = "Alice Smith <alice@example.com> 1577880000 +0100";
+
+        let author = Author::parse(line).unwrap();
+
+        assert_eq!("Alice Smith", author.name());
+        assert_eq!("alice@example.com", author.email());
+        assert_eq!("Wed Jan 1 13:00:00 2020 +0100", author.readable_time());
+        assert_eq!(line, author.to_string());
+    }
+
+    #[test]
+    fn it_rejects_lines_without_an_email() {
+        assert!(Author::parse("Alice Smith 1577880000 +0100").is_err());
+        assert!(Author::parse("Alice >alice@example.com< 1577880000 +0100").is_err());
+    }
+}
@@ -2,13 +2,24 @@ use super::Storable;
 
 pub struct Blob {
     oid: Option<String>,
-    data: String,
+    data: Vec<u8>,
 }
 
 impl Blob {
-    pub fn new(data: String) -> Blob {
+    pub fn new(data: Vec<u8>) -> Blob {
         Blob { oid: None, data }
     }
+
+    pub fn parse(oid: &str, data: Vec<u8>) -> Blob {
+        Blob {
+            oid: Some(oid.to_string()),
+            data,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
 }
 
 impl Storable for Blob {
@@ -25,6 +36,6 @@ impl Storable for Blob {
     }
 
     fn to_string(&self) -> Vec<u8> {
-        self.data.as_bytes().to_vec()
+        self.data.clone()
     }
 }
@@ -1,23 +1,189 @@
-use super::{author::Author, Storable};
+use std::{
+    error::{self, Error},
+    fmt, str,
+};
+
+use super::{
+    super::signing::{Signer, Unsigned, Verification},
+    author::Author,
+    Storable,
+};
 
 pub struct Commit {
     oid: Option<String>,
-    parent: Option<String>,
+    parents: Vec<String>,
     tree: String,
     author: Author,
+    committer: Author,
+    signature: Option<String>,
     message: String,
+    // A commit that was read keeps its bytes apart from any signature, as they're what was signed,
+    // including headers jit doesn't interpret such as `encoding` or `mergetag`
+    payload: Option<Vec<u8>>,
 }
 
 impl Commit {
+    // Headers holding signatures, which are left out of what gets signed
+    const SIGNATURE_HEADERS: [&'static [u8]; 2] = [b"gpgsig", b"gpgsig-sha256"];
+
     pub fn new(parent: Option<String>, tree: String, author: Author, message: &str) -> Commit {
         Commit {
             oid: None,
-            parent,
+            parents: parent.into_iter().collect(),
             tree,
+            committer: author.clone(),
             author,
+            signature: None,
             message: message.to_string(),
+            payload: None,
+        }
+    }
+
+    // The message, and the author and committer names, may be in another encoding than UTF-8, as
+    // set by an `encoding` header. They're decoded lossily for display, while the payload keeps the
+    // original bytes.
+    pub fn parse(oid: &str, data: &[u8]) -> Result<Commit, Box<dyn error::Error>> {
+        let (headers, message) = match find(data, b"\n\n") {
+            Some(split) => (&data[..split + 1], &data[split + 2..]),
+            None => (data, &b""[..]),
+        };
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut signature = None;
+        for (key, value) in header_fields(headers) {
+            match key {
+                b"tree" => tree = Some(str::from_utf8(&value)?.to_string()),
+                b"parent" => parents.push(str::from_utf8(&value)?.to_string()),
+                b"author" => author = Some(Author::parse(&String::from_utf8_lossy(&value))?),
+                b"committer" => committer = Some(Author::parse(&String::from_utf8_lossy(&value))?),
+                b"gpgsig" => signature = Some(format!("{}\n", String::from_utf8_lossy(&value))),
+                _ => {}
+            }
+        }
+
+        let author = author.ok_or(InvalidCommit)?;
+        Ok(Commit {
+            oid: Some(oid.to_string()),
+            parents,
+            tree: tree.ok_or(InvalidCommit)?,
+            committer: committer.unwrap_or_else(|| author.clone()),
+            author,
+            signature,
+            message: String::from_utf8_lossy(message).into_owned(),
+            payload: Some(strip_signatures(headers, message)),
+        })
+    }
+
+    // The first parent, which is the commit this one was made on top of
+    pub fn parent(&self) -> Option<&str> {
+        self.parents.first().map(String::as_str)
+    }
+
+    // Every parent, in order, which a merge has more than one of
+    pub fn parents(&self) -> &[String] {
+        &self.parents
+    }
+
+    pub fn tree(&self) -> &str {
+        &self.tree
+    }
+
+    pub fn author(&self) -> &Author {
+        &self.author
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn signature(&self) -> Option<&str> {
+        self.signature.as_deref()
+    }
+
+    pub fn sign(&mut self, signer: &dyn Signer) -> Result<(), Box<dyn error::Error>> {
+        self.signature = Some(signer.sign(&self.payload())?);
+        Ok(())
+    }
+
+    pub fn verify(&self, signer: &dyn Signer) -> Result<Verification, Box<dyn error::Error>> {
+        match &self.signature {
+            Some(signature) => signer.verify(&self.payload(), signature),
+            None => Err(Unsigned.into()),
+        }
+    }
+
+    // The content that gets signed, i.e. the serialized commit without its signature headers
+    pub fn payload(&self) -> Vec<u8> {
+        match &self.payload {
+            Some(payload) => payload.clone(),
+            None => self.serialize(),
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut lines = Vec::new();
+
+        lines.push(format!("tree {}", self.tree));
+        for parent in &self.parents {
+            lines.push(format!("parent {}", parent));
+        }
+        lines.push(format!("author {}", self.author));
+        lines.push(format!("committer {}", self.committer));
+        lines.push(String::from(""));
+        lines.push(self.message.clone());
+
+        lines.join("\n").into_bytes()
+    }
+}
+
+// Splits headers into keys and values, where values may span several lines, each continuation
+// line being prefixed by a space
+fn header_fields(headers: &[u8]) -> Vec<(&[u8], Vec<u8>)> {
+    let mut fields: Vec<(&[u8], Vec<u8>)> = Vec::new();
+    for line in headers
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.is_empty())
+    {
+        if let Some(continuation) = line.strip_prefix(b" ") {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(b'\n');
+                value.extend(continuation);
+            }
+            continue;
+        }
+        match line.iter().position(|&b| b == b' ') {
+            Some(split) => fields.push((&line[..split], line[split + 1..].to_vec())),
+            None => fields.push((line, Vec::new())),
         }
     }
+    fields
+}
+
+// Rebuilds a commit's bytes without its signature headers and their continuation lines, leaving
+// everything else exactly as it was written
+fn strip_signatures(headers: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    let mut in_signature = false;
+    for line in headers.split_inclusive(|&b| b == b'\n') {
+        if !line.starts_with(b" ") {
+            let key = line.split(|&b| b == b' ').next().unwrap_or(line);
+            in_signature = Commit::SIGNATURE_HEADERS.contains(&key);
+        }
+        if !in_signature {
+            payload.extend(line);
+        }
+    }
+    payload.push(b'\n');
+    payload.extend(message);
+    payload
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len())
+        .position(|window| window == needle)
 }
 
 impl Storable for Commit {
@@ -33,19 +199,85 @@ impl Storable for Commit {
         "commit"
     }
 
+    // The signature goes at the end of the headers, as git puts it
     fn to_string(&self) -> Vec<u8> {
-        let mut lines = Vec::new();
-
-        lines.push(format!("tree {}", self.tree));
-        match &self.parent {
-            Some(parent) => lines.push(format!("parent {}", parent)),
-            None => {}
+        let mut data = self.payload();
+        if let Some(signature) = &self.signature {
+            let header = format!(
+                "gpgsig {}\n",
+                signature.trim_end_matches('\n').replace('\n', "\n ")
+            );
+            let end = find(&data, b"\n\n").map_or(data.len(), |split| split + 1);
+            data.splice(end..end, header.into_bytes());
         }
-        lines.push(format!("author {}", self.author));
-        lines.push(format!("committer {}", self.author));
-        lines.push(String::from(""));
-        lines.push(self.message.clone());
+        data
+    }
+}
 
-        lines.join("\n").into_bytes()
+#[derive(Debug)]
+struct InvalidCommit;
+impl Error for InvalidCommit {}
+impl fmt::Display for InvalidCommit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "commit is missing a tree or author")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_what_it_writes() {
+        let author = Author::parse("Alice <alice@example.com> 1577880000 +0000").unwrap();
+        let commit = Commit::new(
+            Some("1".repeat(40)),
+            "2".repeat(40),
+            author,
+            "Subject\n\nBody\n",
+        );
+
+        let parsed = Commit::parse(&"3".repeat(40), &commit.to_string()).unwrap();
+
+        assert_eq!(Some("1".repeat(40).as_str()), parsed.parent());
+        assert_eq!("2".repeat(40), parsed.tree());
+        assert_eq!("alice@example.com", parsed.author().email());
+        assert_eq!("Subject\n\nBody\n", parsed.message());
+        assert_eq!(commit.to_string(), parsed.to_string());
+    }
+
+    #[test]
+    fn it_requires_a_tree_and_author() {
+        let data = b"parent 1111111111111111111111111111111111111111\n\nmessage\n";
+
+        assert!(Commit::parse(&"3".repeat(40), data).is_err());
+    }
+
+    #[test]
+    fn it_keeps_every_parent_and_header_of_a_signed_merge() {
+        let headers = format!(
+            "tree {}\nparent {}\nparent {}\nauthor A <a@b> 1577880000 +0000\n\
+             committer A <a@b> 1577880000 +0000\nencoding ISO-8859-1\n\
+             mergetag object {}\n type commit\n tag v1\n",
+            "1".repeat(40),
+            "2".repeat(40),
+            "3".repeat(40),
+            "3".repeat(40),
+        );
+        let signature = "gpgsig -----BEGIN SIGNATURE-----\n abc\n -----END SIGNATURE-----\n";
+        let message = b"\nMerge caf\xe9\n";
+        let unsigned = [headers.as_bytes(), message].concat();
+        let signed = [headers.as_bytes(), signature.as_bytes(), message].concat();
+
+        let commit = Commit::parse(&"4".repeat(40), &signed).unwrap();
+
+        assert_eq!(vec!["2".repeat(40), "3".repeat(40)], commit.parents());
+        assert_eq!(Some("2".repeat(40).as_str()), commit.parent());
+        assert_eq!(
+            Some("-----BEGIN SIGNATURE-----\nabc\n-----END SIGNATURE-----\n"),
+            commit.signature()
+        );
+        assert_eq!(unsigned, commit.payload());
+        assert_eq!(signed, commit.to_string());
     }
 }
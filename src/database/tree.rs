@@ -1,16 +1,23 @@
 use std::{
-    ffi::OsString,
+    cmp,
+    error::{self, Error},
+    ffi::{OsStr, OsString},
+    fmt,
     os::unix::ffi::OsStrExt,
     path::{Components, Path},
+    str,
 };
 
 use indexmap::IndexMap;
 
-use super::{super::index::entry::Entry, Storable};
+use super::{
+    super::index::{cache_tree::CacheTree, entry::Entry},
+    Storable,
+};
 
 pub trait TreeEntry {
     fn name(&self) -> &Path;
-    fn parent_directories(&self) -> Components;
+    fn parent_directories(&self) -> Components<'_>;
     fn basename(&self) -> OsString;
 }
 
@@ -24,9 +31,46 @@ pub trait OidAndMode {
     fn mode(&self) -> u32;
 }
 
+// An entry read back from a stored tree, referring to a blob or subtree only by its oid so that
+// subtrees can be loaded from the database as they're needed
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseEntry {
+    oid: String,
+    mode: u32,
+}
+
+impl DatabaseEntry {
+    pub fn new(oid: String, mode: u32) -> DatabaseEntry {
+        DatabaseEntry { oid, mode }
+    }
+
+    pub fn is_tree(&self) -> bool {
+        self.mode == Entry::TREE_MODE
+    }
+
+    pub fn type_(&self) -> &str {
+        match self.mode {
+            Entry::TREE_MODE => "tree",
+            Entry::GITLINK_MODE => "commit",
+            _ => "blob",
+        }
+    }
+}
+
+impl OidAndMode for DatabaseEntry {
+    fn oid(&self) -> &str {
+        &self.oid
+    }
+
+    fn mode(&self) -> u32 {
+        self.mode
+    }
+}
+
 enum TreeNode {
     Tree(Tree),
     Entry(Entry),
+    Stored(DatabaseEntry),
 }
 
 impl OidAndMode for TreeNode {
@@ -34,6 +78,7 @@ impl OidAndMode for TreeNode {
         match self {
             TreeNode::Tree(tree) => <Tree as OidAndMode>::oid(tree),
             TreeNode::Entry(entry) => entry.oid(),
+            TreeNode::Stored(entry) => entry.oid(),
         }
     }
 
@@ -41,6 +86,7 @@ impl OidAndMode for TreeNode {
         match self {
             TreeNode::Tree(tree) => tree.mode(),
             TreeNode::Entry(entry) => entry.mode(),
+            TreeNode::Stored(entry) => entry.mode(),
         }
     }
 }
@@ -87,11 +133,167 @@ impl Tree {
             .or_insert(TreeNode::Tree(Tree::new()));
         let tree = match tree_node {
             TreeNode::Tree(tree) => tree,
-            TreeNode::Entry(_) => panic!("Found entry with children"),
+            TreeNode::Entry(_) | TreeNode::Stored(_) => panic!("Found entry with children"),
         };
         tree.add_entry(parents, &entry)
     }
 
+    // Like `build`, but subtrees still recorded in `cache` are taken from there by their oid, and
+    // the entries they were built from are skipped using the cached entry counts
+    pub fn build_cached<'a, I>(sorted_entries: I, cache: &CacheTree) -> Tree
+    where
+        I: IntoIterator<Item = &'a Entry>,
+    {
+        let mut root = Tree::new();
+
+        let mut entries = sorted_entries.into_iter();
+        while let Some(entry) = entries.next() {
+            let covered = root.add_cached_entry(entry.parent_directories(), entry, Some(cache));
+            if covered > 1 {
+                entries.nth(covered - 2);
+            }
+        }
+
+        root
+    }
+
+    // Returns how many entries were covered, which is more than one if `entry` was found to be in
+    // a cached subtree
+    fn add_cached_entry(
+        &mut self,
+        mut parents: Components,
+        entry: &Entry,
+        cache: Option<&CacheTree>,
+    ) -> usize {
+        let base_component = match parents.next() {
+            None => {
+                self.entries
+                    .insert(entry.basename(), TreeNode::Entry(entry.to_owned()));
+                return 1;
+            }
+            Some(component) => component.as_os_str().to_owned(),
+        };
+
+        let cache = cache.and_then(|cache| cache.subtree(&base_component));
+        if let Some(oid) = cache.and_then(CacheTree::oid) {
+            let stored = DatabaseEntry::new(oid.to_string(), Entry::TREE_MODE);
+            self.entries
+                .insert(base_component, TreeNode::Stored(stored));
+            return cmp::max(cache.map_or(1, CacheTree::entry_count), 1) as usize;
+        }
+
+        let tree_node = self
+            .entries
+            .entry(base_component)
+            .or_insert(TreeNode::Tree(Tree::new()));
+        let tree = match tree_node {
+            TreeNode::Tree(tree) => tree,
+            TreeNode::Entry(_) | TreeNode::Stored(_) => panic!("Found entry with children"),
+        };
+        tree.add_cached_entry(parents, entry, cache)
+    }
+
+    // Like `traverse`, but skips any subtree whose oid is still recorded in `cache`, which is then
+    // updated with the oids of the trees that did need storing
+    pub fn traverse_cached<F>(&mut self, cache: &mut CacheTree, f: &mut F)
+    where
+        F: FnMut(&mut Tree),
+    {
+        let entry_count = self.entry_count(Some(cache));
+        if let Some(oid) = cache.oid() {
+            if cache.entry_count() == entry_count as i32 {
+                self.oid = Some(oid.to_string());
+                return;
+            }
+        }
+
+        for (name, entry) in self.entries.iter_mut() {
+            if let TreeNode::Tree(tree) = entry {
+                tree.traverse_cached(cache.subtree_mut(name), f);
+            }
+        }
+        let entries = &self.entries;
+        cache.retain_subtrees(|name| match entries.get(name) {
+            Some(TreeNode::Tree(_)) => true,
+            Some(TreeNode::Stored(entry)) => entry.is_tree(),
+            _ => false,
+        });
+
+        f(self);
+        cache.set(self.oid.as_ref().expect("Tree not stored"), entry_count);
+    }
+
+    // The number of index entries this tree was built from, including those in subtrees, which
+    // for subtrees taken from the cache is what the cache recorded
+    fn entry_count(&self, cache: Option<&CacheTree>) -> usize {
+        self.entries
+            .iter()
+            .map(|(name, entry)| {
+                let cache = cache.and_then(|cache| cache.subtree(name));
+                match entry {
+                    TreeNode::Tree(tree) => tree.entry_count(cache),
+                    TreeNode::Stored(entry) if entry.is_tree() => {
+                        cache.map_or(0, |cache| cmp::max(cache.entry_count(), 0) as usize)
+                    }
+                    _ => 1,
+                }
+            })
+            .sum()
+    }
+
+    // Parses the `mode name\0oid` records of a stored tree object
+    pub fn parse(oid: &str, data: &[u8]) -> Result<Tree, Box<dyn error::Error>> {
+        const OID_SIZE: usize = 20;
+
+        let mut entries = IndexMap::new();
+        let mut rest = data;
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&b| b == b' ').ok_or(InvalidTree)?;
+            let null = rest.iter().position(|&b| b == 0).ok_or(InvalidTree)?;
+            if null < space || rest.len() < null + 1 + OID_SIZE {
+                return Err(InvalidTree.into());
+            }
+
+            let mode = u32::from_str_radix(str::from_utf8(&rest[..space])?, 8)?;
+            let name = OsStr::from_bytes(&rest[space + 1..null]).to_os_string();
+            let entry_oid = hex::encode(&rest[null + 1..null + 1 + OID_SIZE]);
+
+            entries.insert(name, TreeNode::Stored(DatabaseEntry::new(entry_oid, mode)));
+            rest = &rest[null + 1 + OID_SIZE..];
+        }
+
+        Ok(Tree {
+            oid: Some(oid.to_string()),
+            entries,
+        })
+    }
+
+    // Note that trees built from the index must have been stored before listing their entries
+    pub fn entries(&self) -> impl Iterator<Item = (&OsStr, DatabaseEntry)> {
+        self.sorted_entries().into_iter().map(|(name, node)| {
+            (
+                name.as_os_str(),
+                DatabaseEntry::new(node.oid().to_string(), node.mode()),
+            )
+        })
+    }
+
+    // Git orders tree entries by name as if every subtree's name ended in a '/', so e.g.
+    // `foo-bar.txt` comes before `foo/` but `foo/` comes before `foo0`
+    fn sorted_entries(&self) -> Vec<(&OsString, &TreeNode)> {
+        let mut entries: Vec<(&OsString, &TreeNode)> = self.entries.iter().collect();
+        entries.sort_by(|(a_name, a_node), (b_name, b_node)| {
+            base_name_key(a_name, a_node.mode()).cmp(base_name_key(b_name, b_node.mode()))
+        });
+        entries
+    }
+
+    pub fn entry(&self, name: &OsStr) -> Option<DatabaseEntry> {
+        self.entries
+            .get(name)
+            .map(|node| DatabaseEntry::new(node.oid().to_string(), node.mode()))
+    }
+
     pub fn traverse<F>(&mut self, mut f: F)
     where
         F: FnMut(&mut Tree) + Clone,
@@ -137,14 +339,12 @@ impl Storable for Tree {
     }
 
     fn to_string(&self) -> Vec<u8> {
-        // Note entries sorted on creation of tree
         let entries_vec = self
-            .entries
-            .keys()
-            .map(|name| {
-                let entry = self.entries.get(name).unwrap();
+            .sorted_entries()
+            .into_iter()
+            .map(|(name, entry)| {
                 let mut oid_bytes =
-                    hex::decode(&entry.oid()).expect("Failed to convert oid to bytes");
+                    hex::decode(entry.oid()).expect("Failed to convert oid to bytes");
                 let mut s = format!("{:o} ", entry.mode()).into_bytes();
                 s.extend(name.as_bytes());
                 s.extend(vec![0]);
@@ -155,3 +355,159 @@ impl Storable for Tree {
         entries_vec.concat()
     }
 }
+
+fn base_name_key(name: &OsStr, mode: u32) -> impl Iterator<Item = &u8> {
+    let suffix: &[u8] = if mode == Entry::TREE_MODE { b"/" } else { b"" };
+    name.as_bytes().iter().chain(suffix)
+}
+
+#[derive(Debug)]
+struct InvalidTree;
+impl Error for InvalidTree {}
+impl fmt::Display for InvalidTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "malformed tree object")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::Metadata, path::PathBuf};
+
+    use super::*;
+
+    fn stat() -> Metadata {
+        PathBuf::from(file!())
+            .metadata()
+            .expect("Failed to get metadata for path")
+    }
+
+    fn entry(path: &str, oid: &str) -> Entry {
+        Entry::new(PathBuf::from(path), oid, stat())
+    }
+
+    #[test]
+    fn it_parses_a_serialized_tree() {
+        let entries = vec![
+            entry("alice.txt", &"1".repeat(40)),
+            entry("nested/bob.txt", &"2".repeat(40)),
+        ];
+        let mut root = Tree::build(&entries);
+        root.traverse(|tree| tree.set_oid("3".repeat(40)));
+
+        let parsed = Tree::parse(&"4".repeat(40), &root.to_string()).unwrap();
+
+        assert_eq!(
+            vec![
+                (
+                    OsStr::new("alice.txt"),
+                    DatabaseEntry::new("1".repeat(40), Entry::REGULAR_MODE)
+                ),
+                (
+                    OsStr::new("nested"),
+                    DatabaseEntry::new("3".repeat(40), Entry::TREE_MODE)
+                ),
+            ],
+            parsed.entries().collect::<Vec<_>>()
+        );
+        assert_eq!(root.to_string(), parsed.to_string());
+    }
+
+    #[test]
+    fn it_keeps_non_utf8_names_as_raw_bytes() {
+        let name = OsStr::from_bytes(b"caf\xe9/na\xefve.txt");
+        let entries = vec![Entry::new(PathBuf::from(name), &"1".repeat(40), stat())];
+        let mut root = Tree::build(&entries);
+        root.traverse(|tree| tree.set_oid("2".repeat(40)));
+
+        let parsed = Tree::parse(&"3".repeat(40), &root.to_string()).unwrap();
+
+        assert_eq!(
+            vec![OsStr::from_bytes(b"caf\xe9")],
+            parsed.entries().map(|(name, _)| name).collect::<Vec<_>>()
+        );
+        assert_eq!(root.to_string(), parsed.to_string());
+    }
+
+    #[test]
+    fn it_sorts_subtrees_as_if_they_had_a_trailing_slash() {
+        let entries = vec![
+            entry("foo/bar.txt", &"1".repeat(40)),
+            entry("foo-bar.txt", &"2".repeat(40)),
+            entry("foo0", &"3".repeat(40)),
+        ];
+        let mut root = Tree::build(&entries);
+        root.traverse(|tree| tree.set_oid("4".repeat(40)));
+
+        assert_eq!(
+            vec!["foo-bar.txt", "foo", "foo0"],
+            root.entries()
+                .map(|(name, _)| name.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    // Stores trees by giving each a fresh oid, recording which were stored
+    fn store_all(root: &mut Tree, cache: &mut CacheTree) -> Vec<String> {
+        let mut stored = Vec::new();
+        root.traverse_cached(cache, &mut |tree| {
+            stored.push(format!("{:040}", stored.len() + 1));
+            tree.set_oid(stored.last().unwrap().clone());
+        });
+        stored
+    }
+
+    #[test]
+    fn it_reuses_cached_subtrees_rather_than_storing_them_again() {
+        let entries = vec![
+            entry("docs/guide.txt", &"1".repeat(40)),
+            entry("lib/a.txt", &"2".repeat(40)),
+            entry("lib/nested/b.txt", &"3".repeat(40)),
+            entry("readme.txt", &"4".repeat(40)),
+        ];
+        let mut cache = CacheTree::new();
+        let mut root = Tree::build_cached(&entries, &cache);
+        assert_eq!(4, store_all(&mut root, &mut cache).len());
+        let docs = root.entry(OsStr::new("docs")).unwrap();
+
+        let mut root = Tree::build_cached(&entries, &cache);
+        assert!(store_all(&mut root, &mut cache).is_empty());
+        assert_eq!(Some(format!("{:040}", 4).as_str()), Storable::oid(&root));
+
+        cache.invalidate(Path::new("lib/a.txt"));
+        let mut root = Tree::build_cached(&entries, &cache);
+        assert_eq!(2, store_all(&mut root, &mut cache).len());
+        assert_eq!(Some(docs), root.entry(OsStr::new("docs")));
+        assert_eq!(4, cache.entry_count());
+        assert_eq!(2, cache.subtree(OsStr::new("lib")).unwrap().entry_count());
+    }
+
+    #[test]
+    fn it_skips_the_entries_of_cached_subtrees() {
+        let entries = vec![
+            entry("lib/a.txt", &"1".repeat(40)),
+            entry("lib/b.txt", &"2".repeat(40)),
+            entry("z.txt", &"3".repeat(40)),
+        ];
+        let mut cache = CacheTree::new();
+        cache.subtree_mut(OsStr::new("lib")).set(&"4".repeat(40), 2);
+
+        let mut root = Tree::build_cached(&entries, &cache);
+        store_all(&mut root, &mut cache);
+
+        assert_eq!(
+            vec![
+                (
+                    OsStr::new("lib"),
+                    DatabaseEntry::new("4".repeat(40), Entry::TREE_MODE)
+                ),
+                (
+                    OsStr::new("z.txt"),
+                    DatabaseEntry::new("3".repeat(40), Entry::REGULAR_MODE)
+                ),
+            ],
+            root.entries().collect::<Vec<_>>()
+        );
+        assert_eq!(3, cache.entry_count());
+    }
+}
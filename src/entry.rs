@@ -32,7 +32,7 @@ impl TreeEntry for Entry {
         &self.name
     }
 
-    fn parent_directories(&self) -> Components {
+    fn parent_directories(&self) -> Components<'_> {
         match self.name.parent() {
             Some(parent) => parent.components(),
             None => Path::new("").components(),
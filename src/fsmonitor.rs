@@ -0,0 +1,569 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::{CString, OsStr, OsString},
+    fmt, fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::Shutdown,
+    os::unix::{
+        ffi::OsStrExt,
+        io::RawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::workspace::Workspace;
+
+// A daemon watching the workspace with inotify, so that commands can ask which paths have
+// changed since an earlier point rather than checking every file themselves
+//
+// Each answer comes with a token naming the point it was given at, to ask from next time. Tokens
+// from another run of the daemon, or from before changes had to be forgotten, get an answer of
+// everything having changed.
+const SOCKET_NAME: &str = "jit-fsmonitor.ipc";
+const COOKIE_PREFIX: &str = "jit-fsmonitor-cookie-";
+const MAX_CHANGES: usize = 100_000;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// The daemon's answer to which paths have changed since a token, where a changed directory
+// stands for everything under it
+pub struct Changes {
+    pub token: String,
+    paths: Option<HashSet<PathBuf>>,
+}
+
+impl Changes {
+    pub fn includes(&self, path: &Path) -> bool {
+        match &self.paths {
+            Some(paths) => path.ancestors().any(|parent| paths.contains(parent)),
+            None => true,
+        }
+    }
+}
+
+pub fn query(git_path: &Path, token: &str) -> Result<Changes, Box<dyn Error>> {
+    let response = request(git_path, &format!("query {}", token))?;
+    let mut fields = response.split(|&b| b == 0);
+
+    let token = String::from_utf8(fields.next().unwrap_or_default().to_vec())?;
+    if token.is_empty() {
+        return Err(NoResponse.into());
+    }
+    let paths: Vec<&[u8]> = fields.filter(|path| !path.is_empty()).collect();
+    let paths = if paths.first() == Some(&&b"/"[..]) {
+        None
+    } else {
+        Some(
+            paths
+                .into_iter()
+                .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+                .collect(),
+        )
+    };
+
+    Ok(Changes { token, paths })
+}
+
+pub fn is_running(git_path: &Path) -> bool {
+    UnixStream::connect(git_path.join(SOCKET_NAME)).is_ok()
+}
+
+pub fn stop(git_path: &Path) -> Result<(), Box<dyn Error>> {
+    request(git_path, "stop")?;
+    Ok(())
+}
+
+// Runs the daemon in the background, returning once it's ready for queries
+pub fn start(root_path: &Path) -> Result<(), Box<dyn Error>> {
+    let git_path = root_path.join(".git");
+    if is_running(&git_path) {
+        return Err(AlreadyRunning.into());
+    }
+
+    process::Command::new(std::env::current_exe()?)
+        .args(["fsmonitor", "run"])
+        .current_dir(root_path)
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .spawn()?;
+
+    let started = SystemTime::now();
+    while !is_running(&git_path) {
+        if started.elapsed().unwrap_or_default() > TIMEOUT {
+            return Err(NotStarted.into());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+fn request(git_path: &Path, command: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut stream = UnixStream::connect(git_path.join(SOCKET_NAME))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    Ok(response)
+}
+
+// Watches the workspace and answers queries until asked to stop. Every directory is watched
+// before the socket is opened, so no query can be answered from an incomplete picture.
+pub fn run(root_path: &Path) -> Result<(), Box<dyn Error>> {
+    let git_path = root_path.join(".git");
+    let socket_path = git_path.join(SOCKET_NAME);
+    if is_running(&git_path) {
+        return Err(AlreadyRunning.into());
+    }
+
+    let inotify = Inotify::new()?;
+    let mut watcher = Watcher {
+        root_path: root_path.to_path_buf(),
+        inotify,
+        directories: HashMap::new(),
+        git_watch: -1,
+    };
+    watcher.watch_tree(Path::new(""))?;
+    watcher.git_watch = watcher.inotify.add_watch(&git_path, libc::IN_CREATE)?;
+
+    let state = Arc::new((Mutex::new(History::new()), Condvar::new()));
+    let watcher_state = Arc::clone(&state);
+    thread::spawn(move || watcher.run(&watcher_state));
+
+    // A socket left behind by a daemon that didn't stop cleanly is in the way
+    match fs::remove_file(&socket_path) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+        _ => {}
+    }
+    let listener = UnixListener::bind(&socket_path)?;
+
+    // A client that misbehaves only loses its own connection, so one that never finishes its
+    // request or sends garbage can't stop the daemon
+    let mut cookies = 0;
+    for stream in listener.incoming() {
+        let served = stream
+            .map_err(Into::into)
+            .and_then(|stream| serve(&git_path, &state, stream, &mut cookies));
+        match served {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(error) => eprintln!("warning: fsmonitor: could not serve a client: {}", error),
+        }
+    }
+
+    fs::remove_file(&socket_path)?;
+    Ok(())
+}
+
+// Handles one client's request, returning whether it asked the daemon to stop
+fn serve(
+    git_path: &Path,
+    state: &(Mutex<History>, Condvar),
+    mut stream: UnixStream,
+    cookies: &mut u64,
+) -> Result<bool, Box<dyn Error>> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+
+    // Only the newline is trimmed, since a first query has an empty token
+    let command = command.trim_end_matches('\n');
+    match command.split_once(' ') {
+        Some(("query", token)) => {
+            *cookies += 1;
+            let response = answer(git_path, state, token, *cookies)?;
+            // The client may have given up waiting, which is no reason to complain
+            let _ = stream.write_all(&response);
+            Ok(false)
+        }
+        None if command == "stop" => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+// Answers a query once every change made before it has been seen. Writing a cookie file and
+// waiting for its event to come through inotify makes sure of that, since events are queued in
+// order.
+fn answer(
+    git_path: &Path,
+    state: &(Mutex<History>, Condvar),
+    token: &str,
+    cookie_number: u64,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let cookie = OsString::from(format!(
+        "{}{}-{}",
+        COOKIE_PREFIX,
+        process::id(),
+        cookie_number
+    ));
+    let cookie_path = git_path.join(&cookie);
+    fs::write(&cookie_path, b"")?;
+
+    let (lock, seen) = state;
+    let history = lock.lock().unwrap();
+    let (mut history, timeout) = seen
+        .wait_timeout_while(history, TIMEOUT, |history| {
+            !history.cookies.contains(&cookie)
+        })
+        .unwrap();
+    history.cookies.remove(&cookie);
+    fs::remove_file(&cookie_path)?;
+
+    let changes = if timeout.timed_out() {
+        None
+    } else {
+        history.since(token)
+    };
+
+    let mut response = history.token().into_bytes();
+    response.push(0);
+    match changes {
+        Some(paths) => {
+            for path in paths {
+                response.extend(path.as_os_str().as_bytes());
+                response.push(0);
+            }
+        }
+        None => response.extend(b"/\0"),
+    }
+    Ok(response)
+}
+
+// Every path changed while the daemon has been running, numbered in the order they changed
+struct History {
+    instance: String,
+    sequence: u64,
+    // Changes before this point have been forgotten
+    oldest: u64,
+    changes: Vec<(u64, PathBuf)>,
+    cookies: HashSet<OsString>,
+}
+
+impl History {
+    fn new() -> History {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or(0);
+        History {
+            instance: format!("{}.{}", process::id(), started),
+            sequence: 0,
+            oldest: 0,
+            changes: Vec::new(),
+            cookies: HashSet::new(),
+        }
+    }
+
+    fn token(&self) -> String {
+        format!("jit:{}:{}", self.instance, self.sequence)
+    }
+
+    fn record(&mut self, path: PathBuf) {
+        if self.changes.len() >= MAX_CHANGES {
+            self.forget();
+        }
+        self.sequence += 1;
+        self.changes.push((self.sequence, path));
+    }
+
+    // Drops every change recorded so far, after which older tokens can only be told that
+    // everything has changed. The sequence moves on too, since changes may have been missed
+    // since the current token was given out.
+    fn forget(&mut self) {
+        self.changes.clear();
+        self.sequence += 1;
+        self.oldest = self.sequence;
+    }
+
+    fn since(&self, token: &str) -> Option<HashSet<&Path>> {
+        let (instance, sequence) = token.strip_prefix("jit:")?.rsplit_once(':')?;
+        let sequence: u64 = sequence.parse().ok()?;
+        if instance != self.instance || sequence < self.oldest {
+            return None;
+        }
+
+        Some(
+            self.changes
+                .iter()
+                .filter(|(changed, _)| *changed > sequence)
+                .map(|(_, path)| path.as_path())
+                .collect(),
+        )
+    }
+}
+
+struct Watcher {
+    root_path: PathBuf,
+    inotify: Inotify,
+    // The directory each watch is on, relative to the root of the workspace
+    directories: HashMap<i32, PathBuf>,
+    git_watch: i32,
+}
+
+impl Watcher {
+    const MASK: u32 = libc::IN_ATTRIB
+        | libc::IN_CLOSE_WRITE
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_DELETE_SELF
+        | libc::IN_MODIFY
+        | libc::IN_MOVE_SELF
+        | libc::IN_MOVED_FROM
+        | libc::IN_MOVED_TO
+        | libc::IN_DONT_FOLLOW
+        | libc::IN_ONLYDIR;
+
+    fn watch_tree(&mut self, directory: &Path) -> io::Result<()> {
+        let wd = self
+            .inotify
+            .add_watch(&self.root_path.join(directory), Watcher::MASK)?;
+        self.directories.insert(wd, directory.to_path_buf());
+
+        for entry in fs::read_dir(self.root_path.join(directory))? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && !is_ignored(&entry.file_name()) {
+                self.watch_tree(&directory.join(entry.file_name()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn run(mut self, state: &(Mutex<History>, Condvar)) {
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => {
+                    eprintln!("fatal: could not read filesystem events: {}", error);
+                    process::exit(128);
+                }
+            };
+
+            let (lock, seen) = state;
+            let mut history = lock.lock().unwrap();
+            for event in events {
+                self.handle(&mut history, event);
+            }
+            seen.notify_all();
+        }
+    }
+
+    fn handle(&mut self, history: &mut History, event: Event) {
+        if event.mask & libc::IN_Q_OVERFLOW != 0 {
+            history.forget();
+            return;
+        }
+        if event.wd == self.git_watch {
+            if event.name.as_bytes().starts_with(COOKIE_PREFIX.as_bytes()) {
+                history.cookies.insert(event.name);
+            }
+            return;
+        }
+        if event.mask & libc::IN_IGNORED != 0 {
+            self.directories.remove(&event.wd);
+            return;
+        }
+
+        let directory = match self.directories.get(&event.wd) {
+            Some(directory) => directory.clone(),
+            None => return,
+        };
+        if is_ignored(&event.name) {
+            return;
+        }
+        let path = if event.name.is_empty() {
+            directory
+        } else {
+            directory.join(&event.name)
+        };
+
+        // Anything created in a new directory before it was watched is covered by recording the
+        // directory itself as changed
+        let new_directory = event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0
+            && event.mask & libc::IN_ISDIR != 0;
+        if new_directory {
+            let _ = self.watch_tree(&path);
+        }
+        history.record(path);
+    }
+}
+
+fn is_ignored(name: &OsStr) -> bool {
+    Workspace::IGNORED_NAMES
+        .iter()
+        .any(|ignored| name == OsStr::new(ignored))
+}
+
+struct Inotify {
+    fd: RawFd,
+}
+
+struct Event {
+    wd: i32,
+    mask: u32,
+    name: OsString,
+}
+
+impl Inotify {
+    // Each event is a watch descriptor, mask, cookie and name length, followed by the name padded
+    // with NULs
+    const EVENT_SIZE: usize = 16;
+
+    fn new() -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify { fd })
+    }
+
+    fn add_watch(&self, path: &Path, mask: u32) -> io::Result<i32> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    fn read_events(&self, buffer: &mut [u8]) -> io::Result<Vec<Event>> {
+        let size = unsafe { libc::read(self.fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(parse_events(&buffer[..size as usize]))
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+fn parse_events(mut data: &[u8]) -> Vec<Event> {
+    let field = |data: &[u8], offset: usize| {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[offset..offset + 4]);
+        bytes
+    };
+
+    let mut events = Vec::new();
+    while data.len() >= Inotify::EVENT_SIZE {
+        let length = u32::from_ne_bytes(field(data, 12)) as usize;
+        let end = (Inotify::EVENT_SIZE + length).min(data.len());
+        let name = &data[Inotify::EVENT_SIZE..end];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())];
+
+        events.push(Event {
+            wd: i32::from_ne_bytes(field(data, 0)),
+            mask: u32::from_ne_bytes(field(data, 4)),
+            name: OsStr::from_bytes(name).to_os_string(),
+        });
+        data = &data[end..];
+    }
+    events
+}
+
+#[derive(Debug)]
+struct AlreadyRunning;
+impl Error for AlreadyRunning {}
+impl fmt::Display for AlreadyRunning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fsmonitor daemon is already running")
+    }
+}
+
+#[derive(Debug)]
+struct NotStarted;
+impl Error for NotStarted {}
+impl fmt::Display for NotStarted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fsmonitor daemon did not start")
+    }
+}
+
+#[derive(Debug)]
+struct NoResponse;
+impl Error for NoResponse {}
+impl fmt::Display for NoResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "fsmonitor daemon did not answer the query")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_answers_from_a_token_with_the_changes_since() {
+        let mut history = History::new();
+        history.record(PathBuf::from("a.txt"));
+        let token = history.token();
+        history.record(PathBuf::from("lib/b.txt"));
+
+        let changes = history.since(&token).unwrap();
+        assert_eq!(
+            vec![Path::new("lib/b.txt")],
+            changes.into_iter().collect::<Vec<_>>()
+        );
+
+        history.forget();
+        assert!(history.since(&token).is_none());
+        assert!(history.since("jit:other:0").is_none());
+        assert!(history.since(&history.token()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_refuses_tokens_from_before_events_were_lost() {
+        let mut watcher = Watcher {
+            root_path: PathBuf::from("."),
+            inotify: Inotify::new().unwrap(),
+            directories: HashMap::new(),
+            git_watch: -1,
+        };
+        let mut history = History::new();
+        let token = history.token();
+        assert!(history.since(&token).unwrap().is_empty());
+
+        let overflow = Event {
+            wd: -1,
+            mask: libc::IN_Q_OVERFLOW,
+            name: OsString::new(),
+        };
+        watcher.handle(&mut history, overflow);
+
+        assert!(history.since(&token).is_none());
+        assert!(history.since(&history.token()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn it_parses_inotify_events() {
+        let mut data = Vec::new();
+        for (wd, mask, name) in &[(1i32, libc::IN_MODIFY, &b"a.txt\0\0\0"[..]), (2, 0, b"")] {
+            data.extend(&wd.to_ne_bytes());
+            data.extend(&mask.to_ne_bytes());
+            data.extend(&0u32.to_ne_bytes());
+            data.extend(&(name.len() as u32).to_ne_bytes());
+            data.extend(*name);
+        }
+
+        let events = parse_events(&data);
+
+        assert_eq!(2, events.len());
+        assert_eq!((1, libc::IN_MODIFY), (events[0].wd, events[0].mask));
+        assert_eq!(OsStr::new("a.txt"), events[0].name);
+        assert_eq!(OsStr::new(""), events[1].name);
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use super::config::Config;
+
+// Decides which untracked paths are ignored, following the rules of gitignore(5)
+//
+// Patterns come from several sources, checked from highest precedence to lowest: those given on
+// the command line, `.gitignore` files from the deepest directory up, `.git/info/exclude` and
+// then `core.excludesFile`. Within a source the last matching pattern wins, and the first source
+// with any match decides.
+pub struct Ignore {
+    root_path: PathBuf,
+    command_line: Vec<Pattern>,
+    per_directory: bool,
+    directories: HashMap<PathBuf, Vec<Pattern>>,
+    global: Vec<Vec<Pattern>>,
+}
+
+struct Pattern {
+    glob: Vec<u8>,
+    // The directory holding the file the pattern came from, which anchored patterns start at
+    base: PathBuf,
+    negated: bool,
+    directory_only: bool,
+    anchored: bool,
+}
+
+impl Ignore {
+    pub fn new(root_path: PathBuf) -> Ignore {
+        Ignore {
+            root_path,
+            command_line: Vec::new(),
+            per_directory: false,
+            directories: HashMap::new(),
+            global: Vec::new(),
+        }
+    }
+
+    // Reads `.gitignore` files in every directory, `.git/info/exclude` and `core.excludesFile`
+    pub fn add_standard(&mut self, git_path: &Path, config: &Config) {
+        self.per_directory = true;
+
+        let info_exclude = fs::read_to_string(git_path.join("info").join("exclude"));
+        self.global.push(parse_patterns(
+            &info_exclude.unwrap_or_default(),
+            Path::new(""),
+        ));
+
+        if let Some(path) = Ignore::excludes_file(config) {
+            let content = fs::read_to_string(path).unwrap_or_default();
+            self.global.push(parse_patterns(&content, Path::new("")));
+        }
+    }
+
+    // The file named by `core.excludesFile`, or the default under the XDG config directory
+    pub fn excludes_file(config: &Config) -> Option<PathBuf> {
+        match config.get("core.excludesFile") {
+            Some(path) => match (path.strip_prefix("~/"), env::var_os("HOME")) {
+                (Some(rest), Some(home)) => Some(PathBuf::from(home).join(rest)),
+                _ => Some(PathBuf::from(path)),
+            },
+            None => match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+                (Some(xdg), _) if !xdg.is_empty() => Some(PathBuf::from(xdg).join("git/ignore")),
+                (_, Some(home)) => Some(PathBuf::from(home).join(".config/git/ignore")),
+                _ => None,
+            },
+        }
+    }
+
+    // Adds patterns given on the command line, one per line
+    pub fn add_patterns(&mut self, content: &str) {
+        self.command_line
+            .extend(parse_patterns(content, Path::new("")));
+    }
+
+    pub fn has_patterns(&self) -> bool {
+        self.per_directory || !self.command_line.is_empty() || !self.global.is_empty()
+    }
+
+    // Files can't be re-included once a directory above them is ignored, so each of those is
+    // checked first
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let mut parents: Vec<&Path> = path
+            .ancestors()
+            .skip(1)
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .collect();
+        parents.reverse();
+
+        parents.into_iter().any(|parent| self.matches(parent, true)) || self.matches(path, is_dir)
+    }
+
+    fn matches(&mut self, path: &Path, is_dir: bool) -> bool {
+        if let Some(negated) = last_match(&self.command_line, path, is_dir) {
+            return !negated;
+        }
+
+        if self.per_directory {
+            for dir in path.ancestors().skip(1) {
+                let patterns = self.directory_patterns(dir);
+                if let Some(negated) = last_match(patterns, path, is_dir) {
+                    return !negated;
+                }
+            }
+        }
+
+        for patterns in &self.global {
+            if let Some(negated) = last_match(patterns, path, is_dir) {
+                return !negated;
+            }
+        }
+        false
+    }
+
+    fn directory_patterns(&mut self, dir: &Path) -> &[Pattern] {
+        let root_path = &self.root_path;
+        self.directories
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| {
+                let content = fs::read_to_string(root_path.join(dir).join(".gitignore"));
+                parse_patterns(&content.unwrap_or_default(), dir)
+            })
+    }
+}
+
+fn parse_patterns(content: &str, base: &Path) -> Vec<Pattern> {
+    content
+        .lines()
+        .filter_map(|line| Pattern::parse(line, base))
+        .collect()
+}
+
+// Whether the last pattern matching `path` was negated, if any matched
+fn last_match(patterns: &[Pattern], path: &Path, is_dir: bool) -> Option<bool> {
+    patterns
+        .iter()
+        .rev()
+        .find(|pattern| pattern.matches(path, is_dir))
+        .map(|pattern| pattern.negated)
+}
+
+impl Pattern {
+    fn parse(line: &str, base: &Path) -> Option<Pattern> {
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        // Trailing spaces are dropped unless escaped with a backslash
+        let mut line = line;
+        while line.ends_with(' ') && !line.ends_with("\\ ") {
+            line = &line[..line.len() - 1];
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        // A backslash lets a pattern start with a literal `#` or `!`
+        let line = match line.strip_prefix('\\') {
+            Some(rest) if rest.starts_with('#') || rest.starts_with('!') => rest,
+            _ => line,
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+
+        // A slash anywhere but the end ties the pattern to the directory of its file
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+
+        Some(Pattern {
+            glob: line.as_bytes().to_vec(),
+            base: base.to_path_buf(),
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+
+        if self.anchored {
+            wildmatch(&self.glob, relative.as_os_str().as_bytes())
+        } else {
+            match relative.file_name() {
+                Some(name) => wildmatch(&self.glob, name.as_bytes()),
+                None => false,
+            }
+        }
+    }
+}
+
+// Glob matching where `*` and `?` stop at slashes while `**` crosses them, so `a/**/b` matches
+// `a/b`, `a/x/b` and `a/x/y/b`. Only a `**` that makes up a whole path component is special, and
+// any other run of asterisks acts as a single `*`.
+fn wildmatch(glob: &[u8], text: &[u8]) -> bool {
+    match_from(glob, 0, text)
+}
+
+// Matches `text` against the glob from `i` onwards, with the glob before `i` there to tell
+// whether an asterisk starts a path component
+fn match_from(glob: &[u8], i: usize, text: &[u8]) -> bool {
+    match glob.get(i) {
+        None => text.is_empty(),
+        Some(b'*') => {
+            let mut end = i + 1;
+            while glob.get(end) == Some(&b'*') {
+                end += 1;
+            }
+            let whole_component = end - i > 1
+                && (i == 0 || glob[i - 1] == b'/')
+                && matches!(glob.get(end), None | Some(b'/'));
+
+            if whole_component {
+                if glob.get(end) == Some(&b'/') && match_from(glob, end + 1, text) {
+                    return true;
+                }
+                return (0..=text.len()).any(|n| match_from(glob, end, &text[n..]));
+            }
+            for n in 0..=text.len() {
+                if match_from(glob, end, &text[n..]) {
+                    return true;
+                }
+                if text.get(n) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(&c) if c != b'/' => match_from(glob, i + 1, &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match (match_class(&glob[i + 1..], text.first()), text.first()) {
+            (Some((true, length)), Some(_)) => match_from(glob, i + 1 + length, &text[1..]),
+            (Some(_), _) => false,
+            // An unclosed bracket is matched literally
+            (None, Some(b'[')) => match_from(glob, i + 1, &text[1..]),
+            (None, _) => false,
+        },
+        Some(b'\\') if glob.len() > i + 1 => {
+            text.first() == Some(&glob[i + 1]) && match_from(glob, i + 2, &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && match_from(glob, i + 1, &text[1..]),
+    }
+}
+
+// Matches a bracket expression such as `[a-z]` or `[!0-9]`, given the glob after its opening
+// bracket, and returns whether it matched along with how much of the glob it took up
+fn match_class(glob: &[u8], c: Option<&u8>) -> Option<(bool, usize)> {
+    let (negated, mut i) = match glob.first() {
+        Some(b'!') | Some(b'^') => (true, 1),
+        _ => (false, 0),
+    };
+    let start = i;
+    let mut matched = false;
+
+    loop {
+        let first = *glob.get(i)?;
+        if first == b']' && i > start {
+            break;
+        }
+        match (glob.get(i + 1), glob.get(i + 2)) {
+            (Some(b'-'), Some(&last)) if last != b']' => {
+                matched |= c.is_some_and(|&c| first <= c && c <= last);
+                i += 3;
+            }
+            _ => {
+                matched |= c == Some(&first);
+                i += 1;
+            }
+        }
+    }
+
+    Some((matched != negated && c != Some(&b'/'), i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_matches_globs_as_git_does() {
+        assert!(wildmatch(b"*.o", b"main.o"));
+        assert!(!wildmatch(b"*.o", b"lib/main.o"));
+        assert!(wildmatch(b"lib/**/*.o", b"lib/main.o"));
+        assert!(wildmatch(b"lib/**/*.o", b"lib/a/b/main.o"));
+        assert!(wildmatch(b"**/build", b"a/build"));
+        assert!(wildmatch(b"doc/**", b"doc/a/b.txt"));
+        assert!(wildmatch(b"file[0-9].[!c]", b"file7.h"));
+        assert!(!wildmatch(b"file[0-9].[!c]", b"file7.c"));
+        assert!(wildmatch(b"[]]x", b"]x"));
+        assert!(wildmatch(b"a\\*", b"a*"));
+        assert!(!wildmatch(b"a\\*", b"ab"));
+    }
+
+    #[test]
+    fn it_only_crosses_slashes_with_a_whole_double_asterisk() {
+        assert!(wildmatch(b"a**b", b"axyzb"));
+        assert!(!wildmatch(b"a**b", b"a/b"));
+        assert!(wildmatch(b"foo/**bar", b"foo/xbar"));
+        assert!(wildmatch(b"foo/**bar", b"foo/bar"));
+        assert!(!wildmatch(b"foo/**bar", b"foo/x/bar"));
+        assert!(wildmatch(b"foo**/bar", b"foox/bar"));
+        assert!(!wildmatch(b"foo**/bar", b"foo/x/bar"));
+        assert!(wildmatch(b"***/x", b"a/b/x"));
+        assert!(wildmatch(b"a/***", b"a/b/c"));
+        assert!(wildmatch(b"**", b"a/b"));
+    }
+
+    #[test]
+    fn it_matches_escaped_leading_hashes_and_exclamation_marks() {
+        let mut ignore = Ignore::new(PathBuf::from("/nonexistent"));
+        ignore.add_patterns("#comment\n\\#notes\n*.txt\n\\!important.txt\n");
+
+        assert!(ignore.is_ignored(Path::new("#notes"), false));
+        assert!(!ignore.is_ignored(Path::new("#comment"), false));
+        assert!(ignore.is_ignored(Path::new("!important.txt"), false));
+        assert!(ignore.is_ignored(Path::new("important.txt"), false));
+    }
+
+    #[test]
+    fn it_applies_precedence_and_negation() {
+        let mut ignore = Ignore::new(PathBuf::from("/nonexistent"));
+        ignore.add_patterns("*.log\n!keep.log\nbuild/\n/top.txt\n");
+
+        assert!(ignore.is_ignored(Path::new("debug.log"), false));
+        assert!(!ignore.is_ignored(Path::new("keep.log"), false));
+        assert!(ignore.is_ignored(Path::new("src/build/out.txt"), false));
+        assert!(!ignore.is_ignored(Path::new("build"), false));
+        assert!(ignore.is_ignored(Path::new("top.txt"), false));
+        assert!(!ignore.is_ignored(Path::new("src/top.txt"), false));
+    }
+}
@@ -1,5 +1,9 @@
+pub mod cache_tree;
 mod checksum;
 pub mod entry;
+mod ewah;
+pub mod fsmonitor;
+pub mod untracked_cache;
 
 use std::{
     collections::{hash_map::Entry as EntryApi, BTreeMap, HashMap, HashSet},
@@ -7,20 +11,36 @@ use std::{
     ffi::{OsStr, OsString},
     fmt,
     fs::{File, Metadata},
-    io::ErrorKind,
-    path::PathBuf,
+    io::{ErrorKind, Read},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
 };
 
-use super::database::tree::TreeEntry;
+use super::config::Config;
+use super::database::tree::{DatabaseEntry, OidAndMode, TreeEntry};
+use super::ignore::Ignore;
 use super::lockfile::Lockfile;
+use cache_tree::CacheTree;
 use checksum::{Checksum, ChecksumWriter};
 use entry::Entry;
+use fsmonitor::FsMonitorExtension;
+use untracked_cache::UntrackedCache;
 
 pub struct Index {
     // We do care about order, so this needs to be a BTreeMap
     // Could use HashMap and entry ordering?
-    entries: BTreeMap<OsString, Entry>,
+    entries: BTreeMap<(OsString, u16), Entry>,
     parents: HashMap<OsString, HashSet<OsString>>,
+    cache_tree: CacheTree,
+    untracked_cache: Option<UntrackedCache>,
+    extensions: Vec<Extension>,
+    version: u32,
+    // When the index file was last written, as seconds and nanoseconds
+    timestamp: Option<(u32, u32)>,
+    // Whether executable bits in the workspace can be trusted, from `core.fileMode`
+    file_mode: bool,
+    // The filesystem monitor's token for when entries were last checked, if it's in use
+    fsmonitor_token: Option<String>,
     lockfile: Lockfile,
     changed: bool,
 }
@@ -31,18 +51,162 @@ impl Index {
             // Note entries already sorted so no need to add SortedSet etc
             entries: BTreeMap::new(),
             parents: HashMap::new(),
+            cache_tree: CacheTree::new(),
+            untracked_cache: None,
+            extensions: Vec::new(),
+            version: Index::DEFAULT_VERSION,
+            timestamp: None,
+            file_mode: true,
+            fsmonitor_token: None,
             lockfile: Lockfile::new(pathname),
             changed: false,
         }
     }
 
     pub fn add(&mut self, pathname: PathBuf, oid: String, stat: Metadata) {
-        let entry = Entry::new(pathname, &oid, stat).expect("Failed to create entry");
+        let entry = self.entry_for_file(pathname, &oid, stat);
+        self.add_entry(entry);
+    }
+
+    // Records that a file will be added, staging it as empty in the meantime
+    pub fn add_intent_to_add(&mut self, pathname: PathBuf, stat: Metadata) {
+        let mut entry = self.entry_for_file(pathname, Entry::EMPTY_BLOB_OID, stat);
+        entry.set_intent_to_add();
+        self.add_entry(entry);
+    }
+
+    // Without trustworthy executable bits, files keep the mode they're already staged with and
+    // new ones are added as non-executable
+    fn entry_for_file(&self, pathname: PathBuf, oid: &str, stat: Metadata) -> Entry {
+        let mut entry = Entry::new(pathname, oid, stat);
+        entry.set_fsmonitor_valid(
+            self.fsmonitor_token.is_some() && entry.mode() != Entry::GITLINK_MODE,
+        );
+        if !self.file_mode && Entry::is_regular(entry.mode()) {
+            let mode = match self.entry(entry.path()) {
+                Some(existing) if Entry::is_regular(existing.mode()) => existing.mode(),
+                _ => Entry::REGULAR_MODE,
+            };
+            entry.set_mode(mode);
+        }
+        entry
+    }
+
+    pub fn add_entry(&mut self, entry: Entry) {
         self.discard_conflicts(&entry);
+        self.invalidate_caches(entry.name());
         self.store_entry(entry);
         self.changed = true;
     }
 
+    // Records a merge conflict at `path` as the base, ours and theirs versions in stages 1 to 3,
+    // replacing any resolved entry for it. Versions missing from a side are left out.
+    pub fn add_conflict_set(&mut self, path: PathBuf, items: [Option<DatabaseEntry>; 3]) {
+        self.remove_entry_with_stage(path.as_os_str(), 0);
+
+        for (stage, item) in (1..).zip(items.iter()) {
+            if let Some(item) = item {
+                let mut entry = Entry::from_database(path.clone(), item.oid(), item.mode());
+                entry.set_stage(stage);
+                self.invalidate_caches(entry.name());
+                self.store_entry(entry);
+            }
+        }
+        self.changed = true;
+    }
+
+    // Removes every stage of the entry at `path`, or every entry below it if it's a directory
+    pub fn remove(&mut self, path: &OsStr) {
+        self.remove_entry(path);
+        self.remove_children(path);
+        self.changed = true;
+    }
+
+    // Drops the conflicted stages of `path` without adding a resolution in their place
+    pub fn remove_conflict(&mut self, path: &OsStr) {
+        for stage in 1..=3 {
+            self.remove_entry_with_stage(path, stage);
+        }
+        self.changed = true;
+    }
+
+    pub fn cache_tree(&self) -> &CacheTree {
+        &self.cache_tree
+    }
+
+    // Trees stored for the index should be recorded here so that the next commit can reuse them.
+    // The index only needs writing again if that changes what was cached.
+    pub fn update_cache_tree<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut CacheTree),
+    {
+        let cached = self.cache_tree.clone();
+        f(&mut self.cache_tree);
+        if self.cache_tree != cached {
+            self.changed = true;
+        }
+    }
+
+    // Drops every entry, e.g. before reading a whole tree into the index. The untracked cache
+    // stays enabled, but none of what it recorded can be trusted any more.
+    pub fn clear_entries(&mut self) {
+        let untracked_cache = self.untracked_cache.take();
+        self.clear();
+        self.untracked_cache = untracked_cache.map(|mut cache| {
+            cache.clear();
+            cache
+        });
+        self.changed = true;
+    }
+
+    // Whether an untracked file becomes tracked or the other way round, the cached listing of its
+    // directory needs reading again, as does the tree holding it. Extensions jit doesn't
+    // understand may describe the entries too, so they can't be trusted to match any more.
+    fn invalidate_caches(&mut self, path: &Path) {
+        self.extensions.clear();
+        self.cache_tree.invalidate(path);
+        if let Some(untracked_cache) = &mut self.untracked_cache {
+            untracked_cache.invalidate(path);
+        }
+    }
+
+    pub fn has_untracked_cache(&self) -> bool {
+        self.untracked_cache.is_some()
+    }
+
+    // Starts recording untracked files in the index for later scans to reuse, or stops
+    pub fn set_untracked_cache(&mut self, enabled: bool) {
+        if enabled == self.has_untracked_cache() {
+            return;
+        }
+        self.untracked_cache = if enabled {
+            Some(UntrackedCache::new(&self.root_path()))
+        } else {
+            None
+        };
+        self.changed = true;
+    }
+
+    // Lists the untracked files that aren't ignored by the standard ignore rules, which `ignore`
+    // must be set up with, or returns None when the untracked cache isn't enabled. Directories
+    // whose listing is in the cache are only read again if they've changed, and the listings of
+    // any that are read are recorded for next time.
+    pub fn untracked_files(&mut self, ignore: &mut Ignore) -> Option<Vec<PathBuf>> {
+        let mut untracked_cache = self.untracked_cache.take()?;
+        let (files, changed) = untracked_cache.untracked_files(&self.root_path(), self, ignore);
+        self.untracked_cache = Some(untracked_cache);
+        self.changed |= changed;
+        Some(files)
+    }
+
+    fn root_path(&self) -> PathBuf {
+        let git_path = self.lockfile.file_path().parent();
+        git_path
+            .and_then(Path::parent)
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
     pub fn write_updates(&mut self) {
         if !self.changed {
             self.lockfile
@@ -51,171 +215,505 @@ impl Index {
             return;
         }
 
+        self.smudge_racy_entries();
+        let version = self.write_version();
         let mut writer = ChecksumWriter::new(&mut self.lockfile);
 
         let mut header = String::from("DIRC").into_bytes();
-        header.extend(&2u32.to_be_bytes());
+        header.extend(&version.to_be_bytes());
         header.extend(&(self.entries.len() as u32).to_be_bytes());
         writer.write(&header);
-        // TODO: Tidy this up
-        let mut value_strings = Vec::new();
-        for value in self.entries.values() {
-            value_strings.push(value.to_string());
+
+        let mut previous_path: &[u8] = b"";
+        for entry in self.entries.values() {
+            writer.write(&entry.to_string(version, previous_path));
+            previous_path = entry.path().as_bytes();
         }
-        for value in value_strings {
-            writer.write(&value);
+
+        if !self.cache_tree.is_empty() {
+            write_extension(
+                &mut writer,
+                CacheTree::SIGNATURE,
+                &self.cache_tree.to_string(),
+            );
+        }
+        if let Some(untracked_cache) = &self.untracked_cache {
+            write_extension(
+                &mut writer,
+                UntrackedCache::SIGNATURE,
+                &untracked_cache.to_string(),
+            );
+        }
+        if let Some(token) = &self.fsmonitor_token {
+            let fsmonitor = FsMonitorExtension {
+                token: token.clone(),
+                dirty: self
+                    .entries
+                    .values()
+                    .map(|e| !e.fsmonitor_valid())
+                    .collect(),
+            };
+            write_extension(
+                &mut writer,
+                FsMonitorExtension::SIGNATURE,
+                &fsmonitor.to_string(),
+            );
+        }
+        for extension in &self.extensions {
+            write_extension(&mut writer, &extension.signature, &extension.data);
         }
 
         writer.write_checksum();
+        let stat = self
+            .lockfile
+            .metadata()
+            .expect("Failed to get metadata for index lock");
         self.lockfile.commit().unwrap();
 
+        // Entries are racy from now on if modified as late as this write
+        self.timestamp = Some((stat.mtime() as u32, stat.mtime_nsec() as u32));
+
+        self.version = version;
         self.changed = false;
     }
 
+    // Entries for files modified no earlier than the index is written would look clean once later
+    // writes move the index's timestamp on, even if the file changes again within that time. The
+    // lock file is only written to after being created, so anything as new as it may be racy.
+    // Forgetting their size makes sure their content is checked instead.
+    fn smudge_racy_entries(&mut self) {
+        let stat = self
+            .lockfile
+            .metadata()
+            .expect("Failed to get metadata for index lock");
+        let (seconds, nanoseconds) = (stat.mtime() as u32, stat.mtime_nsec() as u32);
+        for entry in self.entries.values_mut() {
+            if entry.modified_since(seconds, nanoseconds) {
+                entry.smudge();
+            }
+        }
+    }
+
+    // `index.version` picks the format to write, as version 4 makes the index much smaller in
+    // repositories with deep paths. Otherwise the version that was read is kept, bumped to 3 if
+    // any entries now need extended flags.
+    fn write_version(&self) -> u32 {
+        let config = Config::load(&self.lockfile.file_path().with_file_name("config"));
+        let version = match config.get_int("index.version") {
+            Some(version) if (2..=4).contains(&version) => version as u32,
+            _ => self.version,
+        };
+
+        if version < 3 && self.entries.values().any(Entry::has_extended_flags) {
+            3
+        } else {
+            version
+        }
+    }
+
     pub fn entries(&self) -> impl Iterator<Item = &Entry> {
         self.entries.values()
     }
 
-    pub fn load_for_update(&mut self) -> bool {
-        if self.lockfile.hold_for_update().is_ok() {
-            self.load();
-            true
-        } else {
-            false
+    pub fn entry(&self, path: &OsStr) -> Option<&Entry> {
+        self.entry_at_stage(path, 0)
+    }
+
+    pub fn entry_at_stage(&self, path: &OsStr, stage: u16) -> Option<&Entry> {
+        self.entries.get(&(path.to_owned(), stage))
+    }
+
+    // The base, ours and theirs versions of a conflicted path, in that order
+    pub fn conflict_entries(&self, path: &OsStr) -> Vec<&Entry> {
+        (1..=3)
+            .filter_map(|stage| self.entry_at_stage(path, stage))
+            .collect()
+    }
+
+    pub fn is_conflicted(&self, path: &OsStr) -> bool {
+        !self.conflict_entries(path).is_empty()
+    }
+
+    pub fn tracked_file(&self, path: &OsStr) -> bool {
+        (0..=3).any(|stage| self.entry_at_stage(path, stage).is_some())
+    }
+
+    pub fn tracked_directory(&self, path: &OsStr) -> bool {
+        self.parents.contains_key(path)
+    }
+
+    // The paths of every entry below a directory, in index order
+    pub fn child_paths(&self, path: &OsStr) -> Vec<PathBuf> {
+        let mut children: Vec<PathBuf> = match self.parents.get(path) {
+            Some(children) => children.iter().map(PathBuf::from).collect(),
+            None => Vec::new(),
+        };
+        children.sort_by(|a, b| a.as_os_str().cmp(b.as_os_str()));
+        children
+    }
+
+    // Whether the stat data of `entry` shows its file is unchanged without reading it. Files
+    // modified as late as the index was written are "racily clean": they could have changed again
+    // within the same timestamp, so their content needs checking.
+    pub fn is_stat_clean(&self, entry: &Entry, stat: &Metadata) -> bool {
+        entry.stat_unchanged(stat, self.file_mode) && !self.is_racily_clean(entry)
+    }
+
+    // Whether the file could still have the content of `entry`, going by its type and size
+    pub fn stat_match(&self, entry: &Entry, stat: &Metadata) -> bool {
+        entry.stat_match(stat, self.file_mode)
+    }
+
+    pub fn is_racily_clean(&self, entry: &Entry) -> bool {
+        match self.timestamp {
+            Some((seconds, nanoseconds)) => entry.modified_since(seconds, nanoseconds),
+            None => false,
+        }
+    }
+
+    // Records fresh stat data for an entry whose file was found to be unchanged, so the next check
+    // doesn't need to read it, and trusts it while the filesystem monitor reports no change
+    pub fn update_entry_stat(&mut self, path: &OsStr, stat: &Metadata) {
+        let stale = self
+            .entry(path)
+            .is_some_and(|entry| !self.is_stat_clean(entry, stat));
+        if stale {
+            if let Some(entry) = self.entries.get_mut(&(path.to_owned(), 0)) {
+                entry.update_stat(stat);
+                self.changed = true;
+            }
         }
+        self.mark_fsmonitor_valid(path);
+    }
+
+    // Stages an entry with a new mode but the same content, returning false if it isn't tracked
+    pub fn set_entry_mode(&mut self, path: &OsStr, mode: u32) -> bool {
+        let updated = self.update_entry(path, |entry| entry.set_mode(mode));
+        if updated {
+            self.cache_tree.invalidate(Path::new(path));
+        }
+        updated
+    }
+
+    pub fn set_assume_unchanged(&mut self, path: &OsStr, assume_unchanged: bool) -> bool {
+        self.update_entry(path, |entry| entry.set_assume_unchanged(assume_unchanged))
+    }
+
+    pub fn set_skip_worktree(&mut self, path: &OsStr, skip_worktree: bool) -> bool {
+        self.update_entry(path, |entry| entry.set_skip_worktree(skip_worktree))
     }
 
-    pub fn load(&mut self) {
+    fn update_entry<F>(&mut self, path: &OsStr, update: F) -> bool
+    where
+        F: FnOnce(&mut Entry),
+    {
+        match self.entries.get_mut(&(path.to_owned(), 0)) {
+            Some(entry) => {
+                update(entry);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Whether any path still has conflicts left by a merge, in which case no tree can be built
+    pub fn is_unmerged(&self) -> bool {
+        self.entries.values().any(|entry| entry.stage() > 0)
+    }
+
+    pub fn release_lock(&mut self) {
+        self.lockfile
+            .rollback()
+            .expect("Failed to rollback lockfile");
+    }
+
+    // Takes the lock and loads the index, or returns false if the lock is already held. The lock
+    // is given up again if the index can't be read.
+    pub fn load_for_update(&mut self) -> Result<bool, Box<dyn error::Error>> {
+        if !self.lockfile.hold_for_update()? {
+            return Ok(false);
+        }
+        if let Err(error) = self.load() {
+            self.release_lock();
+            return Err(error);
+        }
+        Ok(true)
+    }
+
+    // A missing index is an empty one
+    pub fn load(&mut self) -> Result<(), Box<dyn error::Error>> {
         self.clear();
-        let file = self.open_index_file();
+        let config = Config::load(&self.lockfile.file_path().with_file_name("config"));
+        self.file_mode = config.get_bool("core.fileMode").unwrap_or(true);
+
+        let fsmonitor = match File::open(self.lockfile.file_path()) {
+            Ok(mut file) => {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)?;
+                let stat = file.metadata()?;
+
+                match self.parse(&data) {
+                    Ok(fsmonitor) => {
+                        self.timestamp = Some((stat.mtime() as u32, stat.mtime_nsec() as u32));
+                        fsmonitor
+                    }
+                    Err(error) => {
+                        self.clear();
+                        return Err(error);
+                    }
+                }
+            }
+            Err(error) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => return Err(error.into()),
+        };
+
+        if config.get_bool("core.fsmonitor").unwrap_or(false) {
+            self.refresh_fsmonitor(fsmonitor);
+        }
+        // Unless set to "keep", `core.untrackedCache` decides whether the cache is kept at all
+        if let Some(enabled) = config.get_bool("core.untrackedCache") {
+            self.set_untracked_cache(enabled);
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, data: &[u8]) -> Result<Option<FsMonitorExtension>, Box<dyn error::Error>> {
+        let mut reader = Checksum::new(data)?;
+        let count = self.read_header(&mut reader)?;
+        self.read_entries(&mut reader, count)?;
+        self.read_extensions(&mut reader)
+    }
+
+    // Asks the filesystem monitor what has changed since the index was last written, so that the
+    // entries it vouches for needn't be checked at all. Without an answer, every entry is checked
+    // as usual and the extension is dropped.
+    fn refresh_fsmonitor(&mut self, extension: Option<FsMonitorExtension>) {
+        let git_path = self.lockfile.file_path().with_file_name("");
+        let since = extension.as_ref().map_or("", |extension| &extension.token);
+        let changes = match super::fsmonitor::query(&git_path, since) {
+            Ok(changes) => changes,
+            Err(_) => return,
+        };
 
-        if let Some(file) = file {
-            let mut reader = Checksum::new(file);
-            let count = self
-                .read_header(&mut reader)
-                .expect("Failed to read index header");
-            self.read_entries(&mut reader, count);
-            reader
-                .verify_checksum()
-                .expect("Failed to verify index checksum");
+        let extension = extension.filter(|extension| extension.dirty.len() <= self.entries.len());
+        if let Some(extension) = extension {
+            let mut dirty = extension.dirty.into_iter();
+            for entry in self.entries.values_mut() {
+                let clean = !dirty.next().unwrap_or(false)
+                    && !changes.includes(Path::new(entry.path()))
+                    && entry.mode() != Entry::GITLINK_MODE;
+                entry.set_fsmonitor_valid(clean);
+            }
+        }
+        self.fsmonitor_token = Some(changes.token);
+    }
+
+    // Records that a file was found unchanged, so the filesystem monitor can vouch for it until it
+    // next changes. A submodule's files say nothing about which commit it has checked out.
+    pub fn mark_fsmonitor_valid(&mut self, path: &OsStr) {
+        if self.fsmonitor_token.is_none() {
+            return;
+        }
+        if let Some(entry) = self.entries.get_mut(&(path.to_owned(), 0)) {
+            if !entry.fsmonitor_valid() && entry.mode() != Entry::GITLINK_MODE {
+                entry.set_fsmonitor_valid(true);
+                self.changed = true;
+            }
         }
-        // TODO: Confirm no need to close file
     }
 
     fn clear(&mut self) {
         // TODO: Determine if I need to do anything with lockfile here - I think is OK like this
         self.entries = BTreeMap::new();
         self.parents = HashMap::new();
+        self.cache_tree = CacheTree::new();
+        self.untracked_cache = None;
+        self.extensions = Vec::new();
+        self.version = Index::DEFAULT_VERSION;
+        self.timestamp = None;
+        self.fsmonitor_token = None;
         self.changed = false;
     }
 
-    // TODO: Convert to returning result?
-    fn open_index_file(&self) -> Option<File> {
-        match File::open(self.lockfile.file_path()) {
-            Ok(file) => Some(file),
-            Err(error) => match error.kind() {
-                ErrorKind::NotFound => None,
-                _ => {
-                    panic!("Error when opening file: {:?}", error);
-                }
-            },
-        }
-    }
-
     const HEADER_SIZE: usize = 12;
-    const SIGNATURE: &'static str = "DIRC";
-    const VERSION: u32 = 2;
-
-    fn read_header(&self, reader: &mut Checksum) -> Result<u32, Box<dyn error::Error>> {
-        let data = reader
-            .read(Index::HEADER_SIZE)
-            .expect("Failed to read header data from index");
-        let signature = String::from_utf8(data[0..4].to_owned()).unwrap();
+    const SIGNATURE: &'static [u8; 4] = b"DIRC";
+    const DEFAULT_VERSION: u32 = 2;
+    const SUPPORTED_VERSIONS: [u32; 3] = [2, 3, 4];
+
+    fn read_header(&mut self, reader: &mut Checksum<'_>) -> Result<u32, Box<dyn error::Error>> {
+        let data = reader.read(Index::HEADER_SIZE)?;
+        let signature: [u8; 4] = clone_into_array(&data[0..4]);
         let version = u32::from_be_bytes(clone_into_array(&data[4..8]));
         let count = u32::from_be_bytes(clone_into_array(&data[8..12]));
 
-        if signature != Index::SIGNATURE || version != Index::VERSION {
-            return Err(Invalid.into());
+        if &signature != Index::SIGNATURE {
+            return Err(BadSignature(signature).into());
+        }
+        if !Index::SUPPORTED_VERSIONS.contains(&version) {
+            return Err(UnsupportedVersion(version).into());
         }
+        self.version = version;
         Ok(count)
     }
 
-    const ENTRY_BLOCK: usize = 8;
-    const ENTRY_MIN_SIZE: usize = 64;
-
-    fn read_entries(&mut self, reader: &mut Checksum, count: u32) {
+    fn read_entries(
+        &mut self,
+        reader: &mut Checksum<'_>,
+        count: u32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut previous_path = Vec::new();
         for _i in 0..count {
-            let mut entry = reader
-                .read(Index::ENTRY_MIN_SIZE)
-                .expect("Failed to read entry data from index");
-
-            while entry[entry.len() - 1..] != [0u8] {
-                entry.append(
-                    &mut reader
-                        .read(Index::ENTRY_BLOCK)
-                        .expect("Failed to read entry data from index"),
-                );
-            }
+            let entry = Entry::read(reader, self.version, &previous_path)?;
+            previous_path = entry.path().as_bytes().to_vec();
+            self.store_entry(entry);
+        }
+        Ok(())
+    }
+
+    const EXTENSION_HEADER_SIZE: usize = 8;
+
+    // Extensions that record byte offsets into the file, which rewriting the index invalidates
+    const POSITIONAL_EXTENSIONS: [&'static [u8; 4]; 2] = [b"EOIE", b"IEOT"];
 
-            self.store_entry(Entry::from(entry).expect("Failed to parse entry in index"));
+    // Extensions follow the entries as a 4-byte signature, a 32-bit size and then the data. Any
+    // that jit doesn't understand are kept for writing back if optional, which is signalled by
+    // an uppercase first letter, and refused otherwise since they change how the index is read.
+    fn read_extensions(
+        &mut self,
+        reader: &mut Checksum<'_>,
+    ) -> Result<Option<FsMonitorExtension>, Box<dyn error::Error>> {
+        let mut fsmonitor = None;
+        while reader.has_more_data() {
+            let header = reader.read(Index::EXTENSION_HEADER_SIZE)?;
+            let signature: [u8; 4] = clone_into_array(&header[0..4]);
+            let size = u32::from_be_bytes(clone_into_array(&header[4..8])) as usize;
+            let data = reader.read(size)?;
+
+            if &signature == CacheTree::SIGNATURE {
+                self.cache_tree = CacheTree::parse(data)?;
+            } else if &signature == UntrackedCache::SIGNATURE {
+                self.untracked_cache = Some(UntrackedCache::parse(data)?);
+            } else if &signature == FsMonitorExtension::SIGNATURE {
+                fsmonitor = Some(FsMonitorExtension::parse(data)?);
+            } else if !signature[0].is_ascii_uppercase() {
+                return Err(UnsupportedExtension(signature).into());
+            } else if !Index::POSITIONAL_EXTENSIONS.contains(&&signature) {
+                self.extensions.push(Extension {
+                    signature,
+                    data: data.to_vec(),
+                });
+            }
         }
+        Ok(fsmonitor)
     }
 
     fn store_entry(&mut self, entry: Entry) {
         // TODO: Confirm to_owned here, also confirm clone
-        self.entries.insert(entry.path().to_owned(), entry.clone());
+        self.entries.insert(entry.key(), entry.clone());
 
-        for parent in entry.name().ancestors() {
+        // Every directory containing the entry, up to the root as an empty path
+        for parent in entry.name().ancestors().skip(1) {
             let child_set = self
                 .parents
                 .entry(parent.as_os_str().to_owned())
-                .or_insert(HashSet::new());
+                .or_default();
 
             child_set.insert(entry.name().as_os_str().to_owned());
         }
     }
 
+    // Adding an entry replaces any files or directories in its way, and since every stage of its
+    // own path is removed too, this also resolves a conflict there
     fn discard_conflicts(&mut self, entry: &Entry) {
         for parent in entry.name().ancestors() {
-            self.entries.remove(parent.as_os_str());
+            self.remove_entry(parent.as_os_str());
         }
         self.remove_children(entry.name().as_os_str());
     }
 
     fn remove_children(&mut self, path: &OsStr) {
-        let children = self.parents.get(path);
-        match children {
-            Some(children) => {
-                for child in children {
-                    self.remove_entry(child)
-                }
-            }
+        let children = match self.parents.get(path) {
+            Some(children) => children.clone(),
             None => return,
+        };
+        for child in children {
+            self.remove_entry(&child)
         }
     }
 
     fn remove_entry(&mut self, entry_path: &OsStr) {
-        self.entries.remove(entry_path);
+        for stage in 0..=3 {
+            self.remove_entry_with_stage(entry_path, stage);
+        }
+    }
 
-        for parent in entry.name().ancestors() {
+    fn remove_entry_with_stage(&mut self, entry_path: &OsStr, stage: u16) {
+        let entry = match self.entries.remove(&(entry_path.to_owned(), stage)) {
+            Some(entry) => entry,
+            None => return,
+        };
+        self.invalidate_caches(entry.name());
+
+        // Other stages of the path still need it kept as a child of its directories
+        if (0..=3).any(|stage| self.entry_at_stage(entry_path, stage).is_some()) {
+            return;
+        }
+
+        for parent in entry.name().ancestors().skip(1) {
             let child_set = self.parents.entry(parent.as_os_str().to_owned());
 
-            match child_set {
-                EntryApi::Occupied(child_set) => {
-                    child_set.remove(entry.name().as_os_str().to_owned())
+            if let EntryApi::Occupied(mut child_set) = child_set {
+                child_set.get_mut().remove(entry.name().as_os_str());
+                if child_set.get().is_empty() {
+                    child_set.remove();
                 }
-                EntryApi::Vacant() => {}
             }
         }
     }
 }
 
+// An optional extension jit doesn't understand, written back to the index unchanged as long as
+// no entries are added or removed
+struct Extension {
+    signature: [u8; 4],
+    data: Vec<u8>,
+}
+
+fn write_extension(writer: &mut ChecksumWriter, signature: &[u8; 4], data: &[u8]) {
+    writer.write(signature);
+    writer.write(&(data.len() as u32).to_be_bytes());
+    writer.write(data);
+}
+
 #[derive(Debug)]
-struct Invalid;
-impl Error for Invalid {}
-impl fmt::Display for Invalid {
+struct UnsupportedExtension([u8; 4]);
+impl Error for UnsupportedExtension {}
+impl fmt::Display for UnsupportedExtension {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(
+            f,
+            "index uses {} extension, which jit does not understand",
+            String::from_utf8_lossy(&self.0)
+        )
+    }
+}
+
+#[derive(Debug)]
+struct UnsupportedVersion(u32);
+impl Error for UnsupportedVersion {}
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "index file version {} is not supported", self.0)
+    }
+}
+
+#[derive(Debug)]
+struct BadSignature([u8; 4]);
+impl Error for BadSignature {}
+impl fmt::Display for BadSignature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad signature 0x{:08x}", u32::from_be_bytes(self.0))
     }
 }
 
@@ -234,8 +732,9 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::{ffi::OsStr, path::PathBuf};
+    use std::{ffi::OsStr, fs, os::unix::fs::PermissionsExt, path::PathBuf, time::SystemTime};
 
+    use super::super::temp_dir::TempDir;
     use super::*;
 
     use rand::Rng;
@@ -332,4 +831,212 @@ mod tests {
             index.entries().map(|e| e.path()).collect::<Vec<&OsStr>>()
         );
     }
+
+    #[test]
+    fn it_removes_a_directory_and_its_bookkeeping() {
+        let mut index: Index = index();
+
+        index.add(PathBuf::from("alice.txt"), random_hex(40), stat());
+        index.add(PathBuf::from("nested/bob.txt"), random_hex(40), stat());
+        index.add(
+            PathBuf::from("nested/inner/claire.txt"),
+            random_hex(40),
+            stat(),
+        );
+
+        index.remove(OsStr::new("nested"));
+
+        assert_eq!(
+            vec!["alice.txt"],
+            index.entries().map(|e| e.path()).collect::<Vec<&OsStr>>()
+        );
+        assert!(!index.tracked_directory(OsStr::new("nested")));
+        assert!(!index.tracked_directory(OsStr::new("nested/inner")));
+        assert!(!index.tracked_directory(OsStr::new("alice.txt")));
+        assert_eq!(
+            vec![PathBuf::from("alice.txt")],
+            index.child_paths(OsStr::new(""))
+        );
+    }
+
+    #[test]
+    fn it_distrusts_stat_data_as_new_as_the_index() {
+        let mut index: Index = index();
+        let stat = stat();
+        let entry = Entry::new(PathBuf::from("alice.txt"), &random_hex(40), stat.clone());
+
+        assert!(index.is_stat_clean(&entry, &stat));
+
+        index.timestamp = Some((stat.mtime() as u32, stat.mtime_nsec() as u32));
+        assert!(!index.is_stat_clean(&entry, &stat));
+
+        index.timestamp = Some((stat.mtime() as u32 + 1, 0));
+        assert!(index.is_stat_clean(&entry, &stat));
+    }
+
+    fn conflict_set() -> [Option<DatabaseEntry>; 3] {
+        let item = |oid: &str| Some(DatabaseEntry::new(oid.repeat(40), Entry::REGULAR_MODE));
+        [item("1"), None, item("3")]
+    }
+
+    #[test]
+    fn it_stores_conflicts_in_stages() {
+        let mut index: Index = index();
+
+        index.add(PathBuf::from("alice.txt"), random_hex(40), stat());
+        index.add(PathBuf::from("bob.txt"), random_hex(40), stat());
+        index.add_conflict_set(PathBuf::from("alice.txt"), conflict_set());
+
+        assert_eq!(
+            vec![("alice.txt", 1), ("alice.txt", 3), ("bob.txt", 0)],
+            index
+                .entries()
+                .map(|e| (e.path().to_str().unwrap(), e.stage()))
+                .collect::<Vec<_>>()
+        );
+        assert!(index.is_unmerged());
+        assert!(index.entry(OsStr::new("alice.txt")).is_none());
+        assert_eq!(2, index.conflict_entries(OsStr::new("alice.txt")).len());
+    }
+
+    #[test]
+    fn it_resolves_a_conflict_by_adding_the_path() {
+        let mut index: Index = index();
+
+        index.add_conflict_set(PathBuf::from("nested/alice.txt"), conflict_set());
+        index.add(PathBuf::from("nested/alice.txt"), random_hex(40), stat());
+
+        assert!(!index.is_unmerged());
+        assert_eq!(
+            vec![("nested/alice.txt", 0)],
+            index
+                .entries()
+                .map(|e| (e.path().to_str().unwrap(), e.stage()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_marks_only_tracked_paths() {
+        let mut index: Index = index();
+        index.add(PathBuf::from("config.gen"), random_hex(40), stat());
+
+        assert!(index.set_skip_worktree(OsStr::new("config.gen"), true));
+        assert!(index.set_assume_unchanged(OsStr::new("config.gen"), true));
+        assert!(!index.set_skip_worktree(OsStr::new("missing.txt"), true));
+
+        let entry = index.entry(OsStr::new("config.gen")).unwrap();
+        assert!(entry.skip_worktree() && entry.assume_unchanged());
+    }
+
+    #[test]
+    fn it_only_changes_when_the_cache_tree_does() {
+        let mut index: Index = index();
+        index.update_cache_tree(|cache| cache.set(&"1".repeat(40), 0));
+        assert!(index.changed);
+
+        index.changed = false;
+        index.update_cache_tree(|cache| cache.set(&"1".repeat(40), 0));
+        assert!(!index.changed);
+
+        index.update_cache_tree(|cache| cache.invalidate(Path::new("a.txt")));
+        assert!(index.changed);
+    }
+
+    #[test]
+    fn it_drops_unknown_extensions_once_entries_change() {
+        let mut index: Index = index();
+        index.add(PathBuf::from("alice.txt"), random_hex(40), stat());
+        index.extensions.push(Extension {
+            signature: *b"REUC",
+            data: b"data".to_vec(),
+        });
+
+        index.set_assume_unchanged(OsStr::new("alice.txt"), true);
+        assert_eq!(1, index.extensions.len());
+
+        index.add(PathBuf::from("bob.txt"), random_hex(40), stat());
+        assert!(index.extensions.is_empty());
+    }
+
+    #[test]
+    fn it_smudges_entries_as_new_as_the_written_index() {
+        let dir = TempDir::new("index_racy");
+        let old_file = dir.join("old.txt");
+        fs::write(&old_file, "old").unwrap();
+        File::options()
+            .write(true)
+            .open(&old_file)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let new_file = dir.join("new.txt");
+
+        let mut index = Index::new(dir.join("index"));
+        assert!(index.load_for_update().unwrap());
+        index.add(
+            PathBuf::from("old.txt"),
+            random_hex(40),
+            old_file.metadata().unwrap(),
+        );
+        fs::write(&new_file, "new").unwrap();
+        index.add(
+            PathBuf::from("new.txt"),
+            random_hex(40),
+            new_file.metadata().unwrap(),
+        );
+        index.write_updates();
+
+        let mut index = Index::new(dir.join("index"));
+        index.load().unwrap();
+        index.timestamp = None;
+        let clean = |name: &str, path: &Path| {
+            index.is_stat_clean(
+                index.entry(OsStr::new(name)).unwrap(),
+                &path.metadata().unwrap(),
+            )
+        };
+        assert!(clean("old.txt", &old_file));
+        assert!(!clean("new.txt", &new_file));
+    }
+
+    #[test]
+    fn it_adds_intended_files_without_trusting_executable_bits() {
+        let dir = TempDir::new("index_intent");
+        let script = dir.join("script.sh");
+        fs::write(&script, "#!/bin/sh").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut index: Index = index();
+        index.file_mode = false;
+        index.add_intent_to_add(PathBuf::from("script.sh"), script.metadata().unwrap());
+
+        let entry = index.entry(OsStr::new("script.sh")).unwrap();
+        assert!(entry.intent_to_add());
+        assert_eq!(Entry::EMPTY_BLOB_OID, entry.oid());
+        assert_eq!(Entry::REGULAR_MODE, entry.mode());
+    }
+
+    #[test]
+    fn it_marks_refreshed_entries_valid_without_rewriting_clean_stat_data() {
+        let dir = TempDir::new("index_refresh");
+        let file = dir.join("file.txt");
+        fs::write(&file, "content").unwrap();
+        let stat = file.metadata().unwrap();
+
+        let mut index: Index = index();
+        index.add(PathBuf::from("file.txt"), "1".repeat(40), stat.clone());
+        index.changed = false;
+        index.update_entry_stat(OsStr::new("file.txt"), &stat);
+        assert!(!index.changed);
+
+        // Once a filesystem monitor is in use, a clean entry is worth recording as valid
+        index.fsmonitor_token = Some(String::from("token"));
+        index.update_entry_stat(OsStr::new("file.txt"), &stat);
+        assert!(index.changed);
+        assert!(index
+            .entry(OsStr::new("file.txt"))
+            .unwrap()
+            .fsmonitor_valid());
+    }
 }
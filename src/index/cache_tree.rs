@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeMap,
+    error::{self, Error},
+    ffi::{OsStr, OsString},
+    fmt,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path},
+    str,
+};
+
+// The TREE index extension: the oids of trees built from the index on a previous commit, so
+// that trees whose entries haven't changed since don't need to be rebuilt
+//
+// Each node is serialized depth first as `name\0entry_count subtree_count\n` followed by the
+// 20-byte oid when the node is valid, with an entry count of -1 marking an invalidated node.
+#[derive(Clone, PartialEq)]
+pub struct CacheTree {
+    entry_count: i32,
+    oid: Option<String>,
+    subtrees: BTreeMap<OsString, CacheTree>,
+}
+
+impl CacheTree {
+    pub const SIGNATURE: &'static [u8; 4] = b"TREE";
+    const OID_SIZE: usize = 20;
+
+    pub fn new() -> CacheTree {
+        CacheTree {
+            entry_count: -1,
+            oid: None,
+            subtrees: BTreeMap::new(),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<CacheTree, Box<dyn error::Error>> {
+        let mut rest = data;
+        let (_, root) = CacheTree::parse_node(&mut rest)?;
+        if !rest.is_empty() {
+            return Err(InvalidCacheTree.into());
+        }
+        Ok(root)
+    }
+
+    fn parse_node(data: &mut &[u8]) -> Result<(OsString, CacheTree), Box<dyn error::Error>> {
+        let null = data.iter().position(|&b| b == 0).ok_or(InvalidCacheTree)?;
+        let name = OsStr::from_bytes(&data[..null]).to_os_string();
+        *data = &data[null + 1..];
+
+        let newline = data
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(InvalidCacheTree)?;
+        let counts = str::from_utf8(&data[..newline])?;
+        *data = &data[newline + 1..];
+
+        let mut counts = counts.split(' ');
+        let entry_count: i32 = counts.next().ok_or(InvalidCacheTree)?.parse()?;
+        let subtree_count: usize = counts.next().ok_or(InvalidCacheTree)?.parse()?;
+
+        let oid = if entry_count >= 0 {
+            if data.len() < CacheTree::OID_SIZE {
+                return Err(InvalidCacheTree.into());
+            }
+            let oid = hex::encode(&data[..CacheTree::OID_SIZE]);
+            *data = &data[CacheTree::OID_SIZE..];
+            Some(oid)
+        } else {
+            None
+        };
+
+        let mut subtrees = BTreeMap::new();
+        for _i in 0..subtree_count {
+            let (name, subtree) = CacheTree::parse_node(data)?;
+            subtrees.insert(name, subtree);
+        }
+
+        Ok((
+            name,
+            CacheTree {
+                entry_count,
+                oid,
+                subtrees,
+            },
+        ))
+    }
+
+    pub fn to_string(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_node(OsStr::new(""), &mut bytes);
+        bytes
+    }
+
+    fn write_node(&self, name: &OsStr, bytes: &mut Vec<u8>) {
+        bytes.extend(name.as_bytes());
+        bytes.push(0);
+        bytes.extend(format!("{} {}\n", self.entry_count, self.subtrees.len()).into_bytes());
+        if let (true, Some(oid)) = (self.is_valid(), &self.oid) {
+            bytes.append(&mut hex::decode(oid).expect("Failed to convert oid to bytes"));
+        }
+        for (name, subtree) in &self.subtrees {
+            subtree.write_node(name, bytes);
+        }
+    }
+
+    // Nothing worth writing out if no tree has ever been cached
+    pub fn is_empty(&self) -> bool {
+        !self.is_valid() && self.subtrees.is_empty()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.entry_count >= 0 && self.oid.is_some()
+    }
+
+    pub fn oid(&self) -> Option<&str> {
+        if self.is_valid() {
+            self.oid.as_deref()
+        } else {
+            None
+        }
+    }
+
+    pub fn entry_count(&self) -> i32 {
+        self.entry_count
+    }
+
+    pub fn set(&mut self, oid: &str, entry_count: usize) {
+        self.oid = Some(oid.to_string());
+        self.entry_count = entry_count as i32;
+    }
+
+    pub fn subtree(&self, name: &OsStr) -> Option<&CacheTree> {
+        self.subtrees.get(name)
+    }
+
+    pub fn subtree_mut(&mut self, name: &OsStr) -> &mut CacheTree {
+        self.subtrees.entry(name.to_os_string()).or_default()
+    }
+
+    pub fn retain_subtrees<F>(&mut self, mut keep: F)
+    where
+        F: FnMut(&OsStr) -> bool,
+    {
+        self.subtrees.retain(|name, _| keep(name));
+    }
+
+    // Invalidates every tree containing `path`, and forgets any cached tree at `path` itself in
+    // case a directory there has been replaced by a file
+    pub fn invalidate(&mut self, path: &Path) {
+        let components: Vec<&OsStr> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        self.invalidate_components(&components);
+    }
+
+    fn invalidate_components(&mut self, components: &[&OsStr]) {
+        self.entry_count = -1;
+        self.oid = None;
+
+        match components {
+            [] => {}
+            [name] => {
+                self.subtrees.remove(*name);
+            }
+            [name, rest @ ..] => {
+                if let Some(subtree) = self.subtrees.get_mut(*name) {
+                    subtree.invalidate_components(rest);
+                }
+            }
+        }
+    }
+}
+
+impl Default for CacheTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug)]
+struct InvalidCacheTree;
+impl Error for InvalidCacheTree {}
+impl fmt::Display for InvalidCacheTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "corrupt cache tree extension in index")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache_tree() -> CacheTree {
+        let mut root = CacheTree::new();
+        root.subtree_mut(OsStr::new("lib"))
+            .subtree_mut(OsStr::new("nested"))
+            .set(&"1".repeat(40), 2);
+        root.subtree_mut(OsStr::new("lib")).set(&"2".repeat(40), 3);
+        root.subtree_mut(OsStr::new("docs")).set(&"3".repeat(40), 1);
+        root.set(&"4".repeat(40), 5);
+        root
+    }
+
+    #[test]
+    fn it_round_trips_through_the_extension_format() {
+        let root = cache_tree();
+        let parsed = CacheTree::parse(&root.to_string()).unwrap();
+
+        assert_eq!(root.to_string(), parsed.to_string());
+        assert_eq!(Some("4".repeat(40).as_str()), parsed.oid());
+    }
+
+    #[test]
+    fn it_invalidates_only_the_trees_along_a_path() {
+        let mut root = cache_tree();
+
+        root.invalidate(Path::new("lib/nested/file.txt"));
+
+        assert!(!root.is_valid());
+        assert!(!root.subtree_mut(OsStr::new("lib")).is_valid());
+        assert!(root.subtree_mut(OsStr::new("docs")).is_valid());
+        assert!(!root
+            .subtree_mut(OsStr::new("lib"))
+            .subtree_mut(OsStr::new("nested"))
+            .is_valid());
+    }
+
+    #[test]
+    fn it_forgets_a_tree_replaced_by_a_file() {
+        let mut root = cache_tree();
+
+        root.invalidate(Path::new("lib"));
+
+        assert!(root.subtree_mut(OsStr::new("lib")).is_empty());
+        assert!(root.subtree_mut(OsStr::new("docs")).is_valid());
+    }
+}
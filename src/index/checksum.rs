@@ -1,52 +1,61 @@
 use std::{
     error::{self, Error},
     fmt,
-    fs::File,
-    io::Read,
 };
 
 use sha1::{Digest, Sha1};
 
 use super::super::lockfile::Lockfile;
 
-pub struct Checksum {
-    file: File,
-    digest: Sha1,
+// The contents of an index file, read in one go and checked against the SHA-1 at its end before
+// anything is parsed, so every read after that only needs to stay within the data
+pub struct Checksum<'a> {
+    data: &'a [u8],
+    position: usize,
 }
 
-impl Checksum {
+impl<'a> Checksum<'a> {
     const CHECKSUM_SIZE: usize = 20;
 
-    pub fn new(file: File) -> Checksum {
-        Checksum {
-            file,
-            digest: Sha1::new(),
+    pub fn new(data: &'a [u8]) -> Result<Checksum<'a>, Box<dyn error::Error>> {
+        let end = data
+            .len()
+            .checked_sub(Checksum::CHECKSUM_SIZE)
+            .ok_or(TooShort)?;
+        if Sha1::digest(&data[..end]).as_slice() != &data[end..] {
+            return Err(Mismatch.into());
         }
-    }
 
-    pub fn read(&mut self, size: usize) -> Result<Vec<u8>, Box<dyn error::Error>> {
-        let mut buffer = vec![0; size];
-        let bytesize = self.file.read(&mut buffer)?;
+        Ok(Checksum {
+            data: &data[..end],
+            position: 0,
+        })
+    }
 
-        if bytesize != size {
-            Err(EndOfFile.into())
-        } else {
-            let data = buffer;
-            self.digest.update(&data);
-            Ok(data)
-        }
+    // Whether there's more data to read before the trailing checksum
+    pub fn has_more_data(&self) -> bool {
+        self.position < self.data.len()
     }
 
-    pub fn verify_checksum(&mut self) -> Result<(), Box<dyn error::Error>> {
-        let mut buffer = [0; Checksum::CHECKSUM_SIZE];
-        self.file.read_exact(&mut buffer)?;
+    pub fn read(&mut self, size: usize) -> Result<&'a [u8], Box<dyn error::Error>> {
+        let end = self
+            .position
+            .checked_add(size)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(EndOfData(self.position))?;
 
-        let data = buffer;
-        if data == self.digest.finalize_reset().as_slice() {
-            Ok(())
-        } else {
-            Err(Invalid.into())
-        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    // Reads up to and including the next NUL byte, for data with no recorded length
+    pub fn read_until_nul(&mut self) -> Result<&'a [u8], Box<dyn error::Error>> {
+        let length = self.data[self.position..]
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(EndOfData(self.position))?;
+        self.read(length + 1)
     }
 }
 
@@ -56,7 +65,7 @@ pub struct ChecksumWriter<'a> {
 }
 
 impl ChecksumWriter<'_> {
-    pub fn new(file: &mut Lockfile) -> ChecksumWriter {
+    pub fn new(file: &mut Lockfile) -> ChecksumWriter<'_> {
         ChecksumWriter {
             file,
             digest: Sha1::new(),
@@ -75,20 +84,60 @@ impl ChecksumWriter<'_> {
 }
 
 #[derive(Debug)]
-struct EndOfFile;
-impl Error for EndOfFile {}
-impl fmt::Display for EndOfFile {
+struct TooShort;
+impl Error for TooShort {}
+impl fmt::Display for TooShort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "index file smaller than expected")
+    }
+}
+
+#[derive(Debug)]
+struct Mismatch;
+impl Error for Mismatch {}
+impl fmt::Display for Mismatch {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(f, "bad index file sha1 signature")
     }
 }
 
-// TODO: Maybe combine with invalid in index.rs
 #[derive(Debug)]
-struct Invalid;
-impl Error for Invalid {}
-impl fmt::Display for Invalid {
+struct EndOfData(usize);
+impl Error for EndOfData {}
+impl fmt::Display for EndOfData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(f, "index data ends unexpectedly at byte {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_checksum(data: &[u8]) -> Vec<u8> {
+        let mut file = data.to_vec();
+        file.extend(Sha1::digest(data).as_slice());
+        file
+    }
+
+    #[test]
+    fn it_refuses_data_not_matching_its_checksum() {
+        let mut file = with_checksum(b"DIRC");
+        file[0] = b'X';
+
+        assert!(Checksum::new(&file).is_err());
+        assert!(Checksum::new(b"short").is_err());
+    }
+
+    #[test]
+    fn it_never_reads_past_the_data() {
+        let file = with_checksum(b"abc\0de");
+        let mut reader = Checksum::new(&file).unwrap();
+
+        assert_eq!(b"abc\0", reader.read_until_nul().unwrap());
+        assert!(reader.read(3).is_err());
+        assert!(reader.read_until_nul().is_err());
+        assert_eq!(b"de", reader.read(2).unwrap());
+        assert!(!reader.has_more_data());
     }
 }
@@ -1,14 +1,16 @@
 use std::{
     cmp,
     convert::TryFrom,
-    error::Error,
+    error::{self, Error},
     ffi::{OsStr, OsString},
+    fmt,
     fs::Metadata,
     os::unix::{ffi::OsStrExt, fs::MetadataExt},
     path::{Components, Path, PathBuf},
 };
 
 use super::super::database::tree::{OidAndMode, TreeEntry};
+use super::checksum::Checksum;
 
 // TODO: Remove this Clone?
 #[derive(Clone)]
@@ -25,7 +27,11 @@ pub struct Entry {
     size: u32,
     oid: String,
     flags: u16,
+    extended_flags: u16,
     path: PathBuf,
+    // Set when the filesystem monitor vouches that the file hasn't changed, which is only kept
+    // in memory and the FSMN extension
+    fsmonitor_valid: bool,
 }
 
 impl Entry {
@@ -33,57 +39,199 @@ impl Entry {
 
     pub const REGULAR_MODE: u32 = 0o100644;
     pub const EXECUTABLE_MODE: u32 = 0o100755;
+    pub const SYMLINK_MODE: u32 = 0o120000;
+    pub const GITLINK_MODE: u32 = 0o160000;
     pub const MAX_PATH_SIZE: u16 = 0xfff;
     pub const ENTRY_BLOCK: usize = 8;
 
-    pub fn new(pathname: PathBuf, oid: &str, stat: Metadata) -> Result<Entry, Box<dyn Error>> {
-        let path = pathname;
-        let mode = match stat.mode() {
-            Entry::EXECUTABLE_MODE => Entry::EXECUTABLE_MODE,
-            _ => Entry::REGULAR_MODE,
+    // Size of the stat data, oid and flags that start every entry
+    const HEADER_SIZE: usize = 62;
+
+    // Merge stages: 0 for a normal entry, and 1 to 3 for the base, ours and theirs versions of a
+    // conflicted path
+    pub const STAGE_MASK: u16 = 0x3000;
+    pub const STAGE_SHIFT: u16 = 12;
+
+    // Set by `update-index --assume-unchanged` to trust that the file matches the entry without
+    // looking at it
+    pub const ASSUME_VALID_FLAG: u16 = 0x8000;
+
+    // Set in the flags when a second 16-bit word of flags follows, which needs index version 3
+    const EXTENDED_FLAG: u16 = 0x4000;
+    pub const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+    pub const INTENT_TO_ADD_FLAG: u16 = 0x2000;
+
+    pub const EMPTY_BLOB_OID: &'static str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+    pub fn new(pathname: PathBuf, oid: &str, stat: Metadata) -> Entry {
+        let mut entry = Entry::from_database(pathname, oid, Entry::mode_for_stat(&stat));
+        entry.update_stat(&stat);
+        entry
+    }
+
+    // Records the file's current stat data, e.g. once its content has been found unchanged.
+    //
+    // Like git, fields wider than the index's 32 bits are truncated: sizes over 4 GiB, 64-bit
+    // inode numbers and times before 1970 or after 2106 wrap around. The stat data only has to
+    // show whether a file changed, and it's compared the same way in `stat_unchanged`.
+    pub fn update_stat(&mut self, stat: &Metadata) {
+        self.ctime = stat.ctime() as u32;
+        self.ctime_nsec = stat.ctime_nsec() as u32;
+        self.mtime = stat.mtime() as u32;
+        self.mtime_nsec = stat.mtime_nsec() as u32;
+        self.dev = stat.dev() as u32;
+        self.ino = stat.ino() as u32;
+        self.uid = stat.uid();
+        self.gid = stat.gid();
+        self.size = stat.size() as u32;
+    }
+
+    // Whether the file could still have the entry's content. A size of zero is recorded when the
+    // size isn't known, so only a different type or nonzero size proves it changed. Entries only
+    // added with intent to add stand for no content at all, so never match.
+    //
+    // Without `file_mode`, the filesystem's executable bits can't be trusted and only the type of
+    // file is compared, as with `core.fileMode=false`.
+    pub fn stat_match(&self, stat: &Metadata, file_mode: bool) -> bool {
+        let mode = Entry::mode_for_stat(stat);
+        let mode_matches = if !file_mode && Entry::is_regular(self.mode()) {
+            Entry::is_regular(mode)
+        } else {
+            self.mode() == mode
         };
-        let flags = match u16::try_from(path.as_os_str().len()) {
+
+        !self.intent_to_add() && mode_matches && (self.size == 0 || self.size == stat.size() as u32)
+    }
+
+    // Whether all the recorded stat data still matches the file. The directory of a submodule
+    // says nothing about which commit it has checked out, so those never match.
+    pub fn stat_unchanged(&self, stat: &Metadata, file_mode: bool) -> bool {
+        self.stat_match(stat, file_mode)
+            && self.mode() != Entry::GITLINK_MODE
+            && self.size == stat.size() as u32
+            && self.mtime == stat.mtime() as u32
+            && self.mtime_nsec == stat.mtime_nsec() as u32
+            && self.ctime == stat.ctime() as u32
+            && self.ctime_nsec == stat.ctime_nsec() as u32
+            && self.ino == stat.ino() as u32
+            && self.uid == stat.uid()
+            && self.gid == stat.gid()
+    }
+
+    // Whether the file was modified no earlier than the given time, in which case it could have
+    // changed again within the same timestamp without its stat data showing it
+    pub fn modified_since(&self, seconds: u32, nanoseconds: u32) -> bool {
+        (self.mtime, self.mtime_nsec) >= (seconds, nanoseconds)
+    }
+
+    // Forgets the recorded size so the file's content is checked the next time it's compared
+    pub fn smudge(&mut self) {
+        self.size = 0;
+    }
+
+    // Whether the mode is that of a file, executable or not
+    pub fn is_regular(mode: u32) -> bool {
+        mode == Entry::REGULAR_MODE || mode == Entry::EXECUTABLE_MODE
+    }
+
+    // Only submodules are added to the index as directories, and files are executable when their
+    // owner can execute them, whatever the other permission bits say
+    pub fn mode_for_stat(stat: &Metadata) -> u32 {
+        Entry::canonical_mode(stat.mode())
+    }
+
+    // The mode git would record for a file of the given mode: only the type and whether the
+    // owner can execute it are kept
+    pub fn canonical_mode(mode: u32) -> u32 {
+        match mode & 0o170000 {
+            0o120000 => Entry::SYMLINK_MODE,
+            0o040000 | 0o160000 => Entry::GITLINK_MODE,
+            _ if mode & 0o100 != 0 => Entry::EXECUTABLE_MODE,
+            _ => Entry::REGULAR_MODE,
+        }
+    }
+
+    // Entries read from a tree have no stat data, so it's zeroed as git does until the file is
+    // next added from the workspace
+    pub fn from_database(pathname: PathBuf, oid: &str, mode: u32) -> Entry {
+        let flags = match u16::try_from(pathname.as_os_str().len()) {
             Ok(path_len) => cmp::min(path_len, Entry::MAX_PATH_SIZE),
             Err(_) => Entry::MAX_PATH_SIZE,
         };
 
-        Ok(Entry {
-            ctime: u32::try_from(stat.ctime())?,
-            ctime_nsec: u32::try_from(stat.ctime_nsec())?,
-            mtime: u32::try_from(stat.mtime())?,
-            mtime_nsec: u32::try_from(stat.mtime_nsec())?,
-            dev: u32::try_from(stat.dev())?,
-            ino: u32::try_from(stat.ino())?,
+        Entry {
+            ctime: 0,
+            ctime_nsec: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            dev: 0,
+            ino: 0,
             mode,
-            uid: stat.uid(),
-            gid: stat.gid(),
-            size: u32::try_from(stat.size())?,
+            uid: 0,
+            gid: 0,
+            size: 0,
             oid: oid.to_string(),
             flags,
-            path,
-        })
+            extended_flags: 0,
+            path: pathname,
+            fsmonitor_valid: false,
+        }
     }
 
-    pub fn from(entry: Vec<u8>) -> Result<Entry, Box<dyn Error>> {
-        // TODO: Tidy up
+    // Reads the next entry from an index, where version 4 stores each path as the number of
+    // bytes to drop from the end of the previous entry's path followed by the bytes to append
+    pub fn read(
+        reader: &mut Checksum<'_>,
+        version: u32,
+        previous_path: &[u8],
+    ) -> Result<Entry, Box<dyn error::Error>> {
+        let header = reader.read(Entry::HEADER_SIZE)?;
+        let flags = u16::from_be_bytes(clone_into_array(&header[60..62]));
+        let mut size = Entry::HEADER_SIZE;
+
+        let extended_flags = if flags & Entry::EXTENDED_FLAG != 0 {
+            if version < 3 {
+                return Err(InvalidEntry.into());
+            }
+            size += 2;
+            u16::from_be_bytes(clone_into_array(reader.read(2)?))
+        } else {
+            0
+        };
+
+        let path = if version >= 4 {
+            let strip = read_varint(reader)?;
+            let keep = previous_path.len().checked_sub(strip).ok_or(InvalidEntry)?;
+            let mut path = previous_path[..keep].to_vec();
+            path.extend(reader.read_until_nul()?);
+            path.pop();
+            path
+        } else {
+            let mut path = reader.read_until_nul()?.to_vec();
+            // Entries are padded with one to eight NULs to a multiple of eight bytes
+            size += path.len();
+            let padding = (Entry::ENTRY_BLOCK - size % Entry::ENTRY_BLOCK) % Entry::ENTRY_BLOCK;
+            reader.read(padding)?;
+            path.pop();
+            path
+        };
+
         Ok(Entry {
-            ctime: u32::from_be_bytes(clone_into_array(&entry[0..4])),
-            ctime_nsec: u32::from_be_bytes(clone_into_array(&entry[4..8])),
-            mtime: u32::from_be_bytes(clone_into_array(&entry[8..12])),
-            mtime_nsec: u32::from_be_bytes(clone_into_array(&entry[12..16])),
-            dev: u32::from_be_bytes(clone_into_array(&entry[16..20])),
-            ino: u32::from_be_bytes(clone_into_array(&entry[20..24])),
-            mode: u32::from_be_bytes(clone_into_array(&entry[24..28])),
-            uid: u32::from_be_bytes(clone_into_array(&entry[28..32])),
-            gid: u32::from_be_bytes(clone_into_array(&entry[32..36])),
-            size: u32::from_be_bytes(clone_into_array(&entry[36..40])),
-            oid: hex::encode(entry[40..60].to_owned()),
-            flags: u16::from_be_bytes(clone_into_array(&entry[60..62])),
-            path: PathBuf::from(
-                String::from_utf8(entry[62..].to_owned())
-                    .unwrap()
-                    .trim_matches(char::from(0)),
-            ),
+            ctime: u32::from_be_bytes(clone_into_array(&header[0..4])),
+            ctime_nsec: u32::from_be_bytes(clone_into_array(&header[4..8])),
+            mtime: u32::from_be_bytes(clone_into_array(&header[8..12])),
+            mtime_nsec: u32::from_be_bytes(clone_into_array(&header[12..16])),
+            dev: u32::from_be_bytes(clone_into_array(&header[16..20])),
+            ino: u32::from_be_bytes(clone_into_array(&header[20..24])),
+            mode: u32::from_be_bytes(clone_into_array(&header[24..28])),
+            uid: u32::from_be_bytes(clone_into_array(&header[28..32])),
+            gid: u32::from_be_bytes(clone_into_array(&header[32..36])),
+            size: u32::from_be_bytes(clone_into_array(&header[36..40])),
+            oid: hex::encode(&header[40..60]),
+            flags: flags & !Entry::EXTENDED_FLAG,
+            extended_flags,
+            path: PathBuf::from(OsStr::from_bytes(&path)),
+            fsmonitor_valid: false,
         })
     }
 
@@ -91,7 +239,76 @@ impl Entry {
         self.path.as_os_str()
     }
 
-    pub fn to_string(&self) -> Vec<u8> {
+    pub fn stage(&self) -> u16 {
+        (self.flags & Entry::STAGE_MASK) >> Entry::STAGE_SHIFT
+    }
+
+    pub fn set_stage(&mut self, stage: u16) {
+        self.flags =
+            (self.flags & !Entry::STAGE_MASK) | ((stage << Entry::STAGE_SHIFT) & Entry::STAGE_MASK);
+    }
+
+    // Entries are ordered by path and then stage, so every stage of a path can be stored
+    pub fn key(&self) -> (OsString, u16) {
+        (self.path.as_os_str().to_owned(), self.stage())
+    }
+
+    pub fn has_extended_flags(&self) -> bool {
+        self.extended_flags != 0
+    }
+
+    pub fn assume_unchanged(&self) -> bool {
+        self.flags & Entry::ASSUME_VALID_FLAG != 0
+    }
+
+    pub fn set_assume_unchanged(&mut self, assume_unchanged: bool) {
+        if assume_unchanged {
+            self.flags |= Entry::ASSUME_VALID_FLAG;
+        } else {
+            self.flags &= !Entry::ASSUME_VALID_FLAG;
+        }
+    }
+
+    // Skip-worktree entries stand for files left out of the workspace on purpose, or left there
+    // for the user to change freely, so their files are neither compared nor updated
+    pub fn skip_worktree(&self) -> bool {
+        self.extended_flags & Entry::SKIP_WORKTREE_FLAG != 0
+    }
+
+    pub fn set_skip_worktree(&mut self, skip_worktree: bool) {
+        if skip_worktree {
+            self.extended_flags |= Entry::SKIP_WORKTREE_FLAG;
+        } else {
+            self.extended_flags &= !Entry::SKIP_WORKTREE_FLAG;
+        }
+    }
+
+    pub fn intent_to_add(&self) -> bool {
+        self.extended_flags & Entry::INTENT_TO_ADD_FLAG != 0
+    }
+
+    // Marks the path as one to be added later, which `git add -N` records with the empty blob
+    // until its content is staged
+    pub fn set_intent_to_add(&mut self) {
+        self.oid = Entry::EMPTY_BLOB_OID.to_string();
+        self.extended_flags |= Entry::INTENT_TO_ADD_FLAG;
+    }
+
+    pub fn fsmonitor_valid(&self) -> bool {
+        self.fsmonitor_valid
+    }
+
+    pub fn set_fsmonitor_valid(&mut self, valid: bool) {
+        self.fsmonitor_valid = valid;
+    }
+
+    pub fn set_mode(&mut self, mode: u32) {
+        self.mode = mode;
+    }
+
+    // Serializes the entry for an index of the given version, with `previous_path` being the
+    // path of the entry written before it
+    pub fn to_string(&self, version: u32, previous_path: &[u8]) -> Vec<u8> {
         let mut string = self.ctime.to_be_bytes().to_vec();
         string.extend(&self.ctime_nsec.to_be_bytes());
         string.extend(&self.mtime.to_be_bytes());
@@ -103,13 +320,29 @@ impl Entry {
         string.extend(&self.gid.to_be_bytes());
         string.extend(&self.size.to_be_bytes());
         string.append(&mut hex::decode(&self.oid).expect("Failed to convert oid to bytes"));
-        string.extend(&self.flags.to_be_bytes());
-        string.extend(&self.path.as_path().as_os_str().as_bytes().to_vec());
-        if string[string.len() - 1] != 0 {
-            string.append(&mut String::from("\0").into_bytes());
+        if self.has_extended_flags() {
+            string.extend(&(self.flags | Entry::EXTENDED_FLAG).to_be_bytes());
+            string.extend(&self.extended_flags.to_be_bytes());
+        } else {
+            string.extend(&self.flags.to_be_bytes());
         }
-        while string.len() % Entry::ENTRY_BLOCK != 0 {
-            string.append(&mut String::from("\0").into_bytes());
+
+        let path = self.path.as_os_str().as_bytes();
+        if version >= 4 {
+            let common = previous_path
+                .iter()
+                .zip(path)
+                .take_while(|(a, b)| a == b)
+                .count();
+            string.append(&mut encode_varint(previous_path.len() - common));
+            string.extend(&path[common..]);
+            string.push(0);
+        } else {
+            string.extend(path);
+            string.push(0);
+            while !string.len().is_multiple_of(Entry::ENTRY_BLOCK) {
+                string.push(0);
+            }
         }
         string
     }
@@ -120,7 +353,7 @@ impl TreeEntry for Entry {
         &self.path
     }
 
-    fn parent_directories(&self) -> Components {
+    fn parent_directories(&self) -> Components<'_> {
         match self.path.parent() {
             Some(parent) => parent.components(),
             None => Path::new("").components(),
@@ -144,11 +377,50 @@ impl OidAndMode for Entry {
     fn mode(&self) -> u32 {
         match self.mode {
             Entry::EXECUTABLE_MODE => Entry::EXECUTABLE_MODE,
+            Entry::SYMLINK_MODE => Entry::SYMLINK_MODE,
+            Entry::GITLINK_MODE => Entry::GITLINK_MODE,
             _ => Entry::REGULAR_MODE,
         }
     }
 }
 
+// Git's offset encoding: seven bits per byte, most significant first, with the high bit marking
+// that another byte follows and each continuation adding one so no value has two encodings
+pub(super) fn encode_varint(value: usize) -> Vec<u8> {
+    let mut value = value;
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.insert(0, 0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    bytes
+}
+
+fn read_varint(reader: &mut Checksum<'_>) -> Result<usize, Box<dyn error::Error>> {
+    let mut byte = reader.read(1)?[0];
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = reader.read(1)?[0];
+        value = value
+            .checked_add(1)
+            .and_then(|value| value.checked_mul(0x80))
+            .ok_or(InvalidEntry)?
+            | (byte & 0x7f) as usize;
+    }
+    Ok(value)
+}
+
+#[derive(Debug)]
+struct InvalidEntry;
+impl Error for InvalidEntry {}
+impl fmt::Display for InvalidEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "corrupt entry in index")
+    }
+}
+
 // TODO: Tidy, make common
 use std::convert::AsMut;
 
@@ -161,3 +433,48 @@ where
     <A as AsMut<[T]>>::as_mut(&mut a).clone_from_slice(slice);
     a
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_encodes_varints_as_git_does() {
+        assert_eq!(vec![0x00], encode_varint(0));
+        assert_eq!(vec![0x7f], encode_varint(127));
+        assert_eq!(vec![0x80, 0x00], encode_varint(128));
+        assert_eq!(vec![0x80, 0x7f], encode_varint(255));
+        assert_eq!(vec![0xff, 0x7f], encode_varint(16511));
+        assert_eq!(vec![0x80, 0x80, 0x00], encode_varint(16512));
+    }
+
+    #[test]
+    fn it_prefix_compresses_paths_in_version_4() {
+        let entry = Entry::from_database(PathBuf::from("lib/bar.rs"), &"a".repeat(40), 0o100644);
+
+        let string = entry.to_string(4, b"lib/foo/baz.rs");
+
+        assert_eq!(b"\x0abar.rs\0", &string[Entry::HEADER_SIZE..]);
+        assert_eq!(80, entry.to_string(2, b"").len());
+    }
+
+    #[test]
+    fn it_records_intent_to_add_with_the_empty_blob() {
+        let mut entry = Entry::from_database(PathBuf::from("new.txt"), &"a".repeat(40), 0o100644);
+
+        entry.set_intent_to_add();
+        let string = entry.to_string(3, b"");
+
+        assert_eq!(Entry::EMPTY_BLOB_OID, entry.oid);
+        assert_eq!(&[0x40, 0x07, 0x20, 0x00], &string[60..64]);
+    }
+
+    #[test]
+    fn it_takes_the_executable_bit_from_the_owner() {
+        assert_eq!(Entry::EXECUTABLE_MODE, Entry::canonical_mode(0o100775));
+        assert_eq!(Entry::EXECUTABLE_MODE, Entry::canonical_mode(0o100700));
+        assert_eq!(Entry::REGULAR_MODE, Entry::canonical_mode(0o100654));
+        assert_eq!(Entry::SYMLINK_MODE, Entry::canonical_mode(0o120777));
+        assert_eq!(Entry::GITLINK_MODE, Entry::canonical_mode(0o040755));
+    }
+}
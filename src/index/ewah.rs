@@ -0,0 +1,128 @@
+use std::{
+    convert::TryInto,
+    error::{self, Error},
+    fmt,
+};
+
+// Git's EWAH compressed bitmaps, as used by index extensions, are serialized as the number of
+// bits, the number of 64-bit words, the words themselves and the position of the last marker
+// word. Each marker word holds a bit value in bit 0, how many words of only that bit follow in
+// bits 1 to 32, and then how many literal words follow in bits 33 to 63.
+const RUNNING_LENGTH_BITS: u32 = 32;
+const LITERAL_BITS: u32 = 31;
+
+// Only literal words are written, which any reader accepts even though it doesn't compress
+pub fn encode(bits: &[bool]) -> Vec<u8> {
+    let mut words = vec![0u64; bits.len().div_ceil(64)];
+    for (i, _) in bits.iter().enumerate().filter(|(_, &bit)| bit) {
+        words[i / 64] |= 1 << (i % 64);
+    }
+
+    let mut markers = Vec::new();
+    let mut last_marker = 0;
+    for chunk in words.chunks((1 << LITERAL_BITS) - 1) {
+        last_marker = markers.len();
+        markers.push((chunk.len() as u64) << (1 + RUNNING_LENGTH_BITS));
+        markers.extend(chunk);
+    }
+    if markers.is_empty() {
+        markers.push(0);
+    }
+
+    let mut bytes = (bits.len() as u32).to_be_bytes().to_vec();
+    bytes.extend(&(markers.len() as u32).to_be_bytes());
+    for word in &markers {
+        bytes.extend(&word.to_be_bytes());
+    }
+    bytes.extend(&(last_marker as u32).to_be_bytes());
+    bytes
+}
+
+// Reads a bitmap from the start of `data`, leaving `data` pointing just past it
+pub fn decode(data: &mut &[u8]) -> Result<Vec<bool>, Box<dyn error::Error>> {
+    let bit_count = u32::from_be_bytes(take(data, 4)?.try_into()?) as usize;
+    let word_count = u32::from_be_bytes(take(data, 4)?.try_into()?) as usize;
+    let mut words = Vec::with_capacity(word_count.min(data.len() / 8));
+    for _i in 0..word_count {
+        words.push(u64::from_be_bytes(take(data, 8)?.try_into()?));
+    }
+    take(data, 4)?;
+
+    let mut bits = Vec::with_capacity(bit_count.min(words.len() * 64));
+    let mut words = words.into_iter();
+    while let Some(marker) = words.next() {
+        let running_bit = marker & 1 != 0;
+        let running_length = (marker >> 1) & ((1 << RUNNING_LENGTH_BITS) - 1);
+        let literal_count = marker >> (1 + RUNNING_LENGTH_BITS);
+
+        // The last word can be padded past the number of bits, but never by a whole word
+        let length = bits.len() + (running_length + literal_count) as usize * 64;
+        if length >= bit_count + 64 {
+            return Err(InvalidBitmap.into());
+        }
+
+        bits.resize(bits.len() + running_length as usize * 64, running_bit);
+        for _i in 0..literal_count {
+            let word = words.next().ok_or(InvalidBitmap)?;
+            bits.extend((0..64).map(|bit| word & (1 << bit) != 0));
+        }
+    }
+
+    if bits.len() < bit_count {
+        return Err(InvalidBitmap.into());
+    }
+    bits.truncate(bit_count);
+    Ok(bits)
+}
+
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8], InvalidBitmap> {
+    if data.len() < size {
+        return Err(InvalidBitmap);
+    }
+    let (taken, rest) = data.split_at(size);
+    *data = rest;
+    Ok(taken)
+}
+
+#[derive(Debug)]
+struct InvalidBitmap;
+impl Error for InvalidBitmap {}
+impl fmt::Display for InvalidBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "corrupt bitmap in index extension")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_literal_words() {
+        let mut bits = vec![false; 130];
+        bits[0] = true;
+        bits[64] = true;
+        bits[129] = true;
+
+        let encoded = encode(&bits);
+        let mut data = &encoded[..];
+
+        assert_eq!(bits, decode(&mut data).unwrap());
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn it_decodes_runs_of_repeated_words() {
+        // A run of two words of ones followed by one literal word, as git compresses them
+        let mut data = 130u32.to_be_bytes().to_vec();
+        data.extend(&2u32.to_be_bytes());
+        data.extend(&((1u64 << 33) | (2 << 1) | 1).to_be_bytes());
+        data.extend(&0b10u64.to_be_bytes());
+        data.extend(&0u32.to_be_bytes());
+
+        let bits = decode(&mut &data[..]).unwrap();
+
+        assert!(bits[..128].iter().all(|&bit| bit));
+        assert_eq!(vec![false, true], bits[128..]);
+    }
+}
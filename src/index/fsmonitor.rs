@@ -0,0 +1,95 @@
+use std::{
+    convert::TryInto,
+    error::{self, Error},
+    fmt, str,
+};
+
+use super::ewah;
+
+// The FSMN index extension: the token from the filesystem monitor as of when the index was
+// written, and which entries weren't known to be clean then. Only the entries marked dirty, and
+// any the monitor reports as changed since the token, need their files checking.
+//
+// Version 1 stores the token as a 64-bit timestamp and version 2 as a NUL-terminated string,
+// followed in both by the size of an EWAH compressed bitmap with a bit set for each dirty entry.
+pub struct FsMonitorExtension {
+    pub token: String,
+    pub dirty: Vec<bool>,
+}
+
+impl FsMonitorExtension {
+    pub const SIGNATURE: &'static [u8; 4] = b"FSMN";
+    const VERSION: u32 = 2;
+
+    pub fn parse(data: &[u8]) -> Result<FsMonitorExtension, Box<dyn error::Error>> {
+        let mut rest = data;
+        let version = u32::from_be_bytes(take(&mut rest, 4)?.try_into()?);
+
+        let token = match version {
+            1 => u64::from_be_bytes(take(&mut rest, 8)?.try_into()?).to_string(),
+            2 => {
+                let null = rest.iter().position(|&b| b == 0).ok_or(InvalidFsMonitor)?;
+                let token = str::from_utf8(&rest[..null])?.to_string();
+                rest = &rest[null + 1..];
+                token
+            }
+            _ => return Err(InvalidFsMonitor.into()),
+        };
+
+        let size = u32::from_be_bytes(take(&mut rest, 4)?.try_into()?) as usize;
+        let dirty = ewah::decode(&mut take(&mut rest, size)?)?;
+
+        Ok(FsMonitorExtension { token, dirty })
+    }
+
+    pub fn to_string(&self) -> Vec<u8> {
+        let bitmap = ewah::encode(&self.dirty);
+
+        let mut bytes = FsMonitorExtension::VERSION.to_be_bytes().to_vec();
+        bytes.extend(self.token.as_bytes());
+        bytes.push(0);
+        bytes.extend(&(bitmap.len() as u32).to_be_bytes());
+        bytes.extend(bitmap);
+        bytes
+    }
+}
+
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8], InvalidFsMonitor> {
+    if data.len() < size {
+        return Err(InvalidFsMonitor);
+    }
+    let (taken, rest) = data.split_at(size);
+    *data = rest;
+    Ok(taken)
+}
+
+#[derive(Debug)]
+struct InvalidFsMonitor;
+impl Error for InvalidFsMonitor {}
+impl fmt::Display for InvalidFsMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "corrupt fsmonitor extension in index")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_through_the_extension_format() {
+        let mut dirty = vec![false; 130];
+        dirty[0] = true;
+        dirty[64] = true;
+        dirty[129] = true;
+        let extension = FsMonitorExtension {
+            token: String::from("jit:1:42"),
+            dirty,
+        };
+
+        let parsed = FsMonitorExtension::parse(&extension.to_string()).unwrap();
+
+        assert_eq!("jit:1:42", parsed.token);
+        assert_eq!(extension.dirty, parsed.dirty);
+    }
+}
@@ -0,0 +1,690 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    error::{self, Error},
+    ffi::{CStr, OsStr, OsString},
+    fmt, fs,
+    fs::Metadata,
+    mem,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+};
+
+use super::super::config::Config;
+use super::super::database::{blob::Blob, Database};
+use super::super::ignore::Ignore;
+use super::super::workspace::Workspace;
+use super::entry::encode_varint;
+use super::{ewah, Index};
+
+// The UNTR index extension: the untracked files found in each directory by a previous scan, and
+// what that depended on. A directory whose stat data and `.gitignore` haven't changed since can't
+// have gained or lost any files, so its listing is reused without reading it again.
+//
+// The extension starts with a string identifying the workspace, the stat data and oids of
+// `.git/info/exclude` and `core.excludesFile`, the flags the scan ran with and the name of the
+// per-directory ignore file. Then each directory follows depth first as its untracked count,
+// subdirectory count, name and untracked names, and after them bitmaps of which directories are
+// valid, check-only and have an ignore file, along with the stat data and oids those mark.
+//
+// The cache doesn't carry over between jit and git. Git records the flags its scan ran with, and
+// its `status` runs with `DIR_SHOW_OTHER_DIRECTORIES | DIR_HIDE_EMPTY_DIRECTORIES`, listing a
+// wholly untracked directory as one name and only checking whether it has any files at all.
+// jit's scan lists every untracked file, as `ls-files -o` does, and records no flags. Each
+// throws away a cache written with the other's flags and scans everything again.
+pub struct UntrackedCache {
+    ident: Vec<u8>,
+    info_exclude: ExcludeFile,
+    excludes_file: ExcludeFile,
+    flags: u32,
+    exclude_per_dir: Vec<u8>,
+    root: Option<Directory>,
+}
+
+// The untracked files directly inside a directory, and the directories below it
+#[derive(Default)]
+struct Directory {
+    valid: bool,
+    check_only: bool,
+    stat: StatData,
+    exclude_oid: Option<String>,
+    // Nested repositories are recorded with a trailing slash
+    untracked: Vec<OsString>,
+    subdirectories: BTreeMap<OsString, Directory>,
+}
+
+// A file of ignore patterns, with a null oid when it doesn't exist
+#[derive(Default, PartialEq)]
+struct ExcludeFile {
+    stat: StatData,
+    oid: Option<String>,
+}
+
+// Stat data as the index records it, without the mode
+#[derive(Clone, Copy, Default, PartialEq)]
+struct StatData {
+    ctime: u32,
+    ctime_nsec: u32,
+    mtime: u32,
+    mtime_nsec: u32,
+    dev: u32,
+    ino: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+impl UntrackedCache {
+    pub const SIGNATURE: &'static [u8; 4] = b"UNTR";
+    const OID_SIZE: usize = 20;
+    const EXCLUDE_PER_DIR: &'static [u8] = b".gitignore";
+    // Neither showing untracked directories as a whole nor hiding empty ones
+    const FLAGS: u32 = 0;
+
+    pub fn new(root_path: &Path) -> UntrackedCache {
+        UntrackedCache {
+            ident: ident(root_path),
+            info_exclude: ExcludeFile::default(),
+            excludes_file: ExcludeFile::default(),
+            flags: UntrackedCache::FLAGS,
+            exclude_per_dir: UntrackedCache::EXCLUDE_PER_DIR.to_vec(),
+            root: None,
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<UntrackedCache, Box<dyn error::Error>> {
+        // The extension ends with a NUL, guarding the strings within it
+        let mut rest = match data.split_last() {
+            Some((0, rest)) => rest,
+            _ => return Err(InvalidUntrackedCache.into()),
+        };
+
+        let ident_size = read_varint(&mut rest)?;
+        let ident = take(&mut rest, ident_size)?.to_vec();
+        let info_exclude_stat = StatData::parse(take(&mut rest, StatData::SIZE)?)?;
+        let excludes_file_stat = StatData::parse(take(&mut rest, StatData::SIZE)?)?;
+        let flags = u32::from_be_bytes(take(&mut rest, 4)?.try_into()?);
+        let info_exclude_oid = read_oid(&mut rest)?;
+        let excludes_file_oid = read_oid(&mut rest)?;
+        let exclude_per_dir = read_string(&mut rest)?.to_vec();
+
+        let mut cache = UntrackedCache {
+            ident,
+            info_exclude: ExcludeFile {
+                stat: info_exclude_stat,
+                oid: info_exclude_oid,
+            },
+            excludes_file: ExcludeFile {
+                stat: excludes_file_stat,
+                oid: excludes_file_oid,
+            },
+            flags,
+            exclude_per_dir,
+            root: None,
+        };
+        if rest.is_empty() {
+            return Ok(cache);
+        }
+        let count = read_varint(&mut rest)?;
+        if count == 0 {
+            return Ok(cache);
+        }
+
+        let mut directories = Vec::new();
+        for _i in 0..count {
+            let untracked_count = read_varint(&mut rest)?;
+            let subdirectory_count = read_varint(&mut rest)?;
+            let name = OsStr::from_bytes(read_string(&mut rest)?).to_os_string();
+            let mut directory = Directory::default();
+            for _j in 0..untracked_count {
+                let name = read_string(&mut rest)?;
+                directory
+                    .untracked
+                    .push(OsStr::from_bytes(name).to_os_string());
+            }
+            directories.push((name, subdirectory_count, directory));
+        }
+
+        let valid = ewah::decode(&mut rest)?;
+        let check_only = ewah::decode(&mut rest)?;
+        let exclude_valid = ewah::decode(&mut rest)?;
+        for (i, (_, _, directory)) in directories.iter_mut().enumerate() {
+            if valid.get(i) == Some(&true) {
+                directory.valid = true;
+                directory.stat = StatData::parse(take(&mut rest, StatData::SIZE)?)?;
+            }
+            directory.check_only = check_only.get(i) == Some(&true);
+        }
+        for (i, (_, _, directory)) in directories.iter_mut().enumerate() {
+            if exclude_valid.get(i) == Some(&true) {
+                directory.exclude_oid = read_oid(&mut rest)?;
+            }
+        }
+        if !rest.is_empty() {
+            return Err(InvalidUntrackedCache.into());
+        }
+
+        let mut directories = directories.into_iter();
+        let (_, root) = Directory::assemble(&mut directories)?;
+        if directories.next().is_some() {
+            return Err(InvalidUntrackedCache.into());
+        }
+        cache.root = Some(root);
+        Ok(cache)
+    }
+
+    pub fn to_string(&self) -> Vec<u8> {
+        let mut bytes = encode_varint(self.ident.len());
+        bytes.extend(&self.ident);
+        bytes.extend(self.info_exclude.stat.to_bytes());
+        bytes.extend(self.excludes_file.stat.to_bytes());
+        bytes.extend(&self.flags.to_be_bytes());
+        bytes.extend(oid_bytes(&self.info_exclude.oid));
+        bytes.extend(oid_bytes(&self.excludes_file.oid));
+        bytes.extend(&self.exclude_per_dir);
+        bytes.push(0);
+
+        let root = match &self.root {
+            Some(root) => root,
+            // A count of no directories, which also serves as the final NUL
+            None => {
+                bytes.push(0);
+                return bytes;
+            }
+        };
+
+        let mut directories = Vec::new();
+        root.flatten(OsStr::new(""), &mut directories);
+        bytes.extend(encode_varint(directories.len()));
+        for (name, directory) in &directories {
+            bytes.extend(encode_varint(directory.untracked.len()));
+            bytes.extend(encode_varint(directory.subdirectories.len()));
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            for name in &directory.untracked {
+                bytes.extend(name.as_bytes());
+                bytes.push(0);
+            }
+        }
+
+        let bitmap = |bit: fn(&Directory) -> bool| {
+            let bits: Vec<bool> = directories.iter().map(|(_, d)| bit(d)).collect();
+            ewah::encode(&bits)
+        };
+        bytes.extend(bitmap(|directory| directory.valid));
+        bytes.extend(bitmap(|directory| directory.check_only));
+        bytes.extend(bitmap(|directory| directory.exclude_oid.is_some()));
+        for (_, directory) in directories.iter().filter(|(_, d)| d.valid) {
+            bytes.extend(directory.stat.to_bytes());
+        }
+        for (_, directory) in &directories {
+            if directory.exclude_oid.is_some() {
+                bytes.extend(oid_bytes(&directory.exclude_oid));
+            }
+        }
+        bytes.push(0);
+        bytes
+    }
+
+    // Adding a file to the index or removing it changes whether it's untracked, so the listing
+    // of its directory can't be reused
+    pub fn invalidate(&mut self, path: &Path) {
+        let mut directory = match &mut self.root {
+            Some(root) => root,
+            None => return,
+        };
+        for component in path.parent().into_iter().flat_map(Path::components) {
+            if let Component::Normal(name) = component {
+                directory = match directory.subdirectories.get_mut(name) {
+                    Some(subdirectory) => subdirectory,
+                    None => return,
+                };
+            }
+        }
+        directory.valid = false;
+        directory.untracked.clear();
+    }
+
+    // Forgets every directory, e.g. once the whole index has been replaced
+    pub fn clear(&mut self) {
+        self.root = None;
+    }
+
+    // Lists every untracked file that isn't ignored by the standard ignore rules, which `ignore`
+    // must be set up with, reading only the directories that changed since the last scan. Nested
+    // repositories are listed with a trailing slash. Also returns whether the cache was updated.
+    pub fn untracked_files(
+        &mut self,
+        root_path: &Path,
+        index: &Index,
+        ignore: &mut Ignore,
+    ) -> (Vec<PathBuf>, bool) {
+        let git_path = root_path.join(".git");
+        let database = Database::new(git_path.join("objects"));
+        let mut changed = false;
+
+        // Listings made somewhere else, or by a scan with other options, can't be trusted
+        let ident = ident(root_path);
+        if self.ident != ident
+            || self.flags != UntrackedCache::FLAGS
+            || self.exclude_per_dir != UntrackedCache::EXCLUDE_PER_DIR
+        {
+            *self = UntrackedCache::new(root_path);
+            changed = true;
+        }
+
+        // Global ignore rules apply everywhere, so any change to them means starting again
+        let config = Config::load(&git_path.join("config"));
+        let info_exclude = ExcludeFile::new(&git_path.join("info").join("exclude"), &database);
+        let excludes_file = match Ignore::excludes_file(&config) {
+            Some(path) => ExcludeFile::new(&path, &database),
+            None => ExcludeFile::default(),
+        };
+        if info_exclude.oid != self.info_exclude.oid || excludes_file.oid != self.excludes_file.oid
+        {
+            self.root = None;
+        }
+        if info_exclude != self.info_exclude || excludes_file != self.excludes_file {
+            self.info_exclude = info_exclude;
+            self.excludes_file = excludes_file;
+            changed = true;
+        }
+
+        let mut scan = Scan {
+            root_path,
+            index,
+            ignore,
+            database,
+            files: Vec::new(),
+            changed,
+        };
+        let mut root = self.root.take().unwrap_or_default();
+        scan.directory(Path::new(""), &mut root, false);
+        self.root = Some(root);
+
+        (scan.files, scan.changed)
+    }
+}
+
+impl Directory {
+    // Rebuilds the tree from directories listed depth first with their subdirectory counts
+    fn assemble<I>(directories: &mut I) -> Result<(OsString, Directory), InvalidUntrackedCache>
+    where
+        I: Iterator<Item = (OsString, usize, Directory)>,
+    {
+        let (name, subdirectory_count, mut directory) =
+            directories.next().ok_or(InvalidUntrackedCache)?;
+        for _i in 0..subdirectory_count {
+            let (name, subdirectory) = Directory::assemble(directories)?;
+            directory.subdirectories.insert(name, subdirectory);
+        }
+        Ok((name, directory))
+    }
+
+    fn flatten<'a>(&'a self, name: &'a OsStr, directories: &mut Vec<(&'a OsStr, &'a Directory)>) {
+        directories.push((name, self));
+        for (name, subdirectory) in &self.subdirectories {
+            subdirectory.flatten(name, directories);
+        }
+    }
+}
+
+struct Scan<'a> {
+    root_path: &'a Path,
+    index: &'a Index,
+    ignore: &'a mut Ignore,
+    database: Database,
+    files: Vec<PathBuf>,
+    changed: bool,
+}
+
+impl Scan<'_> {
+    // Lists the untracked files in `path` and below, reusing the listing in `directory` if the
+    // directory hasn't changed since it was made. A changed `.gitignore` in any directory above
+    // means the listing can't be trusted either, as its patterns apply here too.
+    fn directory(&mut self, path: &Path, directory: &mut Directory, excludes_changed: bool) {
+        // The stat data is taken first, so that changes made while reading show up next time
+        let full_path = self.root_path.join(path);
+        let stat = match full_path.symlink_metadata() {
+            Ok(stat) => StatData::new(&stat),
+            Err(_) => {
+                *directory = Directory::default();
+                self.changed = true;
+                return;
+            }
+        };
+        let exclude_oid = exclude_oid(&full_path.join(".gitignore"), &self.database);
+        let excludes_changed = excludes_changed || exclude_oid != directory.exclude_oid;
+
+        if directory.valid && !excludes_changed && directory.stat == stat && !self.is_racy(&stat) {
+            self.files
+                .extend(directory.untracked.iter().map(|name| path.join(name)));
+            for (name, subdirectory) in &mut directory.subdirectories {
+                self.directory(&path.join(name), subdirectory, false);
+            }
+            return;
+        }
+
+        let mut previous = mem::take(&mut directory.subdirectories);
+        let mut untracked = Vec::new();
+        let mut subdirectories = BTreeMap::new();
+        for entry in fs::read_dir(&full_path).into_iter().flatten().flatten() {
+            let name = entry.file_name();
+            if Workspace::IGNORED_NAMES
+                .iter()
+                .any(|ignored| name == *ignored)
+            {
+                continue;
+            }
+            let child = path.join(&name);
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+
+            // Nested repositories are listed as a whole rather than looked inside
+            if is_dir && !Workspace::is_repository(&entry.path()) {
+                if self.ignore.is_ignored(&child, true) {
+                    continue;
+                }
+                let mut subdirectory = previous.remove(&name).unwrap_or_default();
+                self.directory(&child, &mut subdirectory, excludes_changed);
+                subdirectories.insert(name, subdirectory);
+            } else if !self.index.tracked_file(child.as_os_str())
+                && !self.ignore.is_ignored(&child, is_dir)
+            {
+                let mut name = name;
+                if is_dir {
+                    name.push("/");
+                }
+                self.files.push(path.join(&name));
+                untracked.push(name);
+            }
+        }
+        untracked.sort();
+
+        *directory = Directory {
+            valid: true,
+            check_only: false,
+            stat,
+            exclude_oid,
+            untracked,
+            subdirectories,
+        };
+        self.changed = true;
+    }
+
+    // A directory changed as late as the index was written could have changed again within the
+    // same timestamp
+    fn is_racy(&self, stat: &StatData) -> bool {
+        match self.index.timestamp {
+            Some(written) => (stat.mtime, stat.mtime_nsec) >= written,
+            None => true,
+        }
+    }
+}
+
+impl ExcludeFile {
+    fn new(path: &Path, database: &Database) -> ExcludeFile {
+        match path.metadata() {
+            Ok(stat) => ExcludeFile {
+                stat: StatData::new(&stat),
+                oid: exclude_oid(path, database),
+            },
+            Err(_) => ExcludeFile::default(),
+        }
+    }
+}
+
+// The oid of an ignore file's content as a blob, if it exists
+fn exclude_oid(path: &Path, database: &Database) -> Option<String> {
+    let content = fs::read(path).ok()?;
+    Some(database.hash_object(&Blob::new(content)))
+}
+
+impl StatData {
+    const SIZE: usize = 36;
+
+    // Wider fields are truncated to 32 bits, as for index entries
+    fn new(stat: &Metadata) -> StatData {
+        StatData {
+            ctime: stat.ctime() as u32,
+            ctime_nsec: stat.ctime_nsec() as u32,
+            mtime: stat.mtime() as u32,
+            mtime_nsec: stat.mtime_nsec() as u32,
+            dev: stat.dev() as u32,
+            ino: stat.ino() as u32,
+            uid: stat.uid(),
+            gid: stat.gid(),
+            size: stat.size() as u32,
+        }
+    }
+
+    fn parse(data: &[u8]) -> Result<StatData, Box<dyn error::Error>> {
+        let fields: Vec<u32> = data
+            .chunks(4)
+            .map(|field| field.try_into().map(u32::from_be_bytes))
+            .collect::<Result<_, _>>()?;
+        match fields[..] {
+            [ctime, ctime_nsec, mtime, mtime_nsec, dev, ino, uid, gid, size] => Ok(StatData {
+                ctime,
+                ctime_nsec,
+                mtime,
+                mtime_nsec,
+                dev,
+                ino,
+                uid,
+                gid,
+                size,
+            }),
+            _ => Err(InvalidUntrackedCache.into()),
+        }
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [
+            self.ctime,
+            self.ctime_nsec,
+            self.mtime,
+            self.mtime_nsec,
+            self.dev,
+            self.ino,
+            self.uid,
+            self.gid,
+            self.size,
+        ]
+        .iter()
+        .flat_map(|field| field.to_be_bytes())
+        .collect()
+    }
+}
+
+// Identifies where the cache was made, as git does, since stat data means nothing once the
+// repository has moved or is looked at from another system
+fn ident(root_path: &Path) -> Vec<u8> {
+    let mut system = unsafe { mem::zeroed::<libc::utsname>() };
+    let system = if unsafe { libc::uname(&mut system) } == 0 {
+        unsafe { CStr::from_ptr(system.sysname.as_ptr()) }
+            .to_bytes()
+            .to_vec()
+    } else {
+        b"unknown".to_vec()
+    };
+
+    let mut ident = b"Location ".to_vec();
+    ident.extend(root_path.as_os_str().as_bytes());
+    ident.extend(b", system ");
+    ident.extend(system);
+    ident.push(0);
+    ident
+}
+
+fn oid_bytes(oid: &Option<String>) -> Vec<u8> {
+    match oid {
+        Some(oid) => hex::decode(oid).expect("Failed to convert oid to bytes"),
+        None => vec![0; UntrackedCache::OID_SIZE],
+    }
+}
+
+// An oid of all zeros stands for a file that doesn't exist
+fn read_oid(data: &mut &[u8]) -> Result<Option<String>, InvalidUntrackedCache> {
+    let oid = take(data, UntrackedCache::OID_SIZE)?;
+    if oid.iter().all(|&b| b == 0) {
+        Ok(None)
+    } else {
+        Ok(Some(hex::encode(oid)))
+    }
+}
+
+fn read_string<'a>(data: &mut &'a [u8]) -> Result<&'a [u8], InvalidUntrackedCache> {
+    let null = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(InvalidUntrackedCache)?;
+    let string = &data[..null];
+    *data = &data[null + 1..];
+    Ok(string)
+}
+
+// The varint encoding used for path prefixes in version 4 indexes
+fn read_varint(data: &mut &[u8]) -> Result<usize, InvalidUntrackedCache> {
+    let mut byte = take(data, 1)?[0];
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = take(data, 1)?[0];
+        value = value
+            .checked_add(1)
+            .and_then(|value| value.checked_mul(0x80))
+            .ok_or(InvalidUntrackedCache)?
+            | (byte & 0x7f) as usize;
+    }
+    Ok(value)
+}
+
+fn take<'a>(data: &mut &'a [u8], size: usize) -> Result<&'a [u8], InvalidUntrackedCache> {
+    if data.len() < size {
+        return Err(InvalidUntrackedCache);
+    }
+    let (taken, rest) = data.split_at(size);
+    *data = rest;
+    Ok(taken)
+}
+
+#[derive(Debug)]
+struct InvalidUntrackedCache;
+impl Error for InvalidUntrackedCache {}
+impl fmt::Display for InvalidUntrackedCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "corrupt untracked cache extension in index")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::temp_dir::TempDir;
+    use super::*;
+
+    fn directory(untracked: &[&str], exclude_oid: Option<&str>) -> Directory {
+        Directory {
+            valid: true,
+            check_only: false,
+            stat: StatData {
+                mtime: 1_600_000_000,
+                ino: 42,
+                ..StatData::default()
+            },
+            exclude_oid: exclude_oid.map(String::from),
+            untracked: untracked.iter().map(OsString::from).collect(),
+            subdirectories: BTreeMap::new(),
+        }
+    }
+
+    fn cache() -> UntrackedCache {
+        let mut lib = directory(&["new.rs"], None);
+        lib.subdirectories
+            .insert(OsString::from("nested"), directory(&["repo/"], None));
+        let mut root = directory(&["notes.txt"], Some(&"1".repeat(40)));
+        root.subdirectories.insert(OsString::from("lib"), lib);
+        root.subdirectories
+            .insert(OsString::from("docs"), directory(&[], None));
+
+        let mut cache = UntrackedCache::new(Path::new("/repo"));
+        cache.info_exclude.oid = Some("2".repeat(40));
+        cache.root = Some(root);
+        cache
+    }
+
+    #[test]
+    fn it_round_trips_through_the_extension_format() {
+        let cache = cache();
+        let parsed = UntrackedCache::parse(&cache.to_string()).unwrap();
+
+        assert_eq!(cache.to_string(), parsed.to_string());
+        let root = parsed.root.unwrap();
+        assert_eq!(Some("1".repeat(40)), root.exclude_oid);
+        assert_eq!(
+            vec![OsString::from("repo/")],
+            root.subdirectories[OsStr::new("lib")].subdirectories[OsStr::new("nested")].untracked
+        );
+    }
+
+    #[test]
+    fn it_invalidates_only_the_directory_holding_a_path() {
+        let mut cache = cache();
+
+        cache.invalidate(Path::new("lib/new.rs"));
+
+        let root = cache.root.unwrap();
+        assert!(root.valid);
+        assert!(!root.subdirectories[OsStr::new("lib")].valid);
+        assert!(root.subdirectories[OsStr::new("lib")].untracked.is_empty());
+        assert!(root.subdirectories[OsStr::new("lib")].subdirectories[OsStr::new("nested")].valid);
+    }
+
+    #[test]
+    fn it_reads_only_directories_that_changed_since_the_last_scan() {
+        let root_path = TempDir::new("untracked_cache");
+        for name in &[".git", "same", "changed", "ignoring"] {
+            fs::create_dir_all(root_path.join(name)).unwrap();
+        }
+        for name in &[
+            "same/a.txt",
+            "changed/b.txt",
+            "ignoring/c.log",
+            "ignoring/.gitignore",
+        ] {
+            fs::write(root_path.join(name), "").unwrap();
+        }
+
+        // Every directory is older than the index, so none of them is racy
+        let mut index = Index::new(root_path.join(".git/index"));
+        index.timestamp = Some((u32::MAX, 0));
+        let mut cache = UntrackedCache::new(&root_path);
+        let scan = |cache: &mut UntrackedCache| {
+            let mut ignore = Ignore::new(root_path.to_path_buf());
+            ignore.add_standard(&root_path.join(".git"), &Config::new());
+            let (mut files, _) = cache.untracked_files(&root_path, &index, &mut ignore);
+            files.sort();
+            files
+        };
+        scan(&mut cache);
+
+        // A listing only read from the cache still has a name that isn't on disk
+        let root = cache.root.as_mut().unwrap();
+        for name in &["same", "changed", "ignoring"] {
+            let directory = root.subdirectories.get_mut(OsStr::new(name)).unwrap();
+            directory.untracked.push(OsString::from("cached.txt"));
+        }
+        fs::write(root_path.join("changed/new.txt"), "").unwrap();
+        fs::write(root_path.join("ignoring/.gitignore"), "*.log\n").unwrap();
+
+        let files: Vec<PathBuf> = [
+            "changed/b.txt",
+            "changed/new.txt",
+            "ignoring/.gitignore",
+            "same/a.txt",
+            "same/cached.txt",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        assert_eq!(files, scan(&mut cache));
+    }
+}
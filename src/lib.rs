@@ -1,6 +1,15 @@
+pub mod command;
+pub mod config;
 pub mod database;
 pub mod entry;
+pub mod fsmonitor;
+pub mod ignore;
 pub mod index;
 pub mod lockfile;
 pub mod refs;
+pub mod revision;
+pub mod signing;
+pub mod submodule;
+#[cfg(test)]
+mod temp_dir;
 pub mod workspace;
@@ -1,7 +1,7 @@
 use std::{
     error::{self, Error},
     fmt,
-    fs::{self, File, OpenOptions},
+    fs::{self, File, Metadata, OpenOptions},
     io::{ErrorKind, Write},
     path::{Path, PathBuf},
 };
@@ -13,9 +13,12 @@ pub struct Lockfile {
 }
 
 impl Lockfile {
+    // The lock goes beside the file, with `.lock` added to its whole name, so that files which
+    // only differ in their extensions have locks of their own
     pub fn new(path: PathBuf) -> Lockfile {
-        let mut lock_path = path.clone();
-        lock_path.set_extension("lock");
+        let mut lock_path = path.clone().into_os_string();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
         Lockfile {
             file_path: path,
             lock_path,
@@ -36,8 +39,8 @@ impl Lockfile {
                 }
                 Err(error) => match error.kind() {
                     ErrorKind::AlreadyExists => Ok(false),
-                    ErrorKind::NotFound => Err(MissingParent.into()),
-                    ErrorKind::PermissionDenied => Err(NoPermission.into()),
+                    ErrorKind::NotFound => Err(MissingParent(self.lock_path.clone()).into()),
+                    ErrorKind::PermissionDenied => Err(NoPermission(self.lock_path.clone()).into()),
                     _ => {
                         panic!("Error when creating file: {:?}", error);
                     }
@@ -54,7 +57,15 @@ impl Lockfile {
                 lock.write_all(bytes).expect("Error writing data to file");
                 Ok(())
             }
-            None => Err(StaleLock),
+            None => Err(StaleLock(self.lock_path.clone())),
+        }
+    }
+
+    // The lock file's own metadata, with timestamps from the filesystem rather than the system clock
+    pub fn metadata(&self) -> Result<Metadata, Box<dyn error::Error>> {
+        match self.lock.as_ref() {
+            Some(lock) => Ok(lock.metadata()?),
+            None => Err(StaleLock(self.lock_path.clone()).into()),
         }
     }
 
@@ -65,7 +76,7 @@ impl Lockfile {
                 self.lock = None;
                 Ok(())
             }
-            None => Err(StaleLock),
+            None => Err(StaleLock(self.lock_path.clone())),
         }
     }
 
@@ -76,38 +87,82 @@ impl Lockfile {
                 self.lock = None;
                 Ok(())
             }
-            None => Err(StaleLock),
+            None => Err(StaleLock(self.lock_path.clone())),
         }
     }
 
     pub fn file_path(&self) -> &Path {
         &self.file_path
     }
+
+    pub fn lock_path(&self) -> &Path {
+        &self.lock_path
+    }
 }
 
 #[derive(Debug)]
-struct MissingParent;
+struct MissingParent(PathBuf);
 impl Error for MissingParent {}
 impl fmt::Display for MissingParent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(
+            f,
+            "Unable to create '{}': No such file or directory",
+            self.0.display()
+        )
     }
 }
 
 #[derive(Debug)]
-struct NoPermission;
+struct NoPermission(PathBuf);
 impl Error for NoPermission {}
 impl fmt::Display for NoPermission {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(
+            f,
+            "Unable to create '{}': Permission denied",
+            self.0.display()
+        )
+    }
+}
+
+// Another process holds the lock
+#[derive(Debug)]
+pub struct LockDenied(pub PathBuf);
+impl Error for LockDenied {}
+impl fmt::Display for LockDenied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to create '{}': File exists.", self.0.display())
     }
 }
 
+// The lock was used without being held, or after being committed or rolled back
 #[derive(Debug)]
-pub struct StaleLock;
+pub struct StaleLock(PathBuf);
 impl Error for StaleLock {}
 impl fmt::Display for StaleLock {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(f, "'{}' is not held", self.0.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_adds_the_lock_extension_to_the_whole_name() {
+        let lockfile = Lockfile::new(PathBuf::from("refs/heads/v1.0"));
+        assert_eq!(Path::new("refs/heads/v1.0.lock"), lockfile.lock_path);
+
+        let lockfile = Lockfile::new(PathBuf::from(".git/index"));
+        assert_eq!(Path::new(".git/index.lock"), lockfile.lock_path);
+    }
+
+    #[test]
+    fn it_refuses_to_write_without_holding_the_lock() {
+        let mut lockfile = Lockfile::new(PathBuf::from("missing/index"));
+        let error = lockfile.write(b"data").unwrap_err();
+        assert_eq!("'missing/index.lock' is not held", error.to_string());
     }
 }
@@ -1,22 +1,29 @@
-mod database;
-mod entry;
-mod index;
-mod lockfile;
-mod refs;
-mod workspace;
-
 use std::{
-    env, fs,
-    io::{self, Read},
-    path::PathBuf,
+    env,
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     process,
 };
 
-use database::{author::Author, blob::Blob, commit::Commit, tree::Tree, Database, Storable};
-use index::Index;
-use lockfile::Lockfile;
-use refs::Refs;
-use workspace::Workspace;
+use jit::{
+    command::{
+        self, branch, ls_files, read_tree::TreeList, rm, update_index,
+        workspace_check::WorkspaceCheck, PathSpec,
+    },
+    config::Config,
+    database::{author::Author, commit::Commit, tree::OidAndMode, Database, Storable},
+    fsmonitor,
+    ignore::Ignore,
+    index::Index,
+    lockfile::Lockfile,
+    refs::Refs,
+    revision::Revision,
+    signing::{self, Signer},
+    workspace::Workspace,
+};
 
 use chrono::Local;
 use structopt::StructOpt;
@@ -31,13 +38,223 @@ enum Cli {
         path: Option<std::path::PathBuf>,
     },
     /// Save changes to this repository
-    Commit {},
+    Commit {
+        /// Allow recording a commit with the same tree as its parent
+        #[structopt(long)]
+        allow_empty: bool,
+        /// Allow recording a commit with an empty message
+        #[structopt(long)]
+        allow_empty_message: bool,
+        /// Sign the commit with the configured signing program
+        #[structopt(short = "S", long)]
+        gpg_sign: bool,
+        /// Don't sign the commit, even if commit.gpgSign is set
+        #[structopt(long, conflicts_with = "gpg-sign")]
+        no_gpg_sign: bool,
+    },
+    /// Check the signature of commits
+    VerifyCommit {
+        /// The commits to verify
+        #[structopt(required = true)]
+        commits: Vec<String>,
+    },
+    /// Show the commit history
+    Log {
+        /// Check and show the signature of each signed commit
+        #[structopt(long)]
+        show_signature: bool,
+        /// The commit to start from [default: HEAD]
+        revision: Option<String>,
+    },
+    /// List, create, delete or rename branches
+    Branch {
+        /// Show the commit each branch is at
+        #[structopt(short, long)]
+        verbose: bool,
+        /// Delete branches that are merged into HEAD
+        #[structopt(short, long)]
+        delete: bool,
+        /// Delete branches whether or not they're merged
+        #[structopt(short = "D", conflicts_with = "delete")]
+        force_delete: bool,
+        /// Rename a branch, or the current branch if only the new name is given
+        #[structopt(short, long = "move", conflicts_with_all = &["delete", "force-delete"])]
+        move_: bool,
+        /// The branch to create and the commit to start it at [default: HEAD], the branches to
+        /// delete, or the old and new names of the branch to rename
+        names: Vec<String>,
+    },
     /// Add files to the index
     Add {
+        /// Record only that the paths will be added later, so they show up in diffs
+        #[structopt(short = "N", long)]
+        intent_to_add: bool,
         /// The path of the file to add to the index
         #[structopt(parse(from_os_str))]
         paths: Vec<std::path::PathBuf>,
     },
+    /// Remove files from the index and the working tree
+    Rm {
+        /// Only remove paths from the index, keeping the files in the working tree
+        #[structopt(long)]
+        cached: bool,
+        /// Remove files even if they have changes that would be lost
+        #[structopt(short, long)]
+        force: bool,
+        /// Remove directories and everything under them
+        #[structopt(short)]
+        recursive: bool,
+        /// The paths to remove
+        #[structopt(parse(from_os_str), required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// List the contents of a tree object
+    LsTree {
+        /// Recurse into subtrees
+        #[structopt(short = "r")]
+        recursive: bool,
+        /// Show tree entries even when recursing into them
+        #[structopt(short = "t")]
+        show_trees: bool,
+        /// Only show tree entries
+        #[structopt(short = "d")]
+        trees_only: bool,
+        /// Show the size of blob objects
+        #[structopt(short = "l", long = "long")]
+        long: bool,
+        /// Only show the path of each entry
+        #[structopt(long, alias = "name-status")]
+        name_only: bool,
+        /// The tree, or commit whose tree, to list
+        tree_ish: String,
+        /// Only show entries matching these paths
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+    /// Show information about files in the index and the working tree
+    LsFiles {
+        /// Show files in the index, which is the default
+        #[structopt(short, long)]
+        cached: bool,
+        /// Show the mode, oid and stage number of index entries
+        #[structopt(short, long)]
+        stage: bool,
+        /// Show files deleted from the working tree
+        #[structopt(short, long)]
+        deleted: bool,
+        /// Show files changed in the working tree, including deleted ones
+        #[structopt(short, long)]
+        modified: bool,
+        /// Show untracked files
+        #[structopt(short, long)]
+        others: bool,
+        /// Only show ignored files
+        #[structopt(short, long)]
+        ignored: bool,
+        /// Ignore untracked files matching this pattern
+        #[structopt(short = "x", long, number_of_values = 1)]
+        exclude: Vec<String>,
+        /// Read ignore patterns from this file
+        #[structopt(short = "X", long, parse(from_os_str), number_of_values = 1)]
+        exclude_from: Vec<PathBuf>,
+        /// Use .gitignore files, .git/info/exclude and core.excludesFile
+        #[structopt(long)]
+        exclude_standard: bool,
+        /// Terminate paths with NUL rather than newline, and don't quote them
+        #[structopt(short = "z")]
+        nul_terminated: bool,
+        /// Only show files under these paths
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+    /// Register file contents and flags in the index
+    UpdateIndex {
+        /// Add files that aren't in the index yet
+        #[structopt(long)]
+        add: bool,
+        /// Remove files that are missing from the working tree from the index
+        #[structopt(long)]
+        remove: bool,
+        /// Stage an object directly, given as <mode>,<oid>,<path>
+        #[structopt(long, number_of_values = 1)]
+        cacheinfo: Vec<String>,
+        /// Set (+x) or clear (-x) the executable bit of the files
+        #[structopt(long, allow_hyphen_values = true, possible_values = &["+x", "-x"])]
+        chmod: Option<String>,
+        /// Trust that the files haven't changed rather than checking them
+        #[structopt(long)]
+        assume_unchanged: bool,
+        /// Check the files for changes again
+        #[structopt(long, conflicts_with = "assume_unchanged")]
+        no_assume_unchanged: bool,
+        /// Leave the files alone in the working tree, treating them as unchanged
+        #[structopt(long)]
+        skip_worktree: bool,
+        /// Compare and update the files in the working tree again
+        #[structopt(long, conflicts_with = "skip_worktree")]
+        no_skip_worktree: bool,
+        /// Record untracked files in the index so later scans only read changed directories
+        #[structopt(long)]
+        untracked_cache: bool,
+        /// Stop recording untracked files in the index
+        #[structopt(long, conflicts_with = "untracked_cache")]
+        no_untracked_cache: bool,
+        /// The files to update
+        #[structopt(parse(from_os_str))]
+        paths: Vec<PathBuf>,
+    },
+    /// Create a tree object from the index
+    WriteTree {},
+    /// Read tree information into the index
+    ReadTree {
+        /// Merge the trees into the index rather than replacing it: one tree keeps stat data for
+        /// unchanged files, two trees move from the first to the second, and three trees merge
+        /// the second and third using the first as their base
+        #[structopt(short = "m")]
+        merge: bool,
+        /// Read the tree into the index under this directory, keeping existing entries
+        #[structopt(long, parse(from_os_str), conflicts_with = "merge")]
+        prefix: Option<PathBuf>,
+        /// The trees, or commits whose trees, to read
+        #[structopt(required = true, max_values = 3)]
+        tree_ishes: Vec<String>,
+    },
+    /// Initialise, update or inspect submodules
+    Submodule {
+        #[structopt(subcommand)]
+        command: Option<SubmoduleCommand>,
+    },
+    /// Watch the working tree for changes, for commands to ask about with core.fsmonitor set
+    Fsmonitor {
+        #[structopt(subcommand)]
+        command: FsmonitorCommand,
+    },
+}
+
+#[derive(StructOpt)]
+enum SubmoduleCommand {
+    /// Show the commit checked out in each submodule
+    Status,
+    /// Register submodule URLs from .gitmodules in the repository config
+    Init,
+    /// Clone missing submodules and check out the commits recorded in the index
+    Update {
+        /// Initialise submodules that haven't been yet before updating
+        #[structopt(long)]
+        init: bool,
+    },
+}
+
+#[derive(StructOpt)]
+enum FsmonitorCommand {
+    /// Start the daemon in the background
+    Start,
+    /// Run the daemon in the foreground
+    Run,
+    /// Stop the daemon
+    Stop,
+    /// Show whether the daemon is watching the working tree
+    Status,
 }
 
 fn main() {
@@ -53,18 +270,32 @@ fn main() {
 
             let dirs = vec!["objects", "refs"];
             for dir in dirs {
-                fs::create_dir_all(&git_path.join(dir)).unwrap_or_else(|err| {
+                fs::create_dir_all(git_path.join(dir)).unwrap_or_else(|err| {
                     eprintln!("Error when creating directory {}: {}", dir, err);
                     process::exit(1);
                 })
             }
+            // Reinitialising leaves whichever branch HEAD is on alone
+            if !git_path.join(Refs::HEAD).exists() {
+                Refs::new(git_path.clone())
+                    .init_head()
+                    .unwrap_or_else(|err| {
+                        eprintln!("fatal: unable to write HEAD: {}", err);
+                        process::exit(1);
+                    });
+            }
 
             println!(
                 "Initialized empty Jit repository in {}",
                 &git_path.canonicalize().unwrap().to_str().unwrap()
             );
         }
-        Cli::Commit {} => {
+        Cli::Commit {
+            allow_empty,
+            allow_empty_message,
+            gpg_sign,
+            no_gpg_sign,
+        } => {
             let root_path = env::current_dir().expect("Failed to get current working directory");
             let git_path = root_path.join(".git");
             let db_path = git_path.join("objects");
@@ -73,29 +304,86 @@ fn main() {
             let mut index = Index::new(git_path.join("index"));
             let refs = Refs::new(git_path.clone());
 
-            index.load();
+            // The index is rewritten to record the trees stored, if nothing else holds it
+            let locked = index.load_for_update().unwrap_or_else(index_corrupt);
+            if !locked {
+                index.load().unwrap_or_else(index_corrupt);
+            }
 
-            let mut root = Tree::build(index.entries());
-            root.traverse(|tree| database.store(tree));
+            if index.is_unmerged() {
+                if locked {
+                    index.release_lock();
+                }
+                eprintln!("error: Committing is not possible because you have unmerged files.");
+                eprintln!("fatal: Exiting because of an unresolved conflict.");
+                process::exit(128);
+            }
+
+            let root = command::write_tree::write_tree(&database, &mut index);
+            if locked {
+                index.write_updates();
+            }
+
+            let parent = refs.read_head().unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+            let parent_tree = parent.as_ref().map(|parent| {
+                database
+                    .load_commit(parent)
+                    .unwrap_or_else(|err| {
+                        eprintln!("fatal: could not read HEAD commit {}: {}", parent, err);
+                        process::exit(128);
+                    })
+                    .tree()
+                    .to_string()
+            });
+            let tree = Storable::oid(&root).unwrap();
+            if command::commit::nothing_to_commit(parent_tree.as_deref(), tree, &index)
+                && !allow_empty
+            {
+                println!("nothing to commit");
+                process::exit(1);
+            }
 
-            let parent = refs.read_head();
             let name = env::var("GIT_AUTHOR_NAME").expect("Author name env var not found");
             let email = env::var("GIT_AUTHOR_EMAIL").expect("Author email env var not found");
-            let author = Author::new(name, email, Local::now());
+            let identity = format!("{} <{}>", name, email);
+            let author = Author::new(name, email, Local::now().into());
             let mut message = String::new();
             io::stdin()
                 .read_to_string(&mut message)
                 .expect("Error reading from stdin");
 
+            if message.trim().is_empty() && !allow_empty_message {
+                eprintln!("Aborting commit due to empty commit message.");
+                process::exit(1);
+            }
+
             let mut commit = Commit::new(
                 parent.clone(),
-                root.oid().as_ref().unwrap().to_string(),
+                Storable::oid(&root).unwrap().to_string(),
                 author,
                 &message,
             );
+            let config = Config::load(&git_path.join("config"));
+            let sign =
+                !no_gpg_sign && (gpg_sign || config.get_bool("commit.gpgSign").unwrap_or(false));
+            if sign {
+                signing::signer_for(&config, &identity)
+                    .and_then(|signer| commit.sign(signer.as_ref()))
+                    .unwrap_or_else(|err| {
+                        eprintln!("error: gpg failed to sign the data: {}", err);
+                        eprintln!("fatal: failed to write commit object");
+                        process::exit(128);
+                    });
+            }
             database.store(&mut commit);
-            refs.update_head(&commit.oid().as_ref().unwrap().to_string())
-                .expect("Failed to write commit to HEAD");
+            refs.update_head(commit.oid().unwrap(), parent.as_deref())
+                .unwrap_or_else(|err| {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                });
 
             let is_root = match parent {
                 Some(_) => "",
@@ -104,13 +392,16 @@ fn main() {
             println!(
                 "[{}{}] {}",
                 is_root,
-                commit.oid().as_ref().unwrap().to_string(),
-                message.lines().next().expect("Error: no commit message")
+                commit.oid().unwrap(),
+                message.lines().next().unwrap_or("")
             );
 
             Lockfile::new(git_path.join("HEAD"));
         }
-        Cli::Add { paths } => {
+        Cli::Add {
+            intent_to_add,
+            paths,
+        } => {
             let root_path = env::current_dir().expect("Failed to get current working directory");
             let git_path = root_path.join(".git");
 
@@ -118,21 +409,652 @@ fn main() {
             let database = Database::new(git_path.join("objects"));
             let mut index = Index::new(git_path.join("index"));
 
-            index.load_for_update();
+            if !index.load_for_update().unwrap_or_else(index_corrupt) {
+                eprintln!(
+                    "fatal: Unable to create '{}.lock'",
+                    git_path.join("index").display()
+                );
+                process::exit(128);
+            }
 
             for path in paths {
-                let path = root_path.join(path);
-                for pathname in workspace.list_workspace_files(&path) {
-                    let data = workspace.read_file(&pathname);
-                    let stat = workspace.stat_file(&pathname);
-
-                    let mut blob = Blob::new(data);
-                    database.store(&mut blob);
-                    index.add(pathname, blob.oid().as_ref().unwrap().to_string(), stat);
+                let added = command::add::add(
+                    &root_path,
+                    &workspace,
+                    &database,
+                    &mut index,
+                    &path,
+                    intent_to_add,
+                );
+                if let Err(err) = added {
+                    index.release_lock();
+                    eprintln!("error: {}", err);
+                    process::exit(128);
                 }
             }
 
             index.write_updates();
         }
+        Cli::Rm {
+            cached,
+            force,
+            recursive,
+            paths,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let workspace = Workspace::new(root_path.clone());
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path.clone());
+            let mut index = Index::new(git_path.join("index"));
+
+            if !index.load_for_update().unwrap_or_else(index_corrupt) {
+                eprintln!(
+                    "fatal: Unable to create '{}.lock'",
+                    git_path.join("index").display()
+                );
+                process::exit(128);
+            }
+
+            let removals = rm::expand_removals(&index, &paths, recursive).unwrap_or_else(|err| {
+                index.release_lock();
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+
+            let head = refs.read_head().unwrap_or_else(|err| {
+                index.release_lock();
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+            let head = match head {
+                Some(head) => database
+                    .load_tree_list(&head, Path::new(""))
+                    .unwrap_or_else(|err| {
+                        index.release_lock();
+                        eprintln!("fatal: could not read HEAD tree: {}", err);
+                        process::exit(128);
+                    }),
+                None => TreeList::new(),
+            };
+            let check = rm::RemovalCheck {
+                workspace: WorkspaceCheck::new(&root_path, &workspace, &database, &index),
+                head: &head,
+            };
+            let problems = rm::removal_problems(&check, &removals, cached, force);
+            for (path, stat) in check.workspace.into_refreshed() {
+                index.update_entry_stat(path.as_os_str(), &stat);
+            }
+            report_removal_problems(&problems);
+            if !problems.is_empty() {
+                index.write_updates();
+                process::exit(1);
+            }
+
+            let removed = rm::remove(&workspace, &mut index, &removals, cached, &mut io::stdout());
+            if let Err(err) = removed {
+                index.release_lock();
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            }
+            index.write_updates();
+        }
+        Cli::UpdateIndex {
+            add,
+            remove,
+            cacheinfo,
+            chmod,
+            assume_unchanged,
+            no_assume_unchanged,
+            skip_worktree,
+            no_skip_worktree,
+            untracked_cache,
+            no_untracked_cache,
+            paths,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let workspace = Workspace::new(root_path.clone());
+            let database = Database::new(git_path.join("objects"));
+            let mut index = Index::new(git_path.join("index"));
+
+            if !index.load_for_update().unwrap_or_else(index_corrupt) {
+                eprintln!(
+                    "fatal: Unable to create '{}.lock'",
+                    git_path.join("index").display()
+                );
+                process::exit(128);
+            }
+
+            let flag = |set: bool, unset: bool| if set || unset { Some(set) } else { None };
+            let assume_unchanged = flag(assume_unchanged, no_assume_unchanged);
+            let skip_worktree = flag(skip_worktree, no_skip_worktree);
+
+            if let Some(enabled) = flag(untracked_cache, no_untracked_cache) {
+                let config = Config::load(&git_path.join("config"));
+                match config.get_bool("core.untrackedCache") {
+                    Some(configured) if configured != enabled => eprintln!(
+                        "warning: core.untrackedCache is set to {}; remove or change it, if you really want to {} the untracked cache",
+                        configured,
+                        if enabled { "enable" } else { "disable" }
+                    ),
+                    _ => {}
+                }
+                index.set_untracked_cache(enabled);
+            }
+
+            let mut updated = Vec::new();
+            for info in &cacheinfo {
+                let path =
+                    update_index::add_cacheinfo(&mut index, info, add).unwrap_or_else(|err| {
+                        index.release_lock();
+                        eprintln!("fatal: {}", err);
+                        process::exit(128);
+                    });
+                updated.push(path);
+            }
+
+            for path in paths {
+                let path = command::repository_path(&path).unwrap_or_else(|err| {
+                    index.release_lock();
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                });
+
+                // Marking a path only changes its flags, leaving its content as staged
+                let result = if assume_unchanged.is_some() || skip_worktree.is_some() {
+                    update_index::mark(&mut index, &path, assume_unchanged, skip_worktree)
+                } else {
+                    update_index::update_path(
+                        &root_path, &workspace, &database, &mut index, &path, add, remove,
+                    )
+                    .map_err(|err| {
+                        eprintln!("error: {}", err);
+                        format!("Unable to process path {}", path.display()).into()
+                    })
+                };
+                if let Err(err) = result {
+                    index.release_lock();
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+                updated.push(path);
+            }
+
+            if let Some(chmod) = chmod {
+                if let Err(err) = update_index::chmod(&mut index, &updated, &chmod) {
+                    index.release_lock();
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }
+            }
+
+            index.write_updates();
+        }
+        Cli::VerifyCommit { commits } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path.clone());
+            let config = Config::load(&git_path.join("config"));
+            let signer = signing::signer_for(&config, "").unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+
+            let mut all_good = true;
+            for name in commits {
+                let commit = Revision::new(&refs, &database)
+                    .resolve(&name)
+                    .and_then(|oid| database.load_commit(&oid));
+                let verification = commit.and_then(|commit| commit.verify(signer.as_ref()));
+                match verification {
+                    Ok(verification) => {
+                        eprint!("{}", verification.output);
+                        all_good &= verification.good;
+                    }
+                    Err(err) => {
+                        eprintln!("error: {}: {}", name, err);
+                        all_good = false;
+                    }
+                }
+            }
+
+            if !all_good {
+                process::exit(1);
+            }
+        }
+        Cli::Log {
+            show_signature,
+            revision,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path.clone());
+            let config = Config::load(&git_path.join("config"));
+
+            let start = match revision {
+                Some(revision) => Some(
+                    Revision::new(&refs, &database)
+                        .resolve(&revision)
+                        .unwrap_or_else(|err| {
+                            eprintln!("fatal: {}", err);
+                            process::exit(128);
+                        }),
+                ),
+                None => refs.read_head().unwrap_or_else(|err| {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                }),
+            };
+            let signer = if show_signature {
+                signing::signer_for(&config, "").ok()
+            } else {
+                None
+            };
+
+            let mut next = start;
+            let mut first = true;
+            while let Some(oid) = next {
+                let commit = database.load_commit(&oid).unwrap_or_else(|err| {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                });
+
+                if !first {
+                    println!();
+                }
+                first = false;
+                print_commit(&commit, signer.as_deref());
+                next = commit.parent().map(String::from);
+            }
+        }
+        Cli::Branch {
+            verbose,
+            delete,
+            force_delete,
+            move_,
+            names,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path);
+
+            let result = if delete || force_delete {
+                branch::delete_branches(
+                    &root_path,
+                    &refs,
+                    &database,
+                    &names,
+                    force_delete,
+                    &mut io::stdout(),
+                )
+            } else if move_ {
+                branch::rename_branch(&refs, &names)
+            } else if names.is_empty() {
+                branch::list_branches(&refs, &database, verbose, &mut io::stdout())
+            } else {
+                branch::create_branch(&refs, &database, &names)
+            };
+            match result {
+                Ok(()) => {}
+                Err(err) => match err.downcast::<branch::NotDeleted>() {
+                    Ok(not_deleted) => {
+                        for err in not_deleted.0 {
+                            eprintln!("error: {}", err);
+                        }
+                        process::exit(1);
+                    }
+                    Err(err) => {
+                        eprintln!("fatal: {}", err);
+                        process::exit(128);
+                    }
+                },
+            }
+        }
+        Cli::LsTree {
+            recursive,
+            show_trees,
+            trees_only,
+            long,
+            name_only,
+            tree_ish,
+            paths,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path.clone());
+
+            let tree = Revision::new(&refs, &database)
+                .resolve(&tree_ish)
+                .and_then(|oid| database.load_tree(&oid))
+                .unwrap_or_else(|err| {
+                    eprintln!("fatal: not a tree object: {}", err);
+                    process::exit(128);
+                });
+
+            let options = command::ls_tree::Options {
+                recursive,
+                show_trees,
+                trees_only,
+                long,
+                name_only,
+                paths: pathspecs(&paths),
+            };
+            command::ls_tree::ls_tree(&database, &tree, &options, &mut io::stdout())
+                .unwrap_or_else(|err| {
+                    eprintln!("fatal: {}", err);
+                    process::exit(128);
+                });
+        }
+        Cli::LsFiles {
+            cached,
+            stage,
+            deleted,
+            modified,
+            others,
+            ignored,
+            exclude,
+            exclude_from,
+            exclude_standard,
+            nul_terminated,
+            paths,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let workspace = Workspace::new(root_path.clone());
+            let database = Database::new(git_path.join("objects"));
+            let options = ls_files::Options {
+                cached,
+                stage,
+                deleted,
+                modified,
+                others,
+                ignored,
+                standard_excludes: exclude_standard
+                    && exclude.is_empty()
+                    && exclude_from.is_empty(),
+                nul_terminated,
+                paths: pathspecs(&paths),
+            };
+
+            let mut ignore = Ignore::new(root_path.clone());
+            if exclude_standard {
+                ignore.add_standard(&git_path, &Config::load(&git_path.join("config")));
+            }
+            for pattern in exclude {
+                ignore.add_patterns(&pattern);
+            }
+            for path in exclude_from {
+                let content = fs::read_to_string(&path).unwrap_or_else(|err| {
+                    eprintln!(
+                        "fatal: cannot use {} as an exclude file: {}",
+                        path.display(),
+                        err
+                    );
+                    process::exit(128);
+                });
+                ignore.add_patterns(&content);
+            }
+            options.check(&ignore).unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+
+            // What's learnt is written back unless something else holds the index lock
+            let mut index = Index::new(git_path.join("index"));
+            let locked =
+                options.updates_index() && index.load_for_update().unwrap_or_else(index_corrupt);
+            if !locked {
+                index.load().unwrap_or_else(index_corrupt);
+            }
+
+            let listed = ls_files::ls_files(
+                &root_path,
+                &workspace,
+                &database,
+                &mut index,
+                &mut ignore,
+                &options,
+                &mut io::stdout(),
+            );
+            if let Err(err) = listed {
+                if locked {
+                    index.release_lock();
+                }
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            }
+            if locked {
+                index.write_updates();
+            }
+        }
+        Cli::WriteTree {} => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let mut index = Index::new(git_path.join("index"));
+            // The index is rewritten to record the trees stored, if nothing else holds it
+            let locked = index.load_for_update().unwrap_or_else(index_corrupt);
+            if !locked {
+                index.load().unwrap_or_else(index_corrupt);
+            }
+
+            if index.is_unmerged() {
+                if locked {
+                    index.release_lock();
+                }
+                for entry in index.entries().filter(|entry| entry.stage() > 0) {
+                    let mut line = entry.path().as_bytes().to_vec();
+                    line.extend(format!(": unmerged ({})\n", entry.oid()).into_bytes());
+                    io::stderr()
+                        .write_all(&line)
+                        .expect("Failed to write to stderr");
+                }
+                eprintln!("fatal: git-write-tree: error building trees");
+                process::exit(128);
+            }
+
+            let root = command::write_tree::write_tree(&database, &mut index);
+            if locked {
+                index.write_updates();
+            }
+            println!("{}", Storable::oid(&root).unwrap());
+        }
+        Cli::ReadTree {
+            merge,
+            prefix,
+            tree_ishes,
+        } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+
+            let database = Database::new(git_path.join("objects"));
+            let refs = Refs::new(git_path.clone());
+            let mut index = Index::new(git_path.join("index"));
+
+            if tree_ishes.len() > 1 && !merge {
+                eprintln!("fatal: reading more than one tree requires -m");
+                process::exit(128);
+            }
+
+            let trees = tree_ishes
+                .iter()
+                .map(|tree_ish| {
+                    Revision::new(&refs, &database)
+                        .resolve(tree_ish)
+                        .and_then(|oid| database.load_tree_list(&oid, Path::new("")))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap_or_else(|err| {
+                    eprintln!("fatal: failed to unpack tree object: {}", err);
+                    process::exit(128);
+                });
+
+            if !index.load_for_update().unwrap_or_else(index_corrupt) {
+                eprintln!(
+                    "fatal: Unable to create '{}.lock'",
+                    git_path.join("index").display()
+                );
+                process::exit(128);
+            }
+
+            let result =
+                command::read_tree::read_tree(&mut index, &trees, prefix.as_deref(), merge);
+            if let Err(err) = result {
+                index.release_lock();
+                eprintln!("error: {}", err);
+                process::exit(128);
+            }
+
+            index.write_updates();
+        }
+        Cli::Submodule { command } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            let git_path = root_path.join(".git");
+            let config_path = git_path.join("config");
+
+            let mut index = Index::new(git_path.join("index"));
+            index.load().unwrap_or_else(index_corrupt);
+
+            let mut stdout = io::stdout();
+            let result = match command.unwrap_or(SubmoduleCommand::Status) {
+                SubmoduleCommand::Status => {
+                    command::submodule::status(&root_path, &index, &mut stdout).map_err(Into::into)
+                }
+                SubmoduleCommand::Init => {
+                    command::submodule::init(&root_path, &config_path, &mut stdout)
+                }
+                SubmoduleCommand::Update { init } => {
+                    let initialised = if init {
+                        command::submodule::init(&root_path, &config_path, &mut stdout)
+                    } else {
+                        Ok(())
+                    };
+                    initialised.and_then(|_| {
+                        command::submodule::update(&root_path, &config_path, &index, &mut stdout)
+                    })
+                }
+            };
+            result.unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+        }
+        Cli::Fsmonitor { command } => {
+            let root_path = env::current_dir().expect("Failed to get current working directory");
+            fsmonitor_command(&root_path, command).unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128);
+            });
+        }
+    }
+}
+
+fn fsmonitor_command(root_path: &Path, command: FsmonitorCommand) -> Result<(), Box<dyn Error>> {
+    let git_path = root_path.join(".git");
+    match command {
+        FsmonitorCommand::Start => fsmonitor::start(root_path),
+        FsmonitorCommand::Run => fsmonitor::run(root_path),
+        FsmonitorCommand::Stop => fsmonitor::stop(&git_path),
+        FsmonitorCommand::Status => {
+            if fsmonitor::is_running(&git_path) {
+                println!("fsmonitor-daemon is watching '{}'", root_path.display());
+            } else {
+                println!("fsmonitor-daemon is not watching '{}'", root_path.display());
+                process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Like git, nothing is done with an index that can't be read
+fn index_corrupt<T>(error: Box<dyn Error>) -> T {
+    eprintln!("error: {}", error);
+    eprintln!("fatal: index file corrupt");
+    process::exit(128);
+}
+
+// Pathspecs for the listing commands, which refuse any that leave the repository
+fn pathspecs(paths: &[PathBuf]) -> Vec<PathSpec> {
+    paths
+        .iter()
+        .map(|path| PathSpec::new(path))
+        .collect::<Result<_, _>>()
+        .unwrap_or_else(|err| {
+            eprintln!("fatal: {}", err);
+            process::exit(128);
+        })
+}
+
+fn report_removal_problems(problems: &rm::RemovalProblems) {
+    let keep_hint = "(use --cached to keep the file, or -f to force removal)";
+    print_removal_problem(
+        &problems.both,
+        "file has staged content different from both the\nfile and the HEAD:",
+        "files have staged content different from both the\nfile and the HEAD:",
+        "(use -f to force removal)",
+    );
+    print_removal_problem(
+        &problems.staged,
+        "file has changes staged in the index:",
+        "files have changes staged in the index:",
+        keep_hint,
+    );
+    print_removal_problem(
+        &problems.local,
+        "file has local modifications:",
+        "files have local modifications:",
+        keep_hint,
+    );
+    print_removal_problem(
+        &problems.repositories,
+        "submodule (or one of its nested submodules)\nuses a .git directory:",
+        "submodules (or one of their nested submodules)\nuse a .git directory:",
+        "(use 'rm -rf' if you really want to remove it including all of its history)",
+    );
+}
+
+fn print_removal_problem(paths: &[PathBuf], singular: &str, plural: &str, hint: &str) {
+    match paths.len() {
+        0 => return,
+        1 => eprintln!("error: the following {}", singular),
+        _ => eprintln!("error: the following {}", plural),
+    }
+    for path in paths {
+        eprintln!("    {}", path.display());
+    }
+    eprintln!("{}", hint);
+}
+
+fn print_commit(commit: &Commit, signer: Option<&dyn Signer>) {
+    println!("commit {}", commit.oid().unwrap());
+    if let (Some(signer), Some(_)) = (signer, commit.signature()) {
+        match commit.verify(signer) {
+            Ok(verification) => print!("{}", verification.output),
+            Err(err) => println!("error: {}", err),
+        }
+    }
+    println!(
+        "Author: {} <{}>",
+        commit.author().name(),
+        commit.author().email()
+    );
+    println!("Date:   {}", commit.author().readable_time());
+    println!();
+    for line in commit.message().lines() {
+        println!("    {}", line);
     }
 }
@@ -1,7 +1,9 @@
 use std::{
+    collections::BTreeSet,
     error::{self, Error},
     fmt, fs,
-    path::PathBuf,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
 };
 
 use super::lockfile::Lockfile;
@@ -10,42 +12,637 @@ pub struct Refs {
     pathname: PathBuf,
 }
 
+// What a ref holds: either the name of another ref it stands for, or an oid
+#[derive(Debug, PartialEq)]
+pub enum Ref {
+    Symbolic(String),
+    Direct(String),
+}
+
 impl Refs {
+    pub const HEAD: &'static str = "HEAD";
+    pub const HEADS: &'static str = "refs/heads/";
+    pub const DEFAULT_BRANCH: &'static str = "refs/heads/main";
+
+    // As in git, following more symbolic refs than this means they form a loop
+    const MAX_SYMBOLIC_DEPTH: usize = 5;
+    const SYMBOLIC_PREFIX: &'static str = "ref: ";
+
     pub fn new(pathname: PathBuf) -> Refs {
         Refs { pathname }
     }
 
-    pub fn update_head(&self, oid: &str) -> Result<(), Box<dyn error::Error>> {
-        let mut lockfile = Lockfile::new(self.head_path());
-        if lockfile.hold_for_update().is_ok() {
-            lockfile.write(oid.to_string().as_bytes())?;
-            lockfile.write(String::from("\n").as_bytes())?;
-            lockfile.commit()?;
+    // Points HEAD at the default branch in a new repository, which is born on its first commit
+    pub fn init_head(&self) -> Result<(), Box<dyn error::Error>> {
+        fs::create_dir_all(self.pathname.join("refs").join("heads"))?;
+        self.write_ref(Refs::HEAD, &Ref::Symbolic(Refs::DEFAULT_BRANCH.to_string()))
+    }
+
+    // The commit HEAD resolves to, or None if it's on a branch with no commits yet
+    pub fn read_head(&self) -> Result<Option<String>, Box<dyn error::Error>> {
+        self.resolve(Refs::HEAD)
+    }
+
+    // Moves the current branch on to a new commit, or HEAD itself if it's detached. It must still
+    // be at `old_oid`, or not exist yet when that's None, once it's locked, so that a commit made
+    // meanwhile by someone else isn't lost.
+    pub fn update_head(
+        &self,
+        oid: &str,
+        old_oid: Option<&str>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let name = self.resolve_name(Refs::HEAD)?;
+        self.write_locked(&name, &Ref::Direct(oid.to_string()), || {
+            let current = match self.read_ref(&name)? {
+                Some(Ref::Direct(oid)) => Some(oid),
+                _ => None,
+            };
+            if current.as_deref() != old_oid {
+                let expected = old_oid.map(String::from);
+                return Err(RefMoved(name.clone(), current, expected).into());
+            }
             Ok(())
+        })
+    }
+
+    // Points HEAD straight at a commit, leaving whichever branch it was on
+    pub fn detach_head(&self, oid: &str) -> Result<(), Box<dyn error::Error>> {
+        self.write_ref(Refs::HEAD, &Ref::Direct(oid.to_string()))
+    }
+
+    // The full name of the branch HEAD is on, or None when it's detached
+    pub fn current_branch(&self) -> Result<Option<String>, Box<dyn error::Error>> {
+        match self.read_ref(Refs::HEAD)? {
+            Some(Ref::Symbolic(_)) => Ok(Some(self.resolve_name(Refs::HEAD)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // Follows symbolic refs from `name` to the oid they end at, or None if the last one doesn't
+    // exist yet
+    pub fn resolve(&self, name: &str) -> Result<Option<String>, Box<dyn error::Error>> {
+        let name = self.resolve_name(name)?;
+        match self.read_ref(&name)? {
+            Some(Ref::Direct(oid)) => Ok(Some(oid)),
+            _ => Ok(None),
+        }
+    }
+
+    // The name of the ref that `name` ends up standing for after following symbolic refs, which
+    // is where an update to `name` is written
+    fn resolve_name(&self, name: &str) -> Result<String, Box<dyn error::Error>> {
+        let mut name = name.to_string();
+        for _i in 0..=Refs::MAX_SYMBOLIC_DEPTH {
+            match self.read_ref(&name)? {
+                Some(Ref::Symbolic(target)) => name = target,
+                _ => return Ok(name),
+            }
+        }
+        Err(SymbolicRefLoop(name).into())
+    }
+
+    // Reads a ref without following it, from its own file or else from `packed-refs`
+    pub fn read_ref(&self, name: &str) -> Result<Option<Ref>, Box<dyn error::Error>> {
+        let path = self.ref_path(name)?;
+        if path.is_dir() {
+            return Ok(None);
+        }
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                return Ok(self.packed_ref(name)?.map(Ref::Direct))
+            }
+            Err(error) => return Err(error.into()),
+        };
+        let content = content.trim_end();
+
+        match content.strip_prefix(Refs::SYMBOLIC_PREFIX) {
+            Some(target) if is_safe_name(target) && target.starts_with("refs/") => {
+                Ok(Some(Ref::Symbolic(target.to_string())))
+            }
+            None if is_oid(content) => Ok(Some(Ref::Direct(content.to_string()))),
+            _ => Err(BadRef(name.to_string()).into()),
+        }
+    }
+
+    pub fn write_ref(&self, name: &str, value: &Ref) -> Result<(), Box<dyn error::Error>> {
+        self.write_locked(name, value, || Ok(()))
+    }
+
+    // Writes a ref once `check` has approved its current value, which can't change while it's
+    // locked
+    fn write_locked<F>(
+        &self,
+        name: &str,
+        value: &Ref,
+        check: F,
+    ) -> Result<(), Box<dyn error::Error>>
+    where
+        F: FnOnce() -> Result<(), Box<dyn error::Error>>,
+    {
+        let path = self.ref_path(name)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = match value {
+            Ref::Symbolic(target) => format!("{}{}\n", Refs::SYMBOLIC_PREFIX, target),
+            Ref::Direct(oid) => format!("{}\n", oid),
+        };
+        let mut lockfile = Lockfile::new(path.clone());
+        if !lockfile.hold_for_update()? {
+            return Err(RefLocked(name.to_string(), path).into());
+        }
+        if let Err(error) = check() {
+            lockfile.rollback()?;
+            return Err(error);
+        }
+        lockfile.write(content.as_bytes())?;
+        lockfile.commit()?;
+        Ok(())
+    }
+
+    // Deletes a ref from its own file and from `packed-refs`, along with any directories that
+    // leaves empty
+    pub fn delete_ref(&self, name: &str) -> Result<(), Box<dyn error::Error>> {
+        let path = self.ref_path(name)?;
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error.into()),
+            _ => {}
+        }
+        let refs_path = self.pathname.join("refs");
+        for parent in path.ancestors().skip(1) {
+            if parent == refs_path || fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+        self.delete_packed_ref(name)
+    }
+
+    // The short names of every branch and the commits they're at, in name order
+    pub fn list_branches(&self) -> Result<Vec<(String, String)>, Box<dyn error::Error>> {
+        let mut names = BTreeSet::new();
+        self.loose_refs(Path::new(Refs::HEADS.trim_end_matches('/')), &mut names)?;
+        for (_, name) in self.packed_refs()? {
+            if name.starts_with(Refs::HEADS) {
+                names.insert(name);
+            }
+        }
+
+        let mut branches = Vec::new();
+        for name in names {
+            if let Some(oid) = self.resolve(&name)? {
+                branches.push((name[Refs::HEADS.len()..].to_string(), oid));
+            }
+        }
+        Ok(branches)
+    }
+
+    pub fn create_branch(&self, name: &str, oid: &str) -> Result<(), Box<dyn error::Error>> {
+        let full_name = Refs::branch_ref(name)?;
+        if self.read_ref(&full_name)?.is_some() {
+            return Err(BranchExists(name.to_string()).into());
+        }
+        self.check_branch_conflicts(name, None)?;
+        self.write_ref(&full_name, &Ref::Direct(oid.to_string()))
+    }
+
+    // Deletes a branch, returning the commit it was at
+    pub fn delete_branch(&self, name: &str) -> Result<String, Box<dyn error::Error>> {
+        let full_name = format!("{}{}", Refs::HEADS, name);
+        let oid = match self.read_ref(&full_name) {
+            Ok(Some(Ref::Direct(oid))) => oid,
+            _ => return Err(BranchNotFound(name.to_string()).into()),
+        };
+        self.delete_ref(&full_name)?;
+        Ok(oid)
+    }
+
+    // Renames a branch, moving HEAD along with it if it's the current branch. The current branch
+    // can be renamed before it has any commits, which only changes HEAD.
+    pub fn rename_branch(
+        &self,
+        old_name: &str,
+        new_name: &str,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let old_ref = format!("{}{}", Refs::HEADS, old_name);
+        let new_ref = Refs::branch_ref(new_name)?;
+        let is_current = self.current_branch()?.as_deref() == Some(old_ref.as_str());
+
+        let oid = match self.read_ref(&old_ref) {
+            Ok(Some(Ref::Direct(oid))) => Some(oid),
+            _ if is_current => None,
+            _ => return Err(NoSuchBranch(old_name.to_string()).into()),
+        };
+        if old_ref == new_ref {
+            return Ok(());
+        }
+        if self.read_ref(&new_ref)?.is_some() {
+            return Err(BranchExists(new_name.to_string()).into());
+        }
+        self.check_branch_conflicts(new_name, Some(old_name))?;
+
+        // The old ref goes first, as the new name may need its place as a directory
+        if let Some(oid) = &oid {
+            self.delete_ref(&old_ref)?;
+            if let Err(error) = self.write_ref(&new_ref, &Ref::Direct(oid.clone())) {
+                self.write_ref(&old_ref, &Ref::Direct(oid.clone()))?;
+                return Err(error);
+            }
+        }
+        if is_current {
+            self.write_ref(Refs::HEAD, &Ref::Symbolic(new_ref))?;
+        }
+        Ok(())
+    }
+
+    // The full name of a branch, if it's allowed as one
+    fn branch_ref(name: &str) -> Result<String, InvalidBranchName> {
+        let full_name = format!("{}{}", Refs::HEADS, name);
+        if name != Refs::HEAD && !name.starts_with('-') && is_valid_name(&full_name) {
+            Ok(full_name)
         } else {
-            Err(LockDenied.into())
+            Err(InvalidBranchName(name.to_string()))
+        }
+    }
+
+    // A branch can't be created where another branch's name would need to be a directory, or
+    // the other way around
+    fn check_branch_conflicts(
+        &self,
+        name: &str,
+        replacing: Option<&str>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let nested = |outer: &str, inner: &str| {
+            inner.len() > outer.len()
+                && inner.starts_with(outer)
+                && inner.as_bytes()[outer.len()] == b'/'
+        };
+        for (existing, _) in self.list_branches()? {
+            if Some(existing.as_str()) != replacing
+                && (nested(&existing, name) || nested(name, &existing))
+            {
+                return Err(RefConflict(
+                    format!("{}{}", Refs::HEADS, name),
+                    format!("{}{}", Refs::HEADS, existing),
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn loose_refs(&self, directory: &Path, names: &mut BTreeSet<String>) -> io::Result<()> {
+        let entries = match fs::read_dir(self.pathname.join(directory)) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = directory.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                self.loose_refs(&path, names)?;
+            } else if let Some(name) = path.to_str() {
+                // A ref being updated has a lock beside it, which isn't a ref of its own
+                if !name.ends_with(".lock") {
+                    names.insert(name.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn packed_ref(&self, name: &str) -> Result<Option<String>, Box<dyn error::Error>> {
+        let packed = self.packed_refs()?;
+        Ok(packed
+            .into_iter()
+            .find(|(_, ref_name)| ref_name == name)
+            .map(|(oid, _)| oid))
+    }
+
+    // The oids and names in `packed-refs`, whose lines are `<oid> <name>` apart from comments and
+    // the `^<oid>` lines following tags
+    fn packed_refs(&self) -> Result<Vec<(String, String)>, Box<dyn error::Error>> {
+        let packed = match fs::read_to_string(self.packed_refs_path()) {
+            Ok(packed) => packed,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(packed
+            .lines()
+            .filter_map(|line| match line.split_once(' ') {
+                Some((oid, name)) if is_oid(oid) => Some((oid.to_string(), name.to_string())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn delete_packed_ref(&self, name: &str) -> Result<(), Box<dyn error::Error>> {
+        let path = self.packed_refs_path();
+        let packed = match fs::read_to_string(&path) {
+            Ok(packed) => packed,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        // The peeled oid following a tag goes along with it
+        let mut lines = Vec::new();
+        let mut removing = false;
+        for line in packed.lines() {
+            if line.starts_with('^') {
+                if !removing {
+                    lines.push(line);
+                }
+                continue;
+            }
+            removing = matches!(line.split_once(' '), Some((_, ref_name)) if ref_name == name);
+            if !removing {
+                lines.push(line);
+            }
+        }
+        if lines.len() == packed.lines().count() {
+            return Ok(());
+        }
+
+        let mut lockfile = Lockfile::new(path.clone());
+        if !lockfile.hold_for_update()? {
+            return Err(RefLocked(String::from("packed-refs"), path).into());
         }
+        for line in lines {
+            lockfile.write(format!("{}\n", line).as_bytes())?;
+        }
+        lockfile.commit()?;
+        Ok(())
     }
 
-    fn head_path(&self) -> PathBuf {
-        self.pathname.join("HEAD")
+    fn packed_refs_path(&self) -> PathBuf {
+        self.pathname.join("packed-refs")
     }
 
-    pub fn read_head(&self) -> Option<String> {
-        if self.head_path().exists() {
-            let head = fs::read_to_string(self.head_path()).expect("Failed to read HEAD file");
-            Some(head.trim().to_string())
+    fn ref_path(&self, name: &str) -> Result<PathBuf, BadRef> {
+        if is_safe_name(name) {
+            Ok(self.pathname.join(name))
         } else {
-            None
+            Err(BadRef(name.to_string()))
         }
     }
 }
 
+fn is_oid(content: &str) -> bool {
+    content.len() == 40 && content.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// Whether `name` follows git's rules for ref names, as described in git-check-ref-format(1)
+pub fn is_valid_name(name: &str) -> bool {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+    name != "@"
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.ends_with('.')
+        && !name.chars().any(forbidden)
+        && name.split('/').all(|component| {
+            !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+        })
+}
+
+// Ref names are paths inside `.git`, so they mustn't be able to lead outside it
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('/')
+        && name
+            .split('/')
+            .all(|component| !component.is_empty() && component != "." && component != "..")
+}
+
+#[derive(Debug)]
+pub struct RefLocked(String, PathBuf);
+impl Error for RefLocked {}
+impl fmt::Display for RefLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot lock ref '{}': Unable to create '{}.lock': File exists.",
+            self.0,
+            self.1.display()
+        )
+    }
+}
+
+// A ref found at another value than the one expected, where None means it doesn't exist
+#[derive(Debug)]
+pub struct RefMoved(String, Option<String>, Option<String>);
+impl Error for RefMoved {}
+impl fmt::Display for RefMoved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot lock ref '{}': ", self.0)?;
+        match (&self.1, &self.2) {
+            (_, None) => write!(f, "reference already exists"),
+            (None, Some(expected)) => write!(f, "reference is missing but expected {}", expected),
+            (Some(current), Some(expected)) => {
+                write!(f, "is at {} but expected {}", current, expected)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BadRef(String);
+impl Error for BadRef {}
+impl fmt::Display for BadRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad ref '{}'", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidBranchName(String);
+impl Error for InvalidBranchName {}
+impl fmt::Display for InvalidBranchName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' is not a valid branch name", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct BranchExists(String);
+impl Error for BranchExists {}
+impl fmt::Display for BranchExists {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a branch named '{}' already exists", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct BranchNotFound(String);
+impl Error for BranchNotFound {}
+impl fmt::Display for BranchNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "branch '{}' not found.", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct NoSuchBranch(String);
+impl Error for NoSuchBranch {}
+impl fmt::Display for NoSuchBranch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No branch named '{}'.", self.0)
+    }
+}
+
 #[derive(Debug)]
-pub struct LockDenied;
-impl Error for LockDenied {}
-impl fmt::Display for LockDenied {
+pub struct RefConflict(String, String);
+impl Error for RefConflict {}
+impl fmt::Display for RefConflict {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Oh no, something bad went down")
+        write!(
+            f,
+            "cannot lock ref '{}': '{}' exists; cannot create '{}'",
+            self.0, self.1, self.0
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct SymbolicRefLoop(String);
+impl Error for SymbolicRefLoop {}
+impl fmt::Display for SymbolicRefLoop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "symbolic ref loop at '{}'", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::temp_dir::TempDir;
+    use super::*;
+
+    fn refs(name: &str) -> (TempDir, Refs) {
+        let dir = TempDir::new(name);
+        let refs = Refs::new(dir.to_path_buf());
+        refs.init_head().unwrap();
+        (dir, refs)
+    }
+
+    #[test]
+    fn it_advances_the_branch_head_is_on() {
+        let (_dir, refs) = refs("branch");
+        assert_eq!(None, refs.read_head().unwrap());
+
+        refs.update_head(&"1".repeat(40), None).unwrap();
+
+        assert_eq!(
+            Some(Ref::Symbolic(String::from(Refs::DEFAULT_BRANCH))),
+            refs.read_ref(Refs::HEAD).unwrap()
+        );
+        assert_eq!(
+            Some("1".repeat(40)),
+            refs.resolve(Refs::DEFAULT_BRANCH).unwrap()
+        );
+
+        refs.detach_head(&"2".repeat(40)).unwrap();
+        refs.update_head(&"3".repeat(40), Some(&"2".repeat(40)))
+            .unwrap();
+
+        assert_eq!(None, refs.current_branch().unwrap());
+        assert_eq!(Some("3".repeat(40)), refs.read_head().unwrap());
+        assert_eq!(
+            Some("1".repeat(40)),
+            refs.resolve(Refs::DEFAULT_BRANCH).unwrap()
+        );
+    }
+
+    #[test]
+    fn it_only_advances_head_from_the_commit_expected() {
+        let (_dir, refs) = refs("expected");
+        let (one, two, three) = ("1".repeat(40), "2".repeat(40), "3".repeat(40));
+        refs.update_head(&one, None).unwrap();
+
+        let error = refs.update_head(&two, None).unwrap_err();
+        assert_eq!(
+            "cannot lock ref 'refs/heads/main': reference already exists",
+            error.to_string()
+        );
+        let error = refs.update_head(&two, Some(&three)).unwrap_err();
+        assert_eq!(
+            format!(
+                "cannot lock ref 'refs/heads/main': is at {} but expected {}",
+                one, three
+            ),
+            error.to_string()
+        );
+        assert_eq!(Some(one.clone()), refs.read_head().unwrap());
+
+        // Nothing is written while someone else holds the lock, which isn't listed as a branch
+        let lock_path = refs.pathname.join("refs/heads/main.lock");
+        fs::write(&lock_path, "").unwrap();
+        assert!(refs.update_head(&two, Some(&one)).is_err());
+        assert_eq!(
+            vec![(String::from("main"), one.clone())],
+            refs.list_branches().unwrap()
+        );
+        fs::remove_file(&lock_path).unwrap();
+
+        refs.update_head(&two, Some(&one)).unwrap();
+        assert_eq!(Some(two), refs.read_head().unwrap());
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn it_refuses_to_follow_a_symbolic_ref_loop() {
+        let (_dir, refs) = refs("loop");
+        let loop_to = |name: &str| Ref::Symbolic(String::from(name));
+        refs.write_ref("refs/heads/main", &loop_to("refs/heads/other"))
+            .unwrap();
+        refs.write_ref("refs/heads/other", &loop_to("refs/heads/main"))
+            .unwrap();
+
+        assert!(refs.read_head().is_err());
+        assert!(refs.update_head(&"1".repeat(40), None).is_err());
+    }
+
+    #[test]
+    fn it_moves_head_along_with_a_renamed_branch() {
+        let (_dir, refs) = refs("rename");
+        refs.update_head(&"1".repeat(40), None).unwrap();
+        refs.create_branch("topic/one", &"2".repeat(40)).unwrap();
+
+        refs.rename_branch("main", "topic").unwrap_err();
+        refs.rename_branch("topic/one", "topic").unwrap();
+        refs.rename_branch("main", "trunk").unwrap();
+
+        assert_eq!(
+            Some(String::from("refs/heads/trunk")),
+            refs.current_branch().unwrap()
+        );
+        assert_eq!(
+            vec![
+                (String::from("topic"), "2".repeat(40)),
+                (String::from("trunk"), "1".repeat(40)),
+            ],
+            refs.list_branches().unwrap()
+        );
+    }
+
+    #[test]
+    fn it_checks_ref_names_like_git() {
+        for name in ["refs/heads/main", "refs/heads/a/b-c", "refs/tags/v1.0"] {
+            assert!(is_valid_name(name), "{}", name);
+        }
+        for name in [
+            "refs/heads/a..b",
+            "refs/heads/.hidden",
+            "refs/heads/a.lock",
+            "refs/heads/a/",
+            "refs/heads//a",
+            "refs/heads/a b",
+            "refs/heads/a@{1}",
+            "refs/heads/a.",
+            "@",
+        ] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
     }
 }
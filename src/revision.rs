@@ -0,0 +1,226 @@
+use std::{
+    error::{self, Error},
+    fmt,
+};
+
+use super::{
+    database::Database,
+    refs::{self, Refs},
+};
+
+// Resolves revision expressions such as `HEAD`, `@~2`, `3a4f^` or a full object ID into an oid
+pub struct Revision<'a> {
+    refs: &'a Refs,
+    database: &'a Database,
+}
+
+impl Revision<'_> {
+    pub fn new<'a>(refs: &'a Refs, database: &'a Database) -> Revision<'a> {
+        Revision { refs, database }
+    }
+
+    pub fn resolve(&self, expression: &str) -> Result<String, Box<dyn error::Error>> {
+        let expression = expression.trim();
+
+        if let Some(rev) = expression.strip_suffix('^') {
+            return self.parent_of(&self.resolve(rev)?, expression);
+        }
+        if let Some(split) = expression.rfind('~') {
+            let count = &expression[split + 1..];
+            let count: usize = if count.is_empty() {
+                1
+            } else {
+                count
+                    .parse()
+                    .map_err(|_| BadRevision(expression.to_string()))?
+            };
+            let mut oid = self.resolve(&expression[..split])?;
+            for _i in 0..count {
+                oid = self.parent_of(&oid, expression)?;
+            }
+            return Ok(oid);
+        }
+
+        if let Some(oid) = self.read_ref(expression)? {
+            return Ok(oid);
+        }
+
+        const MIN_OID_PREFIX: usize = 4;
+        if expression.len() >= MIN_OID_PREFIX
+            && expression.len() <= 40
+            && expression.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            let candidates = self.database.prefix_match(&expression.to_lowercase());
+            match candidates.len() {
+                0 => {}
+                1 => return Ok(candidates[0].clone()),
+                _ => return Err(AmbiguousRevision(expression.to_string()).into()),
+            }
+        }
+
+        Err(BadRevision(expression.to_string()).into())
+    }
+
+    fn read_ref(&self, name: &str) -> Result<Option<String>, Box<dyn error::Error>> {
+        match name {
+            "HEAD" | "@" => self.refs.read_head(),
+            _ if !refs::is_valid_name(name) => Ok(None),
+            _ => {
+                // Short names are looked up in the same places, and order, as git looks for them
+                let candidates = [
+                    format!("refs/{}", name),
+                    format!("refs/tags/{}", name),
+                    format!("refs/heads/{}", name),
+                    format!("refs/remotes/{}", name),
+                    format!("refs/remotes/{}/HEAD", name),
+                ];
+                let full_name = name.starts_with("refs/").then(|| name.to_string());
+                for candidate in full_name.into_iter().chain(candidates) {
+                    if let Some(oid) = self.refs.resolve(&candidate)? {
+                        return Ok(Some(oid));
+                    }
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn parent_of(&self, oid: &str, expression: &str) -> Result<String, Box<dyn error::Error>> {
+        let commit = self.database.load_commit(oid)?;
+        commit
+            .parent()
+            .map(String::from)
+            .ok_or_else(|| BadRevision(expression.to_string()).into())
+    }
+}
+
+#[derive(Debug)]
+pub struct BadRevision(String);
+impl Error for BadRevision {}
+impl fmt::Display for BadRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown revision '{}'", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct AmbiguousRevision(String);
+impl Error for AmbiguousRevision {}
+impl fmt::Display for AmbiguousRevision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "short object ID {} is ambiguous", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        database::{blob::Blob, commit::Commit, Storable},
+        temp_dir::TempDir,
+    };
+    use super::*;
+    use std::collections::HashMap;
+
+    fn repository(name: &str) -> (TempDir, Refs, Database) {
+        let root_path = TempDir::repository(name);
+        let git_path = root_path.join(".git");
+        let refs = Refs::new(git_path.clone());
+        refs.init_head().unwrap();
+        (root_path, refs, Database::new(git_path.join("objects")))
+    }
+
+    fn commit(database: &Database, parent: Option<&str>, message: &str) -> String {
+        let mut data = String::from("tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n");
+        if let Some(parent) = parent {
+            data.push_str(&format!("parent {}\n", parent));
+        }
+        data.push_str("author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\n");
+        data.push_str(message);
+        let mut commit = Commit::parse("", data.as_bytes()).unwrap();
+        database.store(&mut commit);
+        commit.oid().unwrap().to_string()
+    }
+
+    #[test]
+    fn it_resolves_refs_oids_and_ancestors() {
+        let (_root_path, refs, database) = repository("resolve");
+        let first = commit(&database, None, "first\n");
+        let second = commit(&database, Some(&first), "second\n");
+        let third = commit(&database, Some(&second), "third\n");
+        refs.update_head(&third, None).unwrap();
+        refs.create_branch("topic", &second).unwrap();
+
+        let revision = Revision::new(&refs, &database);
+        let resolve = |expression: &str| revision.resolve(expression).unwrap();
+        assert_eq!(third, resolve("HEAD"));
+        assert_eq!(third, resolve("@"));
+        assert_eq!(second, resolve("topic"));
+        assert_eq!(second, resolve("refs/heads/topic"));
+        assert_eq!(first, resolve(&first));
+        assert_eq!(first, resolve(&first[..7].to_uppercase()));
+
+        assert_eq!(second, resolve("HEAD^"));
+        assert_eq!(second, resolve("@~"));
+        assert_eq!(first, resolve("HEAD~2"));
+        assert_eq!(third, resolve("HEAD~0"));
+        assert_eq!(first, resolve("topic^"));
+        assert_eq!(second, resolve(&format!("{}~1", &third[..7])));
+
+        // Suffixes apply one after another, from left to right
+        assert_eq!(first, resolve("HEAD^^"));
+        assert_eq!(first, resolve("HEAD^~1"));
+        assert_eq!(first, resolve("HEAD~1^"));
+        assert_eq!(first, resolve("@~~"));
+    }
+
+    #[test]
+    fn it_refuses_unknown_or_ambiguous_revisions() {
+        let (_root_path, refs, database) = repository("unknown");
+        let first = commit(&database, None, "first\n");
+        refs.update_head(&first, None).unwrap();
+
+        let revision = Revision::new(&refs, &database);
+        let error = |expression: &str| revision.resolve(expression).unwrap_err().to_string();
+        assert_eq!("unknown revision 'nowhere'", error("nowhere"));
+        assert_eq!("unknown revision 'HEAD^'", error("HEAD^"));
+        assert_eq!("unknown revision 'HEAD~3'", error("HEAD~3"));
+        assert_eq!("unknown revision 'HEAD~x'", error("HEAD~x"));
+        // Short oids need at least four digits, and ones that match nothing are unknown
+        assert_eq!(
+            format!("unknown revision '{}'", &first[..3]),
+            error(&first[..3])
+        );
+        let missing = if first.starts_with("0000") {
+            "ffff"
+        } else {
+            "0000"
+        };
+        assert_eq!(format!("unknown revision '{}'", missing), error(missing));
+
+        // Blobs are stored until two share their first four digits
+        let mut prefixes = HashMap::new();
+        let shared = (0..)
+            .find_map(|n: u32| {
+                let mut blob = Blob::new(n.to_string().into_bytes());
+                database.store(&mut blob);
+                let oid = blob.oid().unwrap().to_string();
+                prefixes
+                    .insert(oid[..4].to_string(), oid.clone())
+                    .map(|other| (other, oid))
+            })
+            .unwrap();
+        let prefix = &shared.0[..4];
+        assert_eq!(
+            format!("short object ID {} is ambiguous", prefix),
+            error(prefix)
+        );
+        // Enough digits to tell them apart picks the one meant
+        let distinct = (4..40)
+            .find(|&len| shared.0[..len] != shared.1[..len])
+            .unwrap();
+        assert_eq!(
+            shared.1,
+            revision.resolve(&shared.1[..distinct + 1]).unwrap()
+        );
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    env,
+    error::{self, Error},
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+use super::config::Config;
+
+pub struct Verification {
+    pub good: bool,
+    pub output: String,
+}
+
+// Produces and checks detached signatures over commit payloads
+pub trait Signer {
+    fn sign(&self, payload: &[u8]) -> Result<String, Box<dyn error::Error>>;
+    fn verify(
+        &self,
+        payload: &[u8],
+        signature: &str,
+    ) -> Result<Verification, Box<dyn error::Error>>;
+}
+
+// Picks a signer based on `gpg.format`, falling back to `identity` when `user.signingKey` is unset
+pub fn signer_for(config: &Config, identity: &str) -> Result<Box<dyn Signer>, Box<dyn Error>> {
+    let key = config.get("user.signingKey").map(String::from);
+
+    match config.get("gpg.format").unwrap_or("openpgp") {
+        "openpgp" => Ok(Box::new(GpgSigner {
+            program: config
+                .get("gpg.openpgp.program")
+                .or_else(|| config.get("gpg.program"))
+                .unwrap_or("gpg")
+                .to_string(),
+            key: key.unwrap_or_else(|| identity.to_string()),
+        })),
+        "ssh" => Ok(Box::new(SshSigner {
+            program: config
+                .get("gpg.ssh.program")
+                .unwrap_or("ssh-keygen")
+                .to_string(),
+            key,
+            allowed_signers: config.get("gpg.ssh.allowedSignersFile").map(PathBuf::from),
+        })),
+        format => Err(UnsupportedFormat(format.to_string()).into()),
+    }
+}
+
+pub struct GpgSigner {
+    program: String,
+    key: String,
+}
+
+impl Signer for GpgSigner {
+    fn sign(&self, payload: &[u8]) -> Result<String, Box<dyn error::Error>> {
+        let output = run(
+            Command::new(&self.program)
+                .arg("--status-fd=2")
+                .arg("-bsau")
+                .arg(&self.key),
+            payload,
+        )?;
+
+        let status = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !status.contains("\n[GNUPG:] SIG_CREATED ") {
+            return Err(SigningFailed(status.into_owned()).into());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    fn verify(
+        &self,
+        payload: &[u8],
+        signature: &str,
+    ) -> Result<Verification, Box<dyn error::Error>> {
+        let signature_file = TempFile::new(signature.as_bytes())?;
+        let output = run(
+            Command::new(&self.program)
+                .arg("--keyid-format=long")
+                .arg("--status-fd=1")
+                .arg("--verify")
+                .arg(signature_file.path())
+                .arg("-"),
+            payload,
+        )?;
+
+        let status = String::from_utf8_lossy(&output.stdout);
+        Ok(Verification {
+            good: output.status.success()
+                && status
+                    .lines()
+                    .any(|line| line.starts_with("[GNUPG:] GOODSIG ")),
+            output: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+pub struct SshSigner {
+    program: String,
+    key: Option<String>,
+    allowed_signers: Option<PathBuf>,
+}
+
+impl SshSigner {
+    const NAMESPACE: &'static str = "git";
+}
+
+impl Signer for SshSigner {
+    fn sign(&self, payload: &[u8]) -> Result<String, Box<dyn error::Error>> {
+        let key = self.key.as_ref().ok_or(MissingSigningKey)?;
+        let output = run(
+            Command::new(&self.program)
+                .args(["-Y", "sign", "-n", SshSigner::NAMESPACE, "-f"])
+                .arg(key),
+            payload,
+        )?;
+
+        if !output.status.success() {
+            return Err(SigningFailed(String::from_utf8_lossy(&output.stderr).into_owned()).into());
+        }
+        Ok(String::from_utf8(output.stdout)?)
+    }
+
+    fn verify(
+        &self,
+        payload: &[u8],
+        signature: &str,
+    ) -> Result<Verification, Box<dyn error::Error>> {
+        let signature_file = TempFile::new(signature.as_bytes())?;
+
+        let allowed_signers = match &self.allowed_signers {
+            Some(allowed_signers) => allowed_signers,
+            None => {
+                // Without an allowed signers file we can only check the signature is well formed
+                let output = run(
+                    Command::new(&self.program)
+                        .args(["-Y", "check-novalidate", "-n", SshSigner::NAMESPACE, "-s"])
+                        .arg(signature_file.path()),
+                    payload,
+                )?;
+                return Ok(Verification {
+                    good: output.status.success(),
+                    output: combined_output(&output),
+                });
+            }
+        };
+
+        let principals = Command::new(&self.program)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(allowed_signers)
+            .arg("-s")
+            .arg(signature_file.path())
+            .output()?;
+        let principal = String::from_utf8_lossy(&principals.stdout)
+            .lines()
+            .next()
+            .map(String::from);
+
+        let principal = match principal {
+            Some(principal) if principals.status.success() => principal,
+            _ => {
+                return Ok(Verification {
+                    good: false,
+                    output: combined_output(&principals),
+                })
+            }
+        };
+
+        let output = run(
+            Command::new(&self.program)
+                .args(["-Y", "verify", "-n", SshSigner::NAMESPACE, "-f"])
+                .arg(allowed_signers)
+                .arg("-I")
+                .arg(&principal)
+                .arg("-s")
+                .arg(signature_file.path()),
+            payload,
+        )?;
+        Ok(Verification {
+            good: output.status.success(),
+            output: combined_output(&output),
+        })
+    }
+}
+
+fn run(command: &mut Command, input: &[u8]) -> Result<Output, Box<dyn error::Error>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("Failed to open stdin of signing program")
+        .write_all(input)?;
+    Ok(child.wait_with_output()?)
+}
+
+fn combined_output(output: &Output) -> String {
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    text
+}
+
+// Signature programs read detached signatures from a file, so this is removed again on drop. The
+// file is created afresh and private to the user, so that nobody else can put their own content,
+// or a symlink, in its place.
+struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    const ATTEMPTS: usize = 100;
+
+    fn new(content: &[u8]) -> Result<TempFile, Box<dyn error::Error>> {
+        for _i in 0..TempFile::ATTEMPTS {
+            let name: String = thread_rng().sample_iter(&Alphanumeric).take(12).collect();
+            let path = env::temp_dir().join(format!("jit_sig_{}", name));
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path);
+            match file {
+                Ok(mut file) => {
+                    let temp_file = TempFile { path };
+                    file.write_all(content)?;
+                    return Ok(temp_file);
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            "could not create a temporary file for the signature",
+        )
+        .into())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+pub struct Unsigned;
+impl Error for Unsigned {}
+impl fmt::Display for Unsigned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no signature found")
+    }
+}
+
+#[derive(Debug)]
+struct SigningFailed(String);
+impl Error for SigningFailed {}
+impl fmt::Display for SigningFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "signing failed: {}", self.0.trim())
+    }
+}
+
+#[derive(Debug)]
+struct MissingSigningKey;
+impl Error for MissingSigningKey {}
+impl fmt::Display for MissingSigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "user.signingKey needs to be set for ssh signing")
+    }
+}
+
+#[derive(Debug)]
+struct UnsupportedFormat(String);
+impl Error for UnsupportedFormat {}
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unsupported value for gpg.format: {}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, FixedOffset};
+    use sha1::{Digest, Sha1};
+
+    use super::*;
+    use crate::database::{author::Author, commit::Commit, Storable};
+
+    // Deterministic stand-in for gpg: the "signature" is just the SHA-1 of the payload
+    struct StubSigner;
+
+    impl Signer for StubSigner {
+        fn sign(&self, payload: &[u8]) -> Result<String, Box<dyn error::Error>> {
+            Ok(format!(
+                "-----BEGIN STUB SIGNATURE-----\n{}\n-----END STUB SIGNATURE-----\n",
+                hex::encode(Sha1::digest(payload))
+            ))
+        }
+
+        fn verify(
+            &self,
+            payload: &[u8],
+            signature: &str,
+        ) -> Result<Verification, Box<dyn error::Error>> {
+            let good = self.sign(payload)? == signature;
+            Ok(Verification {
+                good,
+                output: String::from(if good {
+                    "Good signature"
+                } else {
+                    "BAD signature"
+                }),
+            })
+        }
+    }
+
+    fn commit(message: &str) -> Commit {
+        let time = DateTime::<FixedOffset>::parse_from_str("1600000000 +0100", "%s %z").unwrap();
+        let author = Author::new(
+            String::from("Alice"),
+            String::from("alice@example.com"),
+            time,
+        );
+        Commit::new(None, "a".repeat(40), author, message)
+    }
+
+    #[test]
+    fn it_writes_a_gpgsig_header() {
+        let mut commit = commit("Signed\n");
+        commit.sign(&StubSigner).unwrap();
+
+        let serialized = String::from_utf8(commit.to_string()).unwrap();
+        let header = serialized
+            .lines()
+            .find(|l| l.starts_with("gpgsig "))
+            .unwrap();
+
+        assert_eq!("gpgsig -----BEGIN STUB SIGNATURE-----", header);
+        assert!(serialized.contains("\n -----END STUB SIGNATURE-----\n\nSigned\n"));
+    }
+
+    #[test]
+    fn it_verifies_a_parsed_signed_commit() {
+        let mut commit = commit("Signed\n");
+        commit.sign(&StubSigner).unwrap();
+
+        let parsed = Commit::parse("b".repeat(40).as_str(), &commit.to_string()).unwrap();
+
+        assert_eq!(commit.payload(), parsed.payload());
+        assert!(parsed.verify(&StubSigner).unwrap().good);
+    }
+
+    #[test]
+    fn it_rejects_a_tampered_commit() {
+        let mut commit = commit("Signed\n");
+        commit.sign(&StubSigner).unwrap();
+
+        let tampered = String::from_utf8(commit.to_string())
+            .unwrap()
+            .replace("Signed", "Forged");
+        let parsed = Commit::parse("b".repeat(40).as_str(), tampered.as_bytes()).unwrap();
+
+        assert!(!parsed.verify(&StubSigner).unwrap().good);
+    }
+
+    #[test]
+    fn it_reports_unsigned_commits() {
+        assert!(commit("Unsigned\n").verify(&StubSigner).is_err());
+    }
+
+    #[test]
+    fn it_keeps_signature_files_private_and_cleans_them_up() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let file = TempFile::new(b"signature").unwrap();
+        let path = file.path().to_path_buf();
+
+        assert_eq!(b"signature".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(0o600, path.metadata().unwrap().permissions().mode() & 0o777);
+
+        drop(file);
+        assert!(!path.exists());
+    }
+}
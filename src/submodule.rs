@@ -0,0 +1,335 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    config::Config,
+    database::{
+        blob::Blob,
+        tree::{DatabaseEntry, OidAndMode},
+        Database, Object,
+    },
+    index::Index,
+    refs::Refs,
+    workspace::Workspace,
+};
+
+// A submodule as declared in `.gitmodules`
+pub struct Submodule {
+    name: String,
+    path: PathBuf,
+    url: Option<String>,
+}
+
+impl Submodule {
+    pub fn list(root_path: &Path) -> Vec<Submodule> {
+        let gitmodules = Config::load(&root_path.join(".gitmodules"));
+
+        gitmodules
+            .subsections("submodule")
+            .into_iter()
+            .filter_map(|name| {
+                let path = gitmodules.get(&format!("submodule.{}.path", name))?;
+                Some(Submodule {
+                    path: PathBuf::from(path),
+                    url: gitmodules
+                        .get(&format!("submodule.{}.url", name))
+                        .map(String::from),
+                    name,
+                })
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    // Local-path URLs may be relative to the superproject, as with `./libs/foo` or `../foo`
+    pub fn resolve_url(&self, root_path: &Path) -> Option<PathBuf> {
+        let url = self.url.as_ref()?;
+        let url = url.strip_prefix("file://").unwrap_or(url);
+        if url.starts_with("./") || url.starts_with("../") {
+            Some(root_path.join(url))
+        } else {
+            Some(PathBuf::from(url))
+        }
+    }
+
+    // Records the submodule's URL in the superproject's config, returning false if already there
+    pub fn init(&self, root_path: &Path, config_path: &Path) -> Result<bool, Box<dyn Error>> {
+        let key = format!("submodule.{}.url", self.name);
+        if Config::load(config_path).get(&key).is_some() {
+            return Ok(false);
+        }
+
+        let url = match self.resolve_url(root_path) {
+            Some(url) => url.canonicalize().unwrap_or(url),
+            None => return Err(MissingUrl(self.name.clone()).into()),
+        };
+        Config::set(config_path, &key, &url.to_string_lossy())?;
+        Ok(true)
+    }
+
+    pub fn is_checked_out(&self, root_path: &Path) -> bool {
+        Workspace::is_repository(&root_path.join(&self.path))
+    }
+
+    // Clones the submodule from its local-path URL if needed, and checks out `oid` in it
+    pub fn update(&self, root_path: &Path, url: &Path, oid: &str) -> Result<bool, Box<dyn Error>> {
+        let source_objects = match git_dir(url) {
+            Some(source) => source.join("objects"),
+            None => return Err(NotARepository(url.to_path_buf()).into()),
+        };
+        // Only loose objects can be read, so a source with packs is refused before anything of
+        // the submodule is created
+        if has_packs(&source_objects)? {
+            return Err(PackedObjects(url.to_path_buf()).into());
+        }
+
+        let repo_path = root_path.join(&self.path);
+        let git_path = repo_path.join(".git");
+        for dir in &["objects", "refs"] {
+            fs::create_dir_all(git_path.join(dir))?;
+        }
+        copy_objects(&source_objects, &git_path.join("objects"))?;
+
+        if head_commit(&repo_path).as_deref() == Some(oid) {
+            return Ok(false);
+        }
+        checkout(&repo_path, oid)?;
+        Ok(true)
+    }
+}
+
+// Reads the commit a nested repository has checked out, following a symbolic HEAD if needed
+pub fn head_commit(repo_path: &Path) -> Option<String> {
+    let git_path = git_dir(repo_path)?;
+    Refs::new(git_path).read_head().ok().flatten()
+}
+
+// Finds the git directory for a repository, which may be bare or use a `gitdir:` file
+fn git_dir(repo_path: &Path) -> Option<PathBuf> {
+    let dot_git = repo_path.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    if let Ok(content) = fs::read_to_string(&dot_git) {
+        let target = content.trim().strip_prefix("gitdir: ")?;
+        return Some(repo_path.join(target));
+    }
+    if repo_path.join("objects").is_dir() && repo_path.join("HEAD").is_file() {
+        return Some(repo_path.to_path_buf());
+    }
+    None
+}
+
+fn has_packs(objects_path: &Path) -> Result<bool, Box<dyn Error>> {
+    let packs = match fs::read_dir(objects_path.join("pack")) {
+        Ok(packs) => packs,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error.into()),
+    };
+    for pack in packs {
+        if pack?.path().extension().is_some_and(|ext| ext == "pack") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Copies the loose objects that `target` is missing from `source`
+fn copy_objects(source: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    for dir in fs::read_dir(source)? {
+        let dir = dir?;
+        let name = dir.file_name();
+        if name.len() == 2 && dir.file_type()?.is_dir() {
+            copy_missing(&dir.path(), &target.join(&name))?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_missing(source: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(target)?;
+    for file in fs::read_dir(source)? {
+        let file = file?;
+        let target_file = target.join(file.file_name());
+        match target_file.symlink_metadata() {
+            Ok(_) => {}
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                fs::copy(file.path(), target_file)?;
+            }
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(())
+}
+
+// Replaces the files tracked in a repository's index with the tree of `oid`, detaching HEAD at it.
+// Nothing is touched if the index is locked or if tracked files have changes of their own.
+fn checkout(repo_path: &Path, oid: &str) -> Result<(), Box<dyn Error>> {
+    let git_path = repo_path.join(".git");
+    let database = Database::new(git_path.join("objects"));
+    let workspace = Workspace::new(repo_path.to_path_buf());
+    let target = database.load_tree_list(oid, Path::new(""))?;
+
+    let mut index = Index::new(git_path.join("index"));
+    if !index.load_for_update()? {
+        return Err(IndexLocked(git_path.join("index")).into());
+    }
+    if let Err(error) = replace_files(repo_path, &database, &workspace, &mut index, target) {
+        index.release_lock();
+        return Err(error);
+    }
+    index.write_updates();
+
+    Refs::new(git_path).detach_head(oid)
+}
+
+fn replace_files(
+    repo_path: &Path,
+    database: &Database,
+    workspace: &Workspace,
+    index: &mut Index,
+    target: BTreeMap<PathBuf, DatabaseEntry>,
+) -> Result<(), Box<dyn Error>> {
+    check_clean(repo_path, database, workspace, index, &target)?;
+
+    let stale: Vec<_> = index
+        .entries()
+        .map(|entry| PathBuf::from(entry.path()))
+        .filter(|path| !target.contains_key(path))
+        .collect();
+    for path in stale {
+        workspace.remove_file(&path)?;
+    }
+
+    index.clear_entries();
+    for (path, entry) in target {
+        let data = match database.load(entry.oid()) {
+            Ok(Object::Blob(blob)) => blob.data().to_vec(),
+            // Nested submodules are left for their own update
+            _ => Vec::new(),
+        };
+        workspace.write_file(&path, &data, entry.mode())?;
+        index.add(
+            path.clone(),
+            entry.oid().to_string(),
+            workspace.stat_file(&path),
+        );
+    }
+    Ok(())
+}
+
+// Tracked files that differ from the index, and untracked files in the way of the new tree, would
+// be lost by the checkout. Files that were deleted have nothing left to lose.
+fn check_clean(
+    repo_path: &Path,
+    database: &Database,
+    workspace: &Workspace,
+    index: &Index,
+    target: &BTreeMap<PathBuf, DatabaseEntry>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in index.entries() {
+        let path = Path::new(entry.path());
+        let stat = match repo_path.join(path).symlink_metadata() {
+            Ok(stat) => stat,
+            Err(error) if error.kind() == ErrorKind::NotFound => continue,
+            Err(error) => return Err(error.into()),
+        };
+        if index.is_stat_clean(entry, &stat) {
+            continue;
+        }
+        let blob = Blob::new(workspace.read_file(path));
+        if stat.is_dir() || database.hash_object(&blob) != entry.oid() {
+            return Err(LocalChanges(path.to_path_buf()).into());
+        }
+    }
+
+    for path in target.keys() {
+        let exists = repo_path.join(path).symlink_metadata().is_ok();
+        if exists && index.entry(path.as_os_str()).is_none() {
+            return Err(UntrackedInTheWay(path.clone()).into());
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+struct MissingUrl(String);
+impl Error for MissingUrl {}
+impl fmt::Display for MissingUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No url found for submodule '{}' in .gitmodules", self.0)
+    }
+}
+
+#[derive(Debug)]
+struct NotARepository(PathBuf);
+impl Error for NotARepository {}
+impl fmt::Display for NotARepository {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' is not a repository", self.0.display())
+    }
+}
+
+#[derive(Debug)]
+struct PackedObjects(PathBuf);
+impl Error for PackedObjects {}
+impl fmt::Display for PackedObjects {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "'{}' has packed objects, which can't be read; only repositories of loose objects can be cloned",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct IndexLocked(PathBuf);
+impl Error for IndexLocked {}
+impl fmt::Display for IndexLocked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unable to create '{}.lock'", self.0.display())
+    }
+}
+
+#[derive(Debug)]
+struct LocalChanges(PathBuf);
+impl Error for LocalChanges {}
+impl fmt::Display for LocalChanges {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Your local changes to '{}' would be overwritten by checkout",
+            self.0.display()
+        )
+    }
+}
+
+#[derive(Debug)]
+struct UntrackedInTheWay(PathBuf);
+impl Error for UntrackedInTheWay {}
+impl fmt::Display for UntrackedInTheWay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The untracked working tree file '{}' would be overwritten by checkout",
+            self.0.display()
+        )
+    }
+}
@@ -1,14 +1,24 @@
 use std::{
-    ffi::OsString,
+    ffi::{OsStr, OsString},
     fs::{self, Metadata},
+    io::{self, ErrorKind},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{symlink, PermissionsExt},
+    },
     path::{Path, PathBuf},
 };
 
+use super::index::entry::Entry;
+
 pub struct Workspace {
     pathname: PathBuf,
 }
 
 impl Workspace {
+    // TODO: Make ignore paths better
+    pub const IGNORED_NAMES: [&'static str; 2] = [".git", "target"];
+
     pub fn new(pathname: PathBuf) -> Workspace {
         Workspace { pathname }
     }
@@ -18,12 +28,18 @@ impl Workspace {
     }
 
     pub fn list_files(root_path: &Path, pathname: &Path) -> Vec<PathBuf> {
-        // TODO: Make ignore paths better
-        let ignore_paths: [OsString; 2] = [OsString::from(".git"), OsString::from("target")];
+        let ignore_paths = Workspace::IGNORED_NAMES.map(OsString::from);
+
+        // Symlinks are recorded as links, so never follow them into directories
+        let is_dir = match pathname.symlink_metadata() {
+            Ok(stat) => stat.is_dir(),
+            Err(_) => false,
+        };
+        // A nested repository is recorded as a single submodule entry rather than recursed into
+        let is_submodule = is_dir && pathname != root_path && Workspace::is_repository(pathname);
 
-        // TODO: Maybe simplify checking directory here?
-        if pathname.is_dir() {
-            let dir_entries = fs::read_dir(&pathname).unwrap();
+        if is_dir && !is_submodule {
+            let dir_entries = fs::read_dir(pathname).unwrap();
 
             dir_entries
                 .flat_map(|entry| {
@@ -31,36 +47,154 @@ impl Workspace {
                     let path = entry.path();
                     if ignore_paths.contains(&entry.file_name()) {
                         Vec::new()
-                    } else if path.is_dir() {
-                        Workspace::list_files(&root_path, &path)
+                    } else if entry.file_type().is_ok_and(|t| t.is_dir())
+                        && !Workspace::is_repository(&path)
+                    {
+                        Workspace::list_files(root_path, &path)
                     } else {
                         let relative_path = entry.path();
-                        vec![PathBuf::from(
-                            relative_path
-                                .strip_prefix(&root_path)
-                                .expect("Failed to get file path relative to directory root"),
-                        )]
+                        vec![PathBuf::from(relative_path.strip_prefix(root_path).expect(
+                            "Failed to get file path relative to directory root",
+                        ))]
                     }
                 })
                 .collect()
         } else if ignore_paths.iter().any(|e| e == pathname.as_os_str()) {
             Vec::new()
         } else {
-            vec![PathBuf::from(pathname.strip_prefix(&root_path).expect(
+            vec![PathBuf::from(pathname.strip_prefix(root_path).expect(
                 "Failed to get file path relative to directory root",
             ))]
         }
     }
 
-    pub fn read_file(&self, path: &Path) -> String {
-        fs::read_to_string(path).expect("Unable to read file")
+    pub fn is_repository(pathname: &Path) -> bool {
+        pathname.join(".git").symlink_metadata().is_ok()
+    }
+
+    // The content of a symlink is the path it points to, as with git
+    pub fn read_file(&self, path: &Path) -> Vec<u8> {
+        let is_symlink = self.stat_file(path).file_type().is_symlink();
+        let path = self.pathname.join(path);
+        if is_symlink {
+            fs::read_link(path)
+                .expect("Unable to read symlink")
+                .into_os_string()
+                .into_vec()
+        } else {
+            fs::read(path).expect("Unable to read file")
+        }
     }
 
     // TODO: Return "Result"s everywhere?
     pub fn stat_file(&self, path: &Path) -> Metadata {
         self.pathname
             .join(path)
-            .metadata()
+            .symlink_metadata()
             .expect("Failed to get metadata for path")
     }
+
+    pub fn write_file(&self, path: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+        let path = self.pathname.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if path.symlink_metadata().is_ok() {
+            self.remove_path(&path)?;
+        }
+
+        match mode {
+            Entry::SYMLINK_MODE => symlink(OsStr::from_bytes(data), &path),
+            Entry::GITLINK_MODE => fs::create_dir_all(&path),
+            _ => {
+                fs::write(&path, data)?;
+                let permissions = match mode {
+                    Entry::EXECUTABLE_MODE => 0o755,
+                    _ => 0o644,
+                };
+                fs::set_permissions(&path, fs::Permissions::from_mode(permissions))
+            }
+        }
+    }
+
+    // Removes a file and any parent directories left empty by its removal
+    pub fn remove_file(&self, path: &Path) -> io::Result<()> {
+        let path = self.pathname.join(path);
+        match path.symlink_metadata() {
+            Ok(_) => self.remove_path(&path)?,
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error),
+        }
+
+        for parent in path.ancestors().skip(1) {
+            if parent == self.pathname || fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_path(&self, path: &Path) -> io::Result<()> {
+        if path.symlink_metadata()?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{database::tree::OidAndMode, temp_dir::TempDir};
+    use super::*;
+
+    fn workspace(name: &str) -> (TempDir, Workspace) {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        let workspace = Workspace::new(dir.to_path_buf());
+        (dir, workspace)
+    }
+
+    #[test]
+    fn it_stages_symlinks_as_the_paths_they_point_to() {
+        let (_dir, workspace) = workspace("symlinks");
+        let root = &workspace.pathname;
+        fs::write(root.join("lib/file.txt"), "content").unwrap();
+        symlink("lib/file.txt", root.join("file-link")).unwrap();
+        symlink("lib", root.join("dir-link")).unwrap();
+        symlink("missing.txt", root.join("dangling")).unwrap();
+
+        let mut files = workspace.list_workspace_files(root);
+        files.sort();
+
+        assert_eq!(
+            vec!["dangling", "dir-link", "file-link", "lib/file.txt"],
+            files
+                .iter()
+                .map(|p| p.to_str().unwrap())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(b"lib".to_vec(), workspace.read_file(Path::new("dir-link")));
+        assert_eq!(
+            b"missing.txt".to_vec(),
+            workspace.read_file(Path::new("dangling"))
+        );
+
+        let stat = workspace.stat_file(Path::new("file-link"));
+        let entry = Entry::new(PathBuf::from("file-link"), &"1".repeat(40), stat);
+        assert_eq!(Entry::SYMLINK_MODE, entry.mode());
+    }
+
+    #[test]
+    fn it_writes_symlinks_back_as_links() {
+        let (_dir, workspace) = workspace("write");
+
+        workspace
+            .write_file(Path::new("lib/link"), b"../target", Entry::SYMLINK_MODE)
+            .unwrap();
+
+        let path = workspace.pathname.join("lib/link");
+        assert!(path.symlink_metadata().unwrap().file_type().is_symlink());
+        assert_eq!(PathBuf::from("../target"), fs::read_link(path).unwrap());
+    }
 }